@@ -0,0 +1,104 @@
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+
+use crate::mirror_config::MirrorConfig;
+
+/// Decides which clients are permitted to use Flexo, based on the client's IP address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessControl {
+    /// If empty, all clients are permitted, unless they are denied.
+    allowed_clients: Vec<IpNet>,
+    denied_clients: Vec<IpNet>,
+    /// If None, admin endpoints are available to all permitted clients.
+    admin_allowed_clients: Option<Vec<IpNet>>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct InvalidClientNetwork(pub String);
+
+impl AccessControl {
+    pub fn from_config(properties: &MirrorConfig) -> Result<Self, InvalidClientNetwork> {
+        let allowed_clients = parse_networks(properties.allowed_clients.as_deref().unwrap_or_default())?;
+        let denied_clients = parse_networks(properties.denied_clients.as_deref().unwrap_or_default())?;
+        let admin_allowed_clients = match &properties.admin_allowed_clients {
+            None => None,
+            Some(networks) => Some(parse_networks(networks)?),
+        };
+        Ok(AccessControl {
+            allowed_clients,
+            denied_clients,
+            admin_allowed_clients,
+        })
+    }
+
+    pub fn is_client_permitted(&self, ip_addr: IpAddr) -> bool {
+        let ip_addr = canonical(ip_addr);
+        let is_denied = self.denied_clients.iter().any(|network| network.contains(&ip_addr));
+        let is_allowed = self.allowed_clients.is_empty() ||
+            self.allowed_clients.iter().any(|network| network.contains(&ip_addr));
+        is_allowed && !is_denied
+    }
+
+    pub fn is_admin_permitted(&self, ip_addr: IpAddr) -> bool {
+        let is_admin = match &self.admin_allowed_clients {
+            None => true,
+            Some(networks) => networks.iter().any(|network| network.contains(&canonical(ip_addr))),
+        };
+        is_admin && self.is_client_permitted(ip_addr)
+    }
+}
+
+/// Parses a list of networks in CIDR notation (e.g. "192.168.1.0/24"). Single IP addresses without a prefix length
+/// are also accepted.
+fn parse_networks(networks: &[String]) -> Result<Vec<IpNet>, InvalidClientNetwork> {
+    networks.iter().map(|network| parse_network(network)).collect()
+}
+
+pub fn parse_network(network: &str) -> Result<IpNet, InvalidClientNetwork> {
+    match network.parse::<IpNet>() {
+        Ok(n) => Ok(n),
+        Err(_) => network.parse::<IpAddr>()
+            .map(IpNet::from)
+            .map_err(|_| InvalidClientNetwork(network.to_owned())),
+    }
+}
+
+/// When listening on an IPv6 address, IPv4 clients appear with IPv4-mapped IPv6 addresses (e.g.
+/// ::ffff:192.168.1.10). We convert them back so that they can be matched against IPv4 networks.
+pub fn canonical(ip_addr: IpAddr) -> IpAddr {
+    match ip_addr {
+        IpAddr::V4(_) => ip_addr,
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            None => ip_addr,
+            Some(v4) => IpAddr::V4(v4),
+        },
+    }
+}
+
+#[test]
+fn test_access_control() {
+    let networks = |n: &[&str]| parse_networks(&n.iter().map(|s| s.to_string()).collect::<Vec<_>>()).unwrap();
+    let access_control = AccessControl {
+        allowed_clients: networks(&["192.168.1.0/24", "10.0.0.0/8", "fd00::/8"]),
+        denied_clients: networks(&["192.168.1.13"]),
+        admin_allowed_clients: Some(networks(&["192.168.1.2/32"])),
+    };
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+    assert!(access_control.is_client_permitted(ip("192.168.1.20")));
+    assert!(access_control.is_client_permitted(ip("::ffff:10.1.2.3")));
+    assert!(access_control.is_client_permitted(ip("fd12::1")));
+    assert!(!access_control.is_client_permitted(ip("192.168.1.13")));
+    assert!(!access_control.is_client_permitted(ip("192.168.2.1")));
+    assert!(access_control.is_admin_permitted(ip("192.168.1.2")));
+    assert!(!access_control.is_admin_permitted(ip("192.168.1.20")));
+
+    let access_control = AccessControl {
+        allowed_clients: vec![],
+        denied_clients: networks(&["0.0.0.0/0"]),
+        admin_allowed_clients: None,
+    };
+    assert!(access_control.is_client_permitted(ip("::1")));
+    assert!(!access_control.is_client_permitted(ip("127.0.0.1")));
+    assert_eq!(Err(InvalidClientNetwork("192.168.1.0/33".to_owned())), parse_networks(&["192.168.1.0/33".to_owned()]));
+}
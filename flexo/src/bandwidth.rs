@@ -0,0 +1,248 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::NaiveTime;
+use curl::easy::Handler;
+
+use crate::mirror_config::{parse_bandwidth, MirrorConfig, TimeWindow};
+
+/// The largest amount of data sent at once by a throttled connection. Smaller chunks result in a smoother
+/// transfer rate, larger chunks reduce the number of system calls.
+const MAX_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// How long a paused transfer waits at most before it checks whether it can be resumed.
+pub const MAX_PAUSE_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A token bucket that limits the bandwidth to a given number of bytes per second. Tokens may be taken even if
+/// not enough of them are available: The bucket then goes into debt, and the caller has to wait until the debt has
+/// been repaid. This ensures that a single large chunk cannot exceed the rate.
+#[derive(Debug)]
+pub struct TokenBucket {
+    bytes_per_second: u64,
+    /// The maximum burst size: Tokens accumulate up to this amount while the bucket is not in use.
+    capacity: u64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(bytes_per_second: u64, now: Instant) -> Self {
+        let bytes_per_second = std::cmp::max(bytes_per_second, 1);
+        TokenBucket {
+            bytes_per_second,
+            capacity: bytes_per_second,
+            tokens: bytes_per_second as f64,
+            last_refill: now,
+        }
+    }
+
+    /// Changes the rate. Tokens that have accumulated at the previous rate are retained up to the new capacity.
+    pub fn set_rate(&mut self, bytes_per_second: u64) {
+        let bytes_per_second = std::cmp::max(bytes_per_second, 1);
+        if bytes_per_second != self.bytes_per_second {
+            self.bytes_per_second = bytes_per_second;
+            self.capacity = bytes_per_second;
+            self.tokens = f64::min(self.tokens, self.capacity as f64);
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill).as_secs_f64();
+        self.tokens = f64::min(self.tokens + elapsed * self.bytes_per_second as f64, self.capacity as f64);
+        self.last_refill = now;
+    }
+
+    fn debt(&self) -> Duration {
+        if self.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.bytes_per_second as f64)
+        }
+    }
+
+    /// Takes the given number of tokens and returns how long the caller needs to wait before sending the
+    /// corresponding number of bytes.
+    pub fn take(&mut self, num_bytes: u64, now: Instant) -> Duration {
+        self.refill(now);
+        self.tokens -= num_bytes as f64;
+        self.debt()
+    }
+
+    /// Takes the given number of tokens unless the bucket is in debt. Otherwise, nothing is taken and the time
+    /// until the debt has been repaid is returned.
+    pub fn try_take(&mut self, num_bytes: u64, now: Instant) -> Option<Duration> {
+        self.refill(now);
+        if self.tokens < 0.0 {
+            return Some(self.debt());
+        }
+        self.tokens -= num_bytes as f64;
+        None
+    }
+}
+
+/// A token bucket that can be shared by multiple connections, so that they do not exceed their bandwidth in sum.
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    token_bucket: Arc<Mutex<TokenBucket>>,
+    chunk_size: u64,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_second: u64) -> Self {
+        // Send about ten chunks per second, so that slow connections are not bursty.
+        let chunk_size = (bytes_per_second / 10).clamp(1, MAX_CHUNK_SIZE);
+        Throttle {
+            token_bucket: Arc::new(Mutex::new(TokenBucket::new(bytes_per_second, Instant::now()))),
+            chunk_size,
+        }
+    }
+
+    /// The number of bytes that should be sent at once.
+    pub fn chunk_size(&self) -> u64 {
+        self.chunk_size
+    }
+
+    /// Blocks until the given number of bytes may be sent.
+    pub fn consume(&self, num_bytes: u64) {
+        let wait_time = self.token_bucket.lock().unwrap().take(num_bytes, Instant::now());
+        if wait_time > Duration::from_secs(0) {
+            std::thread::sleep(wait_time);
+        }
+    }
+}
+
+/// The maximum bandwidth used by all downloads from remote mirrors in sum. The limit may depend on the time of day.
+/// Clones share the same budget.
+#[derive(Debug, Clone, Default)]
+pub struct UpstreamBandwidth {
+    /// None if downloads are never limited.
+    limiter: Option<Arc<UpstreamLimiter>>,
+}
+
+#[derive(Debug)]
+struct UpstreamLimiter {
+    schedule: BandwidthSchedule,
+    token_bucket: Mutex<TokenBucket>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BandwidthSchedule {
+    /// Applies outside of all time windows. None means unlimited.
+    default_bytes_per_second: Option<u64>,
+    /// The first entry whose time window contains the current time applies. None means unlimited.
+    entries: Vec<(TimeWindow, Option<u64>)>,
+}
+
+impl BandwidthSchedule {
+    fn bytes_per_second(&self, time: NaiveTime) -> Option<u64> {
+        match self.entries.iter().find(|(time_window, _)| time_window.contains(time)) {
+            None => self.default_bytes_per_second,
+            Some((_, bytes_per_second)) => *bytes_per_second,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvalidUpstreamBandwidth {
+    InvalidBandwidth(String),
+    InvalidTimeWindow(String),
+}
+
+impl UpstreamBandwidth {
+    pub fn from_config(properties: &MirrorConfig) -> Result<Self, InvalidUpstreamBandwidth> {
+        let config = match &properties.upstream_bandwidth {
+            None => return Ok(UpstreamBandwidth::default()),
+            Some(c) => c,
+        };
+        let parse = |bandwidth: &Option<String>| match bandwidth {
+            None => Ok(None),
+            Some(b) => match parse_bandwidth(b) {
+                None => Err(InvalidUpstreamBandwidth::InvalidBandwidth(b.clone())),
+                Some(bytes_per_second) => Ok(Some(bytes_per_second as u64)),
+            },
+        };
+        let default_bytes_per_second = parse(&config.max_bandwidth)?;
+        let entries = config.schedule.as_deref().unwrap_or_default().iter().map(|entry| {
+            let time_window = TimeWindow::parse(&entry.time_window)
+                .ok_or_else(|| InvalidUpstreamBandwidth::InvalidTimeWindow(entry.time_window.clone()))?;
+            Ok((time_window, parse(&entry.max_bandwidth)?))
+        }).collect::<Result<Vec<_>, _>>()?;
+        let schedule = BandwidthSchedule { default_bytes_per_second, entries };
+        let is_ever_limited = schedule.default_bytes_per_second.is_some() ||
+            schedule.entries.iter().any(|(_, bytes_per_second)| bytes_per_second.is_some());
+        if !is_ever_limited {
+            return Ok(UpstreamBandwidth::default());
+        }
+        let limiter = UpstreamLimiter {
+            schedule,
+            token_bucket: Mutex::new(TokenBucket::new(1, Instant::now())),
+        };
+        Ok(UpstreamBandwidth {
+            limiter: Some(Arc::new(limiter)),
+        })
+    }
+
+    pub fn is_limited(&self) -> bool {
+        self.limiter.is_some()
+    }
+
+    /// Takes the given number of bytes, which have just been received from a remote mirror, from the budget.
+    /// If the budget is used up, nothing is taken and the point in time is returned at which the transfer may
+    /// continue: The write callback then pauses the transfer instead of accepting the bytes, so that curl delivers
+    /// them again once the transfer is resumed, see [`UpstreamTransfer`].
+    pub fn try_consume(&self, num_bytes: u64) -> Option<Instant> {
+        let limiter = self.limiter.as_ref()?;
+        let bytes_per_second = limiter.schedule.bytes_per_second(chrono::Local::now().time())?;
+        let now = Instant::now();
+        let mut token_bucket = limiter.token_bucket.lock().unwrap();
+        token_bucket.set_rate(bytes_per_second);
+        token_bucket.try_take(num_bytes, now).map(|wait_time| now + wait_time)
+    }
+}
+
+/// A curl handler whose write callback pauses the transfer while the upstream bandwidth budget is used up.
+pub trait UpstreamTransfer: Handler {
+    /// Set by the write callback when it pauses the transfer: The transfer is resumed at this point in time.
+    fn resume_at(&mut self) -> &mut Option<Instant>;
+}
+
+#[test]
+fn test_bandwidth_schedule() {
+    let time = |h| NaiveTime::from_hms_opt(h, 0, 0).unwrap();
+    let schedule = BandwidthSchedule {
+        default_bytes_per_second: Some(1000),
+        entries: vec![
+            (TimeWindow::parse("23:00-06:00").unwrap(), None),
+            (TimeWindow::parse("12:00-13:00").unwrap(), Some(2000)),
+        ],
+    };
+    assert_eq!(Some(1000), schedule.bytes_per_second(time(9)));
+    assert_eq!(None, schedule.bytes_per_second(time(2)));
+    assert_eq!(Some(2000), schedule.bytes_per_second(time(12)));
+}
+
+#[test]
+fn test_token_bucket() {
+    let start = Instant::now();
+    let mut token_bucket = TokenBucket::new(1000, start);
+    assert_eq!(Duration::from_secs(0), token_bucket.take(1000, start));
+    assert_eq!(Duration::from_millis(500), token_bucket.take(500, start));
+    // After one second, the debt has been repaid and another 500 bytes have accumulated.
+    let later = start + Duration::from_secs(1);
+    assert_eq!(Duration::from_secs(0), token_bucket.take(500, later));
+    // Tokens do not accumulate beyond the capacity.
+    let much_later = later + Duration::from_secs(60);
+    assert_eq!(Duration::from_secs(0), token_bucket.take(1000, much_later));
+    assert_eq!(Duration::from_secs(1), token_bucket.take(1000, much_later));
+}
+
+#[test]
+fn test_token_bucket_try_take() {
+    let start = Instant::now();
+    let mut token_bucket = TokenBucket::new(1000, start);
+    // A single chunk may exceed the available tokens, but nothing is taken while the bucket is in debt.
+    assert_eq!(None, token_bucket.try_take(1500, start));
+    assert_eq!(Some(Duration::from_millis(500)), token_bucket.try_take(100, start));
+    assert_eq!(Some(Duration::from_millis(250)), token_bucket.try_take(100, start + Duration::from_millis(250)));
+    assert_eq!(None, token_bucket.try_take(100, start + Duration::from_millis(500)));
+}
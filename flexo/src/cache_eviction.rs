@@ -0,0 +1,120 @@
+use std::fs;
+use std::io;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use glob::{MatchOptions, Pattern};
+use walkdir::WalkDir;
+
+use crate::cache_stats::is_cache_path;
+
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: true,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EvictionOutcome {
+    /// The files that have been removed, including the cfs files and signatures.
+    Evicted(Vec<String>),
+    /// Nothing has been removed, since some of the files are still being downloaded.
+    InProgress(Vec<String>),
+}
+
+/// Returns the cached files that match the pattern, e.g. "core/os/x86_64/glibc-*". Hidden files are not included.
+pub fn matching_files(cache_directory: &Path, pattern: &Pattern) -> io::Result<Vec<String>> {
+    let mut files = Vec::new();
+    for entry in WalkDir::new(cache_directory) {
+        let entry = entry?;
+        let path = entry.path().strip_prefix(cache_directory).unwrap_or_else(|_| entry.path());
+        if entry.file_type().is_file() && pattern.matches_path_with(path, MATCH_OPTIONS) {
+            files.push(path.to_string_lossy().into_owned());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The files that are removed together with the given file: its cfs file, and its signature with the signature's
+/// cfs file.
+fn companions(path: &str) -> Vec<String> {
+    let with_cfs = |path: &str| {
+        let cfs_path = match path.rsplit_once('/') {
+            None => format!(".{}.cfs", path),
+            Some((directory, file_name)) => format!("{}/.{}.cfs", directory, file_name),
+        };
+        vec![path.to_owned(), cfs_path]
+    };
+    let mut files = with_cfs(path);
+    if !path.ends_with(".sig") {
+        files.append(&mut with_cfs(&format!("{}.sig", path)));
+    }
+    files
+}
+
+/// Removes the files together with their companions, unless any of those files is in progress. The paths are
+/// relative to the cache directory, paths that could refer to files outside of it are rejected. This function
+/// must be called while no new downloads can be scheduled.
+pub fn evict_unless_in_progress<F>(
+    cache_directory: &Path,
+    paths: &[String],
+    is_in_progress: F,
+) -> io::Result<EvictionOutcome> where F: Fn(&str) -> bool {
+    if let Some(path) = paths.iter().find(|path| !is_cache_path(path)) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("{:?} is not within the cache directory", path)));
+    }
+    let files = paths.iter().flat_map(|path| companions(path)).collect::<Vec<_>>();
+    let in_progress = files.iter().filter(|path| is_in_progress(path)).cloned().collect::<Vec<_>>();
+    if !in_progress.is_empty() {
+        return Ok(EvictionOutcome::InProgress(in_progress));
+    }
+    let mut evicted = Vec::new();
+    for path in files {
+        match fs::remove_file(cache_directory.join(&path)) {
+            Ok(()) => evicted.push(path),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(EvictionOutcome::Evicted(evicted))
+}
+
+#[test]
+fn test_evict_unless_in_progress() {
+    let cache_directory = tempfile::tempdir().unwrap();
+    let repo_directory = cache_directory.path().join("core/os/x86_64");
+    std::fs::create_dir_all(&repo_directory).unwrap();
+    for file_name in ["glibc-2.33-3-x86_64.pkg.tar.zst", ".glibc-2.33-3-x86_64.pkg.tar.zst.cfs",
+        "glibc-2.33-3-x86_64.pkg.tar.zst.sig", "zlib-1.2.11-4-x86_64.pkg.tar.zst"] {
+        std::fs::write(repo_directory.join(file_name), "").unwrap();
+    }
+    let pattern = Pattern::new("core/os/x86_64/glibc-*.zst").unwrap();
+    let paths = matching_files(cache_directory.path(), &pattern).unwrap();
+    assert_eq!(vec!["core/os/x86_64/glibc-2.33-3-x86_64.pkg.tar.zst"], paths);
+    let in_progress = |path: &str| path == "core/os/x86_64/glibc-2.33-3-x86_64.pkg.tar.zst.sig";
+    let expected = EvictionOutcome::InProgress(vec!["core/os/x86_64/glibc-2.33-3-x86_64.pkg.tar.zst.sig".to_owned()]);
+    assert_eq!(expected, evict_unless_in_progress(cache_directory.path(), &paths, in_progress).unwrap());
+    assert!(repo_directory.join("glibc-2.33-3-x86_64.pkg.tar.zst").exists());
+    let expected = EvictionOutcome::Evicted(vec![
+        "core/os/x86_64/glibc-2.33-3-x86_64.pkg.tar.zst".to_owned(),
+        "core/os/x86_64/.glibc-2.33-3-x86_64.pkg.tar.zst.cfs".to_owned(),
+        "core/os/x86_64/glibc-2.33-3-x86_64.pkg.tar.zst.sig".to_owned(),
+    ]);
+    assert_eq!(expected, evict_unless_in_progress(cache_directory.path(), &paths, |_| false).unwrap());
+    assert!(repo_directory.join("zlib-1.2.11-4-x86_64.pkg.tar.zst").exists());
+}
+
+#[test]
+fn test_evict_outside_of_cache_directory() {
+    let directory = tempfile::tempdir().unwrap();
+    let cache_directory = directory.path().join("cache");
+    std::fs::create_dir_all(&cache_directory).unwrap();
+    let secret = directory.path().join("secret");
+    std::fs::write(&secret, "").unwrap();
+    for path in [secret.to_str().unwrap(), "../secret", ""].iter() {
+        let result = evict_unless_in_progress(&cache_directory, &[path.to_string()], |_| false);
+        assert_eq!(ErrorKind::InvalidInput, result.unwrap_err().kind());
+    }
+    assert!(secret.exists());
+}
@@ -0,0 +1,207 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::io;
+use std::io::ErrorKind;
+use std::path::{Component, Path};
+
+use serde::Serialize;
+use walkdir::WalkDir;
+
+use crate::mirror_flexo::get_complete_size_from_cfs_file;
+use crate::package_version::PackageFile;
+
+const NUM_LARGEST_FILES: usize = 10;
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct DirectoryStats {
+    pub num_files: u64,
+    /// Files such as glibc-2.33-3-x86_64.pkg.tar.zst, without signatures and database files.
+    pub num_packages: u64,
+    pub size: u64,
+}
+
+/// A file in the cache directory.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct CachedFile {
+    /// Relative to the cache directory, i.e., the same path that is requested by clients.
+    pub path: String,
+    pub cached_size: u64,
+    /// Read from the cfs file, None if the cfs file does not exist.
+    pub complete_size: Option<u64>,
+}
+
+impl CachedFile {
+    fn new(cache_directory: &Path, path: &Path, cached_size: u64) -> Self {
+        CachedFile {
+            path: relative_path(cache_directory, path),
+            cached_size,
+            complete_size: get_complete_size_from_cfs_file(path),
+        }
+    }
+
+    /// True if the file is still being downloaded, or if its download was aborted.
+    pub fn is_incomplete(&self) -> bool {
+        self.complete_size.map(|complete_size| self.cached_size < complete_size).unwrap_or(false)
+    }
+}
+
+/// The files in the cache directory. Hidden files, e.g. the cfs files, are not included.
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub num_files: u64,
+    pub num_packages: u64,
+    pub size: u64,
+    /// Keyed by the directory relative to the cache directory, i.e., by repository and architecture, e.g.
+    /// "core/os/x86_64".
+    pub directories: BTreeMap<String, DirectoryStats>,
+    pub largest_files: Vec<CachedFile>,
+    pub incomplete_files: Vec<CachedFile>,
+    /// Cfs files whose file no longer exists, e.g. because it was removed by hand.
+    pub orphaned_cfs_files: Vec<String>,
+}
+
+fn relative_path(cache_directory: &Path, path: &Path) -> String {
+    path.strip_prefix(cache_directory).unwrap_or(path).to_string_lossy().into_owned()
+}
+
+fn is_package(file_name: &str) -> bool {
+    PackageFile::from_filename(file_name).is_some()
+}
+
+/// Walks the cache directory without modifying it, so that this can also be used while Flexo is running.
+pub fn cache_stats(cache_directory: &Path) -> io::Result<CacheStats> {
+    let mut stats = CacheStats::default();
+    let mut files = Vec::new();
+    for entry in WalkDir::new(cache_directory) {
+        let entry = entry?;
+        let file_name = match entry.file_name().to_str() {
+            Some(file_name) if entry.file_type().is_file() => file_name,
+            _ => continue,
+        };
+        if let Some(cached_file_name) = file_name.strip_prefix('.').and_then(|f| f.strip_suffix(".cfs")) {
+            if !entry.path().with_file_name(cached_file_name).exists() {
+                stats.orphaned_cfs_files.push(relative_path(cache_directory, entry.path()));
+            }
+            continue;
+        } else if file_name.starts_with('.') {
+            continue;
+        }
+        let cached_file = CachedFile::new(cache_directory, entry.path(), entry.metadata()?.len());
+        let directory = entry.path().parent()
+            .map(|parent| relative_path(cache_directory, parent))
+            .unwrap_or_default();
+        let num_packages = if is_package(file_name) { 1 } else { 0 };
+        let directory_stats = stats.directories.entry(directory).or_default();
+        directory_stats.num_files += 1;
+        directory_stats.num_packages += num_packages;
+        directory_stats.size += cached_file.cached_size;
+        stats.num_files += 1;
+        stats.num_packages += num_packages;
+        stats.size += cached_file.cached_size;
+        files.push(cached_file);
+    }
+    stats.incomplete_files = files.iter().filter(|f| f.is_incomplete()).cloned().collect();
+    files.sort_by_key(|f| Reverse(f.cached_size));
+    files.truncate(NUM_LARGEST_FILES);
+    stats.largest_files = files;
+    stats.orphaned_cfs_files.sort();
+    Ok(stats)
+}
+
+/// True if the path, as given by a client, refers to a file within the cache directory: The path must be relative
+/// and must not contain components such as "..".
+pub fn is_cache_path(path: &str) -> bool {
+    !path.is_empty() && Path::new(path).components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Returns the given file, or None if it is not cached. The path is relative to the cache directory.
+pub fn cached_file(cache_directory: &Path, path: &str) -> io::Result<Option<CachedFile>> {
+    if !is_cache_path(path) {
+        return Ok(None);
+    }
+    let path = cache_directory.join(path);
+    let is_hidden = path.file_name().and_then(|f| f.to_str()).map(|f| f.starts_with('.')).unwrap_or(true);
+    if is_hidden {
+        return Ok(None);
+    }
+    let canonical_path = match path.canonicalize() {
+        Ok(p) => p,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    // Symbolic links must not lead out of the cache directory.
+    if !canonical_path.starts_with(cache_directory.canonicalize()?) {
+        return Ok(None);
+    }
+    match path.metadata() {
+        Ok(metadata) if metadata.is_file() => Ok(Some(CachedFile::new(cache_directory, &path, metadata.len()))),
+        Ok(_) => Ok(None),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns all cached versions of the given package, from all repositories and architectures.
+pub fn find_package(cache_directory: &Path, name: &str) -> io::Result<Vec<CachedFile>> {
+    let mut files = Vec::new();
+    for entry in WalkDir::new(cache_directory) {
+        let entry = entry?;
+        let is_match = entry.file_name().to_str()
+            .and_then(PackageFile::from_filename)
+            .map(|package_file| package_file.name == name)
+            .unwrap_or(false);
+        if is_match && entry.file_type().is_file() {
+            files.push(CachedFile::new(cache_directory, entry.path(), entry.metadata()?.len()));
+        }
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+#[test]
+fn test_cache_stats() {
+    let cache_directory = tempfile::tempdir().unwrap();
+    let repo_directory = cache_directory.path().join("core/os/x86_64");
+    std::fs::create_dir_all(&repo_directory).unwrap();
+    std::fs::write(repo_directory.join("complete-1-1-x86_64.pkg.tar.zst"), [0; 100]).unwrap();
+    std::fs::write(repo_directory.join(".complete-1-1-x86_64.pkg.tar.zst.cfs"), "100\n").unwrap();
+    std::fs::write(repo_directory.join("partial-1-1-x86_64.pkg.tar.zst"), [0; 50]).unwrap();
+    std::fs::write(repo_directory.join(".partial-1-1-x86_64.pkg.tar.zst.cfs"), "200\n").unwrap();
+    std::fs::write(repo_directory.join("core.db"), [0; 10]).unwrap();
+    std::fs::write(repo_directory.join(".removed-1-1-x86_64.pkg.tar.zst.cfs"), "300\n").unwrap();
+    let stats = cache_stats(cache_directory.path()).unwrap();
+    assert_eq!(3, stats.num_files);
+    assert_eq!(2, stats.num_packages);
+    assert_eq!(160, stats.size);
+    let expected = DirectoryStats { num_files: 3, num_packages: 2, size: 160 };
+    assert_eq!(Some(&expected), stats.directories.get("core/os/x86_64"));
+    assert_eq!("core/os/x86_64/complete-1-1-x86_64.pkg.tar.zst", stats.largest_files[0].path);
+    let incomplete_file = CachedFile {
+        path: "core/os/x86_64/partial-1-1-x86_64.pkg.tar.zst".to_owned(),
+        cached_size: 50,
+        complete_size: Some(200),
+    };
+    assert_eq!(vec![incomplete_file.clone()], stats.incomplete_files);
+    assert_eq!(vec!["core/os/x86_64/.removed-1-1-x86_64.pkg.tar.zst.cfs"], stats.orphaned_cfs_files);
+
+    assert_eq!(Some(incomplete_file.clone()),
+               cached_file(cache_directory.path(), "core/os/x86_64/partial-1-1-x86_64.pkg.tar.zst").unwrap());
+    assert_eq!(None, cached_file(cache_directory.path(), "core/os/x86_64/missing-1-1-x86_64.pkg.tar.zst").unwrap());
+    assert_eq!(vec![incomplete_file], find_package(cache_directory.path(), "partial").unwrap());
+}
+
+#[test]
+fn test_cached_file_outside_of_cache_directory() {
+    let directory = tempfile::tempdir().unwrap();
+    let cache_directory = directory.path().join("cache");
+    std::fs::create_dir_all(cache_directory.join("core/os/x86_64")).unwrap();
+    let secret = directory.path().join("secret");
+    std::fs::write(&secret, [0; 10]).unwrap();
+    std::os::unix::fs::symlink(&secret, cache_directory.join("core/os/x86_64/link")).unwrap();
+    let absolute_path = secret.to_str().unwrap().to_owned();
+    for path in ["", absolute_path.as_str(), "../secret", "core/../../secret", "./core", "core/os/x86_64/link"].iter() {
+        assert_eq!(None, cached_file(&cache_directory, path).unwrap(), "{:?}", path);
+    }
+    assert!(is_cache_path("core/os/x86_64/glibc-2.33-3-x86_64.pkg.tar.zst"));
+    assert!(!is_cache_path("/etc/shadow"));
+}
@@ -0,0 +1,118 @@
+use std::fmt;
+
+use crate::mirrorlist::MirrorlistFormat;
+
+pub const USAGE: &str = "\
+Usage: flexo [COMMAND] [--config PATH]
+
+Commands:
+  serve           Start the server (default)
+  rank-mirrors    Run the latency tests on all mirrors that match the [mirrors_auto] settings and print a
+                  ranked mirrorlist
+  cache stats     Print the number and size of the cached files
+  cache purge     Remove old package versions, as configured by num_versions_retain
+  check-config    Report all problems of the configuration
+  version         Print the version
+  help            Print this help
+
+Options:
+  --config PATH   Read the settings from this TOML file instead of /etc/flexo/flexo.toml,
+                  even if FLEXO_* environment variables are set
+  --format FORMAT The output format of rank-mirrors: pacman (default) or json";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Command {
+    Serve,
+    RankMirrors(MirrorlistFormat),
+    CacheStats,
+    CachePurge,
+    CheckConfig,
+    Version,
+    Help,
+}
+
+/// The parsed command line.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Cli {
+    pub command: Command,
+    pub config_file: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CliError {
+    UnknownCommand(String),
+    UnknownOption(String),
+    MissingValue(String),
+    InvalidValue(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::UnknownCommand(command) => write!(f, "Unknown command: {}", command),
+            CliError::UnknownOption(option) => write!(f, "Unknown option: {}", option),
+            CliError::MissingValue(option) => write!(f, "The option {} requires a value", option),
+            CliError::InvalidValue(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Parses the arguments, without the name of the binary. Without a command, the server is started, so that
+/// existing service files keep working.
+pub fn parse_args<I>(args: I) -> Result<Cli, CliError> where I: IntoIterator<Item=String> {
+    let mut words = Vec::new();
+    let mut config_file = None;
+    let mut format = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => match args.next() {
+                None => return Err(CliError::MissingValue(arg)),
+                Some(path) => config_file = Some(path),
+            },
+            "--format" => match args.next() {
+                None => return Err(CliError::MissingValue(arg)),
+                Some(value) => format = Some(value.parse::<MirrorlistFormat>().map_err(CliError::InvalidValue)?),
+            },
+            "-h" | "--help" => words.push("help".to_owned()),
+            "-V" | "--version" => words.push("version".to_owned()),
+            _ if arg.starts_with("--config=") => config_file = Some(arg["--config=".len()..].to_owned()),
+            _ if arg.starts_with('-') => return Err(CliError::UnknownOption(arg)),
+            _ => words.push(arg),
+        }
+    }
+    let words = words.iter().map(|w| w.as_str()).collect::<Vec<_>>();
+    let command = match words.as_slice() {
+        [] | ["serve"] => Command::Serve,
+        ["rank-mirrors"] => Command::RankMirrors(format.take().unwrap_or(MirrorlistFormat::Pacman)),
+        ["cache", "stats"] => Command::CacheStats,
+        ["cache", "purge"] => Command::CachePurge,
+        ["check-config"] => Command::CheckConfig,
+        ["version"] => Command::Version,
+        ["help"] | [_, "help"] => Command::Help,
+        _ => return Err(CliError::UnknownCommand(words.join(" "))),
+    };
+    if format.is_some() {
+        return Err(CliError::InvalidValue("The option --format is only supported by rank-mirrors".to_owned()));
+    }
+    Ok(Cli { command, config_file })
+}
+
+#[test]
+fn test_parse_args() {
+    let parse = |args: &[&str]| parse_args(args.iter().map(|a| a.to_string()));
+    assert_eq!(Ok(Cli { command: Command::Serve, config_file: None }), parse(&[]));
+    let expected = Cli { command: Command::Serve, config_file: Some("/tmp/flexo.toml".to_owned()) };
+    assert_eq!(Ok(expected), parse(&["serve", "--config", "/tmp/flexo.toml"]));
+    let expected = Cli { command: Command::CacheStats, config_file: Some("/tmp/flexo.toml".to_owned()) };
+    assert_eq!(Ok(expected), parse(&["--config=/tmp/flexo.toml", "cache", "stats"]));
+    assert_eq!(Command::Help, parse(&["cache", "--help"]).unwrap().command);
+    assert_eq!(Err(CliError::UnknownCommand("cache".to_owned())), parse(&["cache"]));
+    assert_eq!(Err(CliError::MissingValue("--config".to_owned())), parse(&["serve", "--config"]));
+    assert_eq!(Err(CliError::UnknownOption("--port".to_owned())), parse(&["--port", "7878"]));
+    assert_eq!(Command::RankMirrors(MirrorlistFormat::Pacman), parse(&["rank-mirrors"]).unwrap().command);
+    assert_eq!(Command::RankMirrors(MirrorlistFormat::Json),
+               parse(&["rank-mirrors", "--format", "json"]).unwrap().command);
+    assert!(parse(&["rank-mirrors", "--format", "xml"]).is_err());
+    assert!(parse(&["cache", "stats", "--format", "json"]).is_err());
+}
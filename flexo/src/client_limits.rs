@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use ipnet::IpNet;
+
+use crate::access_control::{canonical, parse_network};
+use crate::bandwidth::Throttle;
+use crate::mirror_config::{parse_bandwidth, MirrorConfig};
+
+/// Limits the bandwidth and the number of simultaneous connections of each client. Limits apply per IP address:
+/// If a limit is configured for 192.168.1.0/24, each client within this network has its own limit.
+#[derive(Debug, Clone)]
+pub struct ClientLimits {
+    rules: Vec<ClientLimitRule>,
+    clients: Arc<Mutex<HashMap<IpAddr, ClientState>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ClientLimitRule {
+    network: IpNet,
+    /// Bytes per second.
+    max_bandwidth: Option<u64>,
+    max_connections: Option<u32>,
+}
+
+#[derive(Debug)]
+struct ClientState {
+    num_connections: u32,
+    /// Shared by all connections of the client.
+    throttle: Option<Throttle>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvalidClientLimit {
+    InvalidNetwork(String),
+    InvalidBandwidth(String),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct TooManyConnections;
+
+/// Represents one connection of a client. The connection is no longer counted once this value is dropped.
+#[derive(Debug)]
+pub struct ClientSlot {
+    ip_addr: IpAddr,
+    clients: Arc<Mutex<HashMap<IpAddr, ClientState>>>,
+    pub throttle: Option<Throttle>,
+}
+
+impl Drop for ClientSlot {
+    fn drop(&mut self) {
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client_state) = clients.get_mut(&self.ip_addr) {
+            client_state.num_connections -= 1;
+            if client_state.num_connections == 0 {
+                clients.remove(&self.ip_addr);
+            }
+        }
+    }
+}
+
+impl ClientLimits {
+    pub fn from_config(properties: &MirrorConfig) -> Result<Self, InvalidClientLimit> {
+        let rules = properties.client_limits.as_deref().unwrap_or_default().iter().map(|client_limit| {
+            let network = parse_network(&client_limit.network)
+                .map_err(|_| InvalidClientLimit::InvalidNetwork(client_limit.network.clone()))?;
+            let max_bandwidth = match &client_limit.max_bandwidth {
+                None => None,
+                Some(bandwidth) => match parse_bandwidth(bandwidth) {
+                    None => return Err(InvalidClientLimit::InvalidBandwidth(bandwidth.clone())),
+                    Some(b) => Some(b as u64),
+                },
+            };
+            Ok(ClientLimitRule {
+                network,
+                max_bandwidth,
+                max_connections: client_limit.max_connections,
+            })
+        }).collect::<Result<Vec<_>, _>>()?;
+        Ok(ClientLimits {
+            rules,
+            clients: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// Registers a new connection of the given client. Returns an error if the client already has the maximum
+    /// number of connections.
+    pub fn acquire(&self, ip_addr: IpAddr) -> Result<ClientSlot, TooManyConnections> {
+        let ip_addr = canonical(ip_addr);
+        // If multiple rules match, the first one applies.
+        let rule = self.rules.iter().find(|rule| rule.network.contains(&ip_addr));
+        let mut clients = self.clients.lock().unwrap();
+        let client_state = clients.entry(ip_addr).or_insert_with(|| ClientState {
+            num_connections: 0,
+            throttle: rule.and_then(|r| r.max_bandwidth).map(Throttle::new),
+        });
+        match rule.and_then(|r| r.max_connections) {
+            Some(max_connections) if client_state.num_connections >= max_connections => {
+                if client_state.num_connections == 0 {
+                    clients.remove(&ip_addr);
+                }
+                Err(TooManyConnections)
+            }
+            _ => {
+                client_state.num_connections += 1;
+                Ok(ClientSlot {
+                    ip_addr,
+                    clients: self.clients.clone(),
+                    throttle: client_state.throttle.clone(),
+                })
+            }
+        }
+    }
+}
+
+#[test]
+fn test_client_limits() {
+    let client_limits = ClientLimits {
+        rules: vec![
+            ClientLimitRule {
+                network: parse_network("192.168.1.10").unwrap(),
+                max_bandwidth: None,
+                max_connections: Some(1),
+            },
+            ClientLimitRule {
+                network: parse_network("192.168.1.0/24").unwrap(),
+                max_bandwidth: Some(1024 * 1024),
+                max_connections: Some(2),
+            },
+        ],
+        clients: Arc::new(Mutex::new(HashMap::new())),
+    };
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+    let slot1 = client_limits.acquire(ip("192.168.1.20")).unwrap();
+    assert!(slot1.throttle.is_some());
+    let slot2 = client_limits.acquire(ip("::ffff:192.168.1.20")).unwrap();
+    assert_eq!(TooManyConnections, client_limits.acquire(ip("192.168.1.20")).unwrap_err());
+    // Limits apply to each client individually.
+    let _other_client = client_limits.acquire(ip("192.168.1.21")).unwrap();
+    drop(slot1);
+    let _slot3 = client_limits.acquire(ip("192.168.1.20")).unwrap();
+    drop(slot2);
+
+    let slot4 = client_limits.acquire(ip("192.168.1.10")).unwrap();
+    assert!(slot4.throttle.is_none());
+    assert_eq!(TooManyConnections, client_limits.acquire(ip("192.168.1.10")).unwrap_err());
+
+    // Clients without matching rules are not limited.
+    let _slots = (0..10).map(|_| client_limits.acquire(ip("10.0.0.1")).unwrap()).collect::<Vec<_>>();
+}
@@ -0,0 +1,125 @@
+use std::fs::File;
+use std::io;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::net::{Shutdown, TcpStream};
+
+use openssl::ssl::SslStream;
+
+use crate::bandwidth::Throttle;
+
+const TLS_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A connection to a client, either via plain HTTP or via HTTPS.
+pub struct ClientStream {
+    connection: Connection,
+    /// If set, payloads are sent no faster than the throttle permits.
+    throttle: Option<Throttle>,
+}
+
+enum Connection {
+    Plain(TcpStream),
+    Tls(Box<SslStream<TcpStream>>),
+}
+
+impl ClientStream {
+    pub fn plain(stream: TcpStream) -> Self {
+        ClientStream {
+            connection: Connection::Plain(stream),
+            throttle: None,
+        }
+    }
+
+    pub fn tls(stream: SslStream<TcpStream>) -> Self {
+        ClientStream {
+            connection: Connection::Tls(Box::new(stream)),
+            throttle: None,
+        }
+    }
+
+    pub fn set_throttle(&mut self, throttle: Option<Throttle>) {
+        self.throttle = throttle;
+    }
+
+    pub fn tcp_stream(&self) -> &TcpStream {
+        match &self.connection {
+            Connection::Plain(s) => s,
+            Connection::Tls(s) => s.get_ref(),
+        }
+    }
+
+    pub fn shutdown(&mut self) {
+        if let Connection::Tls(s) = &mut self.connection {
+            let _ = s.shutdown();
+        }
+        let _ = self.tcp_stream().shutdown(Shutdown::Both);
+    }
+
+    /// Sends the given file, starting at offset bytes_sent until the given file size has been reached. Returns the
+    /// new offset.
+    pub fn send_file(&mut self, source: &mut File, filesize: u64, bytes_sent: i64) -> io::Result<i64> {
+        match self.throttle.clone() {
+            None => self.send_file_unthrottled(source, filesize, bytes_sent),
+            Some(throttle) => {
+                let mut offset = bytes_sent;
+                while (offset as u64) < filesize {
+                    let chunk_end = std::cmp::min(offset as u64 + throttle.chunk_size(), filesize);
+                    throttle.consume(chunk_end - offset as u64);
+                    offset = self.send_file_unthrottled(source, chunk_end, offset)?;
+                }
+                Ok(offset)
+            }
+        }
+    }
+
+    fn send_file_unthrottled(&mut self, source: &mut File, filesize: u64, bytes_sent: i64) -> io::Result<i64> {
+        match &mut self.connection {
+            Connection::Plain(s) => {
+                let result = crate::send_payload(source, filesize, bytes_sent, s);
+                // Enabling and then disabling the nodelay option results in a flush.
+                // For some reason, receiver.flush() does not have this effect.
+                s.set_nodelay(true)?;
+                s.set_nodelay(false)?;
+                result
+            }
+            Connection::Tls(s) => {
+                // sendfile cannot be used with TLS, since the payload needs to be encrypted in user space.
+                source.seek(SeekFrom::Start(bytes_sent as u64))?;
+                let mut remaining = filesize - bytes_sent as u64;
+                let mut buf = vec![0; TLS_BUFFER_SIZE];
+                while remaining > 0 {
+                    let len = std::cmp::min(remaining, TLS_BUFFER_SIZE as u64) as usize;
+                    source.read_exact(&mut buf[..len])?;
+                    s.write_all(&buf[..len])?;
+                    remaining -= len as u64;
+                }
+                s.flush()?;
+                Ok(filesize as i64)
+            }
+        }
+    }
+}
+
+impl Read for ClientStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.connection {
+            Connection::Plain(s) => s.read(buf),
+            Connection::Tls(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for ClientStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.connection {
+            Connection::Plain(s) => s.write(buf),
+            Connection::Tls(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.connection {
+            Connection::Plain(s) => s.flush(),
+            Connection::Tls(s) => s.flush(),
+        }
+    }
+}
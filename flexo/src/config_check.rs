@@ -0,0 +1,129 @@
+use crate::config_reload::{prepare_config, ReloadableContext};
+use crate::mirror_config;
+use crate::mirror_config::{parse_bandwidth, ConfigError, ConfigProblem, ConfigSource, MirrorConfig, TimeWindow};
+use crate::tls::TlsAcceptor;
+#[cfg(test)]
+use crate::test_fixtures;
+
+/// The outcome of "flexo check-config".
+#[derive(Debug)]
+pub struct ConfigReport {
+    /// Flexo refuses to start with most of these problems. Settings that are ignored, i.e., unknown keys in the
+    /// TOML file and environment variables that start with FLEXO_ but are not used by Flexo, are only logged as
+    /// warnings when Flexo starts, but they are reported as problems as well: They are usually misspelled settings.
+    pub problems: Vec<ConfigProblem>,
+}
+
+/// Runs "flexo check-config": Reports all problems of the configuration, without binding the port or contacting
+/// remote mirrors. Returns the exit code.
+pub fn check_config_command(source: &ConfigSource) -> i32 {
+    let report = check_config(source);
+    if report.problems.is_empty() {
+        println!("No problems found in {}.", source);
+        0
+    } else {
+        for problem in &report.problems {
+            eprintln!("{}", problem);
+        }
+        eprintln!("Found {} problem(s) in {}.", report.problems.len(), source);
+        1
+    }
+}
+
+/// Loads the configuration from the TOML file or from environment variables and validates it the same way as
+/// Flexo does when it starts.
+pub fn check_config(source: &ConfigSource) -> ConfigReport {
+    let (properties, ignored_settings) = match mirror_config::try_load_config_with_warnings(source) {
+        Ok(c) => c,
+        Err(ConfigError::UnreadableFile(path, e)) => {
+            let problems = vec![ConfigProblem::new(&path, format!("unable to read the file: {}", e))];
+            return ConfigReport { problems };
+        }
+        // The TOML parser stops at the first error. Its message already includes the line.
+        Err(ConfigError::InvalidToml(path, e)) => {
+            let problems = vec![ConfigProblem::new(&path, e.to_string())];
+            return ConfigReport { problems };
+        }
+        Err(ConfigError::InvalidEnvironment(problems)) => return ConfigReport { problems },
+    };
+    let mut problems = ignored_settings;
+    problems.extend(check_settings(&properties));
+    // Stops at the first invalid setting, like Flexo does when it starts.
+    let prepared = prepare_config(properties, None)
+        .and_then(|properties| ReloadableContext::from_config(properties, None));
+    if let Err(message) = prepared {
+        problems.push(ConfigProblem::new(&source.to_string(), message));
+    }
+    ConfigReport { problems }
+}
+
+/// Checks the settings that are not validated when Flexo starts, or only when they are first used, e.g. durations
+/// and the certificate of the TLS listener.
+fn check_settings(properties: &MirrorConfig) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+    if let Some(duration) = &properties.refresh_latency_tests_after {
+        if let Err(e) = humantime::parse_duration(duration) {
+            let message = format!("unable to parse the duration {:?}: {}", duration, e);
+            problems.push(ConfigProblem::new("refresh_latency_tests_after", message));
+        }
+    }
+    check_bandwidth(&mut problems, "low_speed_limit_formatted", &properties.low_speed_limit_formatted);
+    if let Some(prefetch) = &properties.prefetch {
+        check_time_window(&mut problems, "prefetch.time_window", &prefetch.time_window);
+    }
+    if let Some(tls_config) = &properties.tls {
+        if let Err(e) = TlsAcceptor::new(tls_config.clone()) {
+            let message = format!("unable to load the certificate {} or the private key {}: {}",
+                                  tls_config.certificate_file, tls_config.private_key_file, e);
+            problems.push(ConfigProblem::new("tls", message));
+        }
+    }
+    problems
+}
+
+fn check_bandwidth(problems: &mut Vec<ConfigProblem>, location: &str, bandwidth: &Option<String>) {
+    if let Some(bandwidth) = bandwidth {
+        if parse_bandwidth(bandwidth).is_none() {
+            let message = format!("unable to parse the bandwidth {:?}: Expected a bandwidth such as \"20 MBit/s\"",
+                                  bandwidth);
+            problems.push(ConfigProblem::new(location, message));
+        }
+    }
+}
+
+fn check_time_window(problems: &mut Vec<ConfigProblem>, location: &str, time_window: &Option<String>) {
+    if let Some(time_window) = time_window {
+        if TimeWindow::parse(time_window).is_none() {
+            let message = format!("unable to parse the time window {:?}: Expected a format like \"23:00-06:00\"",
+                                  time_window);
+            problems.push(ConfigProblem::new(location, message));
+        }
+    }
+}
+
+#[test]
+fn test_check_config() {
+    let directory = tempfile::tempdir().unwrap();
+    let check = |toml: &str| {
+        let path = directory.path().join("flexo.toml");
+        std::fs::write(&path, test_fixtures::config_toml(&format!("port = 7878\n{}", toml))).unwrap();
+        check_config(&ConfigSource::TomlFile(path.to_str().unwrap().to_owned()))
+    };
+    let report = check("\
+        refresh_latency_tests_after = \"2 fortnights\"
+        allowed_clients = [\"192.168.1.0/24\", \"192.168.300.0/24\"]
+        [upstream_bandwidth]
+        max_bandwidth = \"20 MBit/s\"
+        schedule = [{ time_window = \"23:00-06:00\", max_bandwidth = \"fast\" }]");
+    assert_eq!(2, report.problems.len());
+    assert_eq!("refresh_latency_tests_after", report.problems[0].location);
+    assert!(report.problems[1].message.contains("\"fast\""));
+    let report = check("allowed_clients = [\"192.168.300.0/24\"]\nunknown_setting = 1");
+    assert_eq!(2, report.problems.len());
+    assert_eq!("unknown_setting", report.problems[0].location);
+    assert!(report.problems[1].message.contains("192.168.300.0/24"));
+    // Flexo starts despite unknown settings, but they are usually misspelled settings.
+    let report = check("unknown_setting = 1");
+    assert_eq!(1, report.problems.len());
+    assert_eq!("unknown_setting", report.problems[0].location);
+}
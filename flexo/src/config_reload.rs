@@ -0,0 +1,266 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+
+use serde::Serialize;
+
+use flexo::JobContext;
+use flexo::thread_pool::ThreadPool;
+
+use crate::access_control::{AccessControl, InvalidClientNetwork};
+use crate::bandwidth::{InvalidUpstreamBandwidth, UpstreamBandwidth};
+use crate::client_limits::{ClientLimits, InvalidClientLimit};
+use crate::credentials::Credentials;
+use crate::mirror_config;
+use crate::mirror_config::{ConfigSource, MirrorConfig, UpstreamHttpVersion};
+use crate::mirror_flexo::DownloadJob;
+use crate::upstream_proxy::UpstreamProxy;
+#[cfg(test)]
+use crate::test_fixtures;
+
+/// Initializes the settings that are derived from other settings, and makes sure that all settings are valid.
+/// If the configuration is reloaded, the previous configuration is passed so that state can be retained.
+pub fn prepare_config(mut properties: MirrorConfig, previous: Option<&MirrorConfig>) -> Result<MirrorConfig, String> {
+    properties.upstream_bandwidth_limiter = match previous {
+        // Keep the limiter, so that downloads that are already running share the same budget with new downloads.
+        Some(p) if p.upstream_bandwidth == properties.upstream_bandwidth => p.upstream_bandwidth_limiter.clone(),
+        _ => match UpstreamBandwidth::from_config(&properties) {
+            Ok(u) => u,
+            Err(InvalidUpstreamBandwidth::InvalidBandwidth(bandwidth)) => {
+                return Err(format!("Unable to parse the upstream bandwidth {:?}: Expected a bandwidth such as \
+                    \"20 MBit/s\".", bandwidth));
+            }
+            Err(InvalidUpstreamBandwidth::InvalidTimeWindow(time_window)) => {
+                return Err(format!("Unable to parse the time window {:?}: Expected a format like \
+                    \"23:00-06:00\".", time_window));
+            }
+        },
+    };
+    properties.download_thread_pool = match previous {
+        // Changes of download_threads require a restart, so the running downloads keep their pool.
+        Some(p) => p.download_thread_pool.clone(),
+        None => ThreadPool::new("download", properties.download_thread_pool_config()),
+    };
+    if let Some(p) = previous {
+        // Downloads that start after the reload can reuse the connections of the running downloads.
+        properties.upstream_transfers = p.upstream_transfers.clone();
+    }
+    properties.upstream_proxy_settings = match properties.upstream_proxy.as_ref().map(UpstreamProxy::from_config) {
+        None => None,
+        Some(Ok(upstream_proxy)) => Some(upstream_proxy),
+        Some(Err(e)) => return Err(format!("Invalid setting in the [upstream_proxy] section: {}", e)),
+    };
+    let custom_repo_tls_configs = properties.custom_repo.iter().flatten().filter_map(|repo| {
+        repo.tls.as_ref().map(|tls| (format!("[custom_repo.tls] of {}", repo.name), tls))
+    });
+    let tls_configs = properties.upstream_tls.iter().map(|tls| ("[upstream_tls]".to_owned(), tls))
+        .chain(custom_repo_tls_configs);
+    for (section, tls_config) in tls_configs {
+        if let Err(e) = tls_config.validate() {
+            return Err(format!("Invalid setting in the section {}: {}", section, e));
+        }
+        if tls_config.insecure_disable_verification == Some(true) {
+            warn!("The certificates of remote mirrors are not verified, as configured in the section {}.", section);
+        }
+    }
+    for custom_repo in properties.custom_repo.iter_mut().flatten() {
+        if let Some(path) = &custom_repo.credentials_file {
+            match Credentials::from_file(Path::new(path)) {
+                Ok(credentials) => custom_repo.credentials = Some(credentials),
+                Err(e) => {
+                    return Err(format!("Unable to read the credentials of the custom repo {} from {}: {:?}",
+                                       custom_repo.name, path, e));
+                }
+            }
+        }
+    }
+    if properties.upstream_http_version == Some(UpstreamHttpVersion::Http2) && !curl::Version::get().feature_http2() {
+        warn!("The setting upstream_http_version = \"2\" is ignored because libcurl was built without HTTP/2 support.");
+    }
+    Ok(properties)
+}
+
+/// The parts of the server's state that are replaced when the configuration is reloaded. Each client connection
+/// uses the state that was current when the connection was accepted.
+pub struct ReloadableContext {
+    pub properties: MirrorConfig,
+    pub access_control: AccessControl,
+    pub client_limits: ClientLimits,
+    pub admin_credentials: Option<Credentials>,
+}
+
+impl ReloadableContext {
+    /// If the configuration is reloaded, the previous context is passed so that state can be retained.
+    pub fn from_config(properties: MirrorConfig, previous: Option<&ReloadableContext>) -> Result<Self, String> {
+        let access_control = match AccessControl::from_config(&properties) {
+            Ok(a) => a,
+            Err(InvalidClientNetwork(network)) => {
+                return Err(format!("Unable to parse {:?}: Expected an IP address or a network in CIDR notation, \
+                    such as 192.168.1.0/24.", network));
+            }
+        };
+        let client_limits = match previous {
+            // Keep the connection counts and throttles of the clients that are currently connected.
+            Some(p) if p.properties.client_limits == properties.client_limits => p.client_limits.clone(),
+            _ => match ClientLimits::from_config(&properties) {
+                Ok(c) => c,
+                Err(InvalidClientLimit::InvalidNetwork(network)) => {
+                    return Err(format!("Unable to parse the network {:?} of the client limits: Expected an IP \
+                        address or a network in CIDR notation, such as 192.168.1.0/24.", network));
+                }
+                Err(InvalidClientLimit::InvalidBandwidth(bandwidth)) => {
+                    return Err(format!("Unable to parse the bandwidth {:?} of the client limits: Expected a \
+                        bandwidth such as \"2 MiB/s\".", bandwidth));
+                }
+            },
+        };
+        let admin_credentials = match &properties.admin_credentials_file {
+            None => None,
+            Some(path) => match Credentials::from_file(Path::new(path)) {
+                Ok(c) => Some(c),
+                Err(e) => return Err(format!("Unable to read the admin credentials from {}: {:?}", path, e)),
+            },
+        };
+        Ok(ReloadableContext {
+            properties,
+            access_control,
+            client_limits,
+            admin_credentials,
+        })
+    }
+}
+
+/// Reverts all settings of the new configuration that cannot be applied while Flexo is running, and returns
+/// the names of those settings that have changed.
+pub fn retain_settings_requiring_restart(new: &mut MirrorConfig, current: &MirrorConfig) -> Vec<&'static str> {
+    let mut changed = Vec::new();
+    macro_rules! retain {
+        ($field:ident) => {
+            if new.$field != current.$field {
+                changed.push(stringify!($field));
+                new.$field = current.$field.clone();
+            }
+        };
+    }
+    retain!(cache_directory);
+    retain!(port);
+    retain!(listen_ip_address);
+    retain!(tls);
+    retain!(mirror_selection_method);
+    retain!(mirrors_predefined);
+    retain!(archive_fallback);
+    retain!(prefetch);
+    retain!(client_threads);
+    retain!(download_threads);
+    // Blacklisted mirrors can be removed while Flexo is running, everything else requires new latency tests.
+    let blacklist = new.mirrors_auto.as_ref().map(|m| m.mirrors_blacklist.clone());
+    if let (Some(new_mirrors_auto), Some(current_mirrors_auto)) = (&mut new.mirrors_auto, &current.mirrors_auto) {
+        new_mirrors_auto.mirrors_blacklist = current_mirrors_auto.mirrors_blacklist.clone();
+    }
+    retain!(mirrors_auto);
+    if let (Some(new_mirrors_auto), Some(blacklist)) = (&mut new.mirrors_auto, blacklist) {
+        let is_unblocked = |url: &String| !blacklist.contains(url);
+        if current.mirrors_auto.iter().flat_map(|m| m.mirrors_blacklist.iter()).any(is_unblocked) {
+            // Mirrors that were removed from the blacklist are only used again after new latency tests.
+            changed.push("mirrors_blacklist");
+        }
+        new_mirrors_auto.mirrors_blacklist = blacklist;
+    }
+    changed
+}
+
+/// The changes made by a reload of the configuration.
+#[derive(Serialize, Debug)]
+pub struct ReloadReport {
+    /// Settings that have changed, but will only be applied after a restart.
+    pub restart_required: Vec<&'static str>,
+    /// The number of mirrors that are no longer used because they were added to the blacklist.
+    pub num_removed_mirrors: usize,
+}
+
+/// Reloads the configuration on SIGHUP or when requested via the admin endpoint.
+#[derive(Clone)]
+pub struct ConfigReloader {
+    context: Arc<RwLock<Arc<ReloadableContext>>>,
+    job_context: Arc<Mutex<JobContext<DownloadJob>>>,
+    config_source: ConfigSource,
+    /// Only one reload may run at any given time.
+    reload_mutex: Arc<Mutex<()>>,
+}
+
+impl ConfigReloader {
+    pub fn new(
+        context: ReloadableContext,
+        job_context: Arc<Mutex<JobContext<DownloadJob>>>,
+        config_source: ConfigSource,
+    ) -> Self {
+        ConfigReloader {
+            context: Arc::new(RwLock::new(Arc::new(context))),
+            job_context,
+            config_source,
+            reload_mutex: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn current(&self) -> Arc<ReloadableContext> {
+        self.context.read().unwrap().clone()
+    }
+
+    /// Applies the new configuration, or leaves the current configuration unchanged if the new configuration is
+    /// invalid.
+    pub fn reload(&self) -> Result<ReloadReport, String> {
+        let _lock = self.reload_mutex.lock().unwrap();
+        if self.config_source == ConfigSource::Environment {
+            info!("The settings are read from environment variables, which do not change while Flexo is running.");
+        }
+        let current = self.current();
+        let properties = mirror_config::try_load_config(&self.config_source).map_err(|e| e.to_string())?;
+        let mut properties = prepare_config(properties, Some(&current.properties))?;
+        let restart_required = retain_settings_requiring_restart(&mut properties, &current.properties);
+        let context = ReloadableContext::from_config(properties, Some(&current))?;
+        let blacklist = context.properties.mirrors_auto.as_ref()
+            .map(|m| m.mirrors_blacklist.clone())
+            .unwrap_or_default();
+        {
+            let mut job_context = self.job_context.lock().unwrap();
+            let num_removed_mirrors = match job_context.retain_providers(|p| !blacklist.contains(&p.uri)) {
+                None => return Err("All mirrors are blacklisted.".to_owned()),
+                Some(n) => n,
+            };
+            job_context.properties = context.properties.clone();
+            // Idle channels keep the settings they were created with, e.g. the proxy.
+            job_context.reset_channels();
+            *self.context.write().unwrap() = Arc::new(context);
+            Ok(ReloadReport { restart_required, num_removed_mirrors })
+        }
+    }
+}
+
+#[test]
+fn test_retain_settings_requiring_restart() {
+    let current = test_fixtures::mirror_config("port = 7878\nlow_speed_limit = 1000");
+    let mut new = test_fixtures::mirror_config("port = 8080\nlow_speed_limit = 2000");
+    assert_eq!(vec!["port"], retain_settings_requiring_restart(&mut new, &current));
+    assert_eq!(7878, new.port);
+    assert_eq!(Some(2000), new.low_speed_limit());
+}
+
+#[test]
+fn test_reload_retains_client_limits() {
+    let config = |max_connections: u32| test_fixtures::mirror_config(&format!("\
+        port = 7878
+        [[client_limits]]
+        network = \"192.168.1.0/24\"
+        max_bandwidth = \"1 MiB/s\"
+        max_connections = {}", max_connections));
+    let ip = "192.168.1.20".parse().unwrap();
+    let current = ReloadableContext::from_config(config(1), None).unwrap();
+    let slot = current.client_limits.acquire(ip).unwrap();
+    let reloaded = ReloadableContext::from_config(config(1), Some(&current)).unwrap();
+    // The connection that was accepted before the reload still counts.
+    assert!(reloaded.client_limits.acquire(ip).is_err());
+    drop(slot);
+    let _slot = reloaded.client_limits.acquire(ip).unwrap();
+    // Changed limits start from scratch.
+    let changed = ReloadableContext::from_config(config(2), Some(&reloaded)).unwrap();
+    assert!(changed.client_limits.acquire(ip).is_ok());
+}
@@ -0,0 +1,106 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Credentials used for HTTP authentication. The Debug implementation does not reveal any secrets, so that
+/// credentials do not show up in the logs.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum Credentials {
+    Basic { username: String, password: String },
+    Bearer(String),
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credentials::Basic { username, .. } => write!(f, "Basic {{ username: {:?}, password: <redacted> }}", username),
+            Credentials::Bearer(_) => write!(f, "Bearer(<redacted>)"),
+        }
+    }
+}
+
+impl Credentials {
+    /// Reads the credentials from a file containing a single line: Either "username:password" for HTTP Basic
+    /// authentication, or a token for bearer authentication.
+    pub fn from_file(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        Credentials::parse(&contents).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "file is empty"))
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        let line = s.lines().next()?.trim();
+        if line.is_empty() {
+            return None;
+        }
+        match line.split_once(':') {
+            None => Some(Credentials::Bearer(line.to_owned())),
+            Some((username, password)) => Some(Credentials::Basic {
+                username: username.to_owned(),
+                password: password.to_owned(),
+            }),
+        }
+    }
+
+    /// The value of the Authorization header a client has to send to authenticate with these credentials.
+    pub fn authorization_header_value(&self) -> String {
+        match self {
+            Credentials::Basic { username, password } => {
+                format!("Basic {}", base64::encode(format!("{}:{}", username, password)))
+            }
+            Credentials::Bearer(token) => format!("Bearer {}", token),
+        }
+    }
+
+    /// The value of the WWW-Authenticate header sent to clients that have not authenticated.
+    pub fn challenge(&self) -> &'static str {
+        match self {
+            Credentials::Basic { .. } => "Basic realm=\"flexo\"",
+            Credentials::Bearer(_) => "Bearer realm=\"flexo\"",
+        }
+    }
+
+    pub fn matches(&self, authorization: &AuthorizationHeader) -> bool {
+        let expected = self.authorization_header_value();
+        let (expected_scheme, expected_value) = expected.split_once(' ').unwrap();
+        match authorization.0.trim().split_once(' ') {
+            None => false,
+            Some((scheme, value)) => {
+                scheme.eq_ignore_ascii_case(expected_scheme) && constant_time_eq(value.trim(), expected_value)
+            }
+        }
+    }
+}
+
+/// The value of the Authorization header sent by a client.
+#[derive(Clone, PartialEq, Eq)]
+pub struct AuthorizationHeader(pub String);
+
+impl fmt::Debug for AuthorizationHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AuthorizationHeader(<redacted>)")
+    }
+}
+
+/// Compares the two strings without returning early, so that the time required does not reveal how many leading
+/// characters were correct.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len() && a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[test]
+fn test_credentials() {
+    let basic = Credentials::parse("flexo:secret\n").unwrap();
+    assert_eq!(Credentials::Basic { username: "flexo".to_owned(), password: "secret".to_owned() }, basic);
+    assert!(basic.matches(&AuthorizationHeader("Basic ZmxleG86c2VjcmV0".to_owned())));
+    assert!(basic.matches(&AuthorizationHeader("basic ZmxleG86c2VjcmV0".to_owned())));
+    assert!(!basic.matches(&AuthorizationHeader("Basic ZmxleG86c2VjcmV1".to_owned())));
+    assert!(!basic.matches(&AuthorizationHeader("Bearer ZmxleG86c2VjcmV0".to_owned())));
+    assert!(!format!("{:?}", basic).contains("secret"));
+
+    let bearer = Credentials::parse("s3cr3t-t0ken").unwrap();
+    assert!(bearer.matches(&AuthorizationHeader("Bearer s3cr3t-t0ken".to_owned())));
+    assert!(!bearer.matches(&AuthorizationHeader("Bearer s3cr3t".to_owned())));
+    assert!(!bearer.matches(&AuthorizationHeader("s3cr3t-t0ken".to_owned())));
+    assert_eq!(None, Credentials::parse("\n"));
+}
@@ -0,0 +1,53 @@
+use curl::easy::{Easy, Easy2};
+
+/// The options of [`Easy2`] that are shared by all connections to remote mirrors, so that they can be applied to
+/// [`Easy`] handles as well.
+pub trait CurlHandle {
+    fn proxy(&mut self, url: &str) -> Result<(), curl::Error>;
+    fn proxy_username(&mut self, user: &str) -> Result<(), curl::Error>;
+    fn proxy_password(&mut self, pass: &str) -> Result<(), curl::Error>;
+    fn noproxy(&mut self, skip: &str) -> Result<(), curl::Error>;
+    fn cainfo(&mut self, path: &str) -> Result<(), curl::Error>;
+    fn ssl_cert(&mut self, cert: &str) -> Result<(), curl::Error>;
+    fn ssl_key(&mut self, key: &str) -> Result<(), curl::Error>;
+    fn ssl_verify_peer(&mut self, verify: bool) -> Result<(), curl::Error>;
+    fn ssl_verify_host(&mut self, verify: bool) -> Result<(), curl::Error>;
+}
+
+/// Forwards each method to the inherent method of the same name, which takes precedence over the trait method.
+macro_rules! impl_curl_handle {
+    (impl<$($generic:ident),*> for $handle:ty) => {
+        impl<$($generic),*> CurlHandle for $handle {
+            fn proxy(&mut self, url: &str) -> Result<(), curl::Error> {
+                self.proxy(url)
+            }
+            fn proxy_username(&mut self, user: &str) -> Result<(), curl::Error> {
+                self.proxy_username(user)
+            }
+            fn proxy_password(&mut self, pass: &str) -> Result<(), curl::Error> {
+                self.proxy_password(pass)
+            }
+            fn noproxy(&mut self, skip: &str) -> Result<(), curl::Error> {
+                self.noproxy(skip)
+            }
+            fn cainfo(&mut self, path: &str) -> Result<(), curl::Error> {
+                self.cainfo(path)
+            }
+            fn ssl_cert(&mut self, cert: &str) -> Result<(), curl::Error> {
+                self.ssl_cert(cert)
+            }
+            fn ssl_key(&mut self, key: &str) -> Result<(), curl::Error> {
+                self.ssl_key(key)
+            }
+            fn ssl_verify_peer(&mut self, verify: bool) -> Result<(), curl::Error> {
+                self.ssl_verify_peer(verify)
+            }
+            fn ssl_verify_host(&mut self, verify: bool) -> Result<(), curl::Error> {
+                self.ssl_verify_host(verify)
+            }
+        }
+    };
+}
+
+impl_curl_handle!(impl<H> for Easy2<H>);
+impl_curl_handle!(impl<> for Easy);
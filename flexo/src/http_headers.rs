@@ -22,10 +22,22 @@ pub fn reply_header_internal_server_error() -> String {
     reply_header("500 Internal Server Error", 0, None, PayloadOrigin::NoPayload, SystemTime::now())
 }
 
+pub fn reply_header_unprocessable_entity(content_length: u64) -> String {
+    reply_header("422 Unprocessable Entity", content_length, None, PayloadOrigin::NoPayload, SystemTime::now())
+}
+
+pub fn reply_header_conflict(content_length: u64) -> String {
+    reply_header("409 Conflict", content_length, None, PayloadOrigin::NoPayload, SystemTime::now())
+}
+
 pub fn reply_header_forbidden() -> String {
     reply_header("403 Forbidden", 0, None, PayloadOrigin::NoPayload, SystemTime::now())
 }
 
+pub fn reply_header_service_unavailable() -> String {
+    reply_header("503 Service Unavailable", 0, None, PayloadOrigin::NoPayload, SystemTime::now())
+}
+
 fn reply_header(
     status_line: &str,
     content_length: u64,
@@ -57,6 +69,22 @@ fn reply_header(
     header
 }
 
+pub fn reply_header_unauthorized(challenge: &str) -> String {
+    unauthorized_header(challenge, SystemTime::now())
+}
+
+fn unauthorized_header(challenge: &str, now: SystemTime) -> String {
+    let timestamp = httpdate::fmt_http_date(now);
+    let header = format!("\
+        HTTP/1.1 401 Unauthorized\r\n\
+        Server: flexo\r\n\
+        Date: {}\r\n\
+        WWW-Authenticate: {}\r\n\
+        Content-Length: 0\r\n\r\n", timestamp, challenge);
+
+    header
+}
+
 pub fn redirect_header(path: &str, now: SystemTime) -> String {
     let timestamp = httpdate::fmt_http_date(now);
     let header = format!("\
@@ -89,6 +117,19 @@ fn test_reply_header() {
     assert_eq!(expected, actual)
 }
 
+#[test]
+fn test_unauthorized_header() {
+    let timestamp = httpdate::parse_http_date("Thu, 06 Apr 2023 20:00:18 GMT").unwrap();
+    let expected = "HTTP/1.1 401 Unauthorized\r\n\
+        Server: flexo\r\n\
+        Date: Thu, 06 Apr 2023 20:00:18 GMT\r\n\
+        WWW-Authenticate: Basic realm=\"flexo\"\r\n\
+        Content-Length: 0\r\n\r\n";
+    let actual = unauthorized_header("Basic realm=\"flexo\"", timestamp);
+
+    assert_eq!(expected, actual)
+}
+
 #[test]
 fn test_redirect_header() {
     let timestamp = httpdate::parse_http_date("Thu, 06 Apr 2023 20:00:18 GMT").unwrap();
@@ -1,23 +1,48 @@
 mod provider_guards;
+pub mod thread_pool;
+pub mod progress;
 
 #[macro_use] extern crate log;
 
 use std::collections::{HashMap, HashSet};
 use std::collections::hash_map::Entry;
 use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
-use std::{thread, fmt};
-use std::thread::JoinHandle;
+use std::fmt;
+use std::thread;
 use serde::Serialize;
 use std::time::{Instant, Duration};
-use crossbeam::channel::{Receiver, Sender, unbounded};
+use crossbeam::channel::{Receiver, Sender, bounded, unbounded};
 use crate::provider_guards::{ProviderGuards, ProviderChoice, ProviderGuard};
+use crate::thread_pool::{TaskStart, ThreadPool, ThreadPoolConfig};
+use crate::progress::{ProgressReceiver, ProgressSender, ProgressSnapshot};
 use std::fmt::{Display, Formatter};
 
-const NUM_MAX_ATTEMPTS: i32 = 25;
+/// The thread pool used for jobs, unless a different thread pool is set with JobContext::with_thread_pool.
+pub const DEFAULT_JOB_THREAD_POOL_CONFIG: ThreadPoolConfig = ThreadPoolConfig {
+    max_threads: 64,
+    max_queue_size: 256,
+    // Jobs that have not started by then are pointless: The client is no longer waiting for the content length.
+    queue_timeout: Duration::from_secs(5),
+};
 
-// It's important that this value is lower than the TIMEOUT_RECEIVE_CONTENT_LENGTH value:
-// Otherwise, if we keep doing our retries for too long, the other thread stops waiting.
-const TIMEOUT_ALL_RETRIES: Duration = Duration::from_secs(4);
+/// Limits how long a job keeps trying other providers after an attempt was unsuccessful.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FailoverBudget {
+    /// The maximum number of providers tried, including the first one.
+    pub max_attempts: u32,
+    /// No further provider is tried once this much time has passed since the first unsuccessful attempt.
+    pub retry_timeout: Duration,
+    /// Providers that have failed recently are only selected if all other providers have failed recently as well.
+    pub provider_cooldown: Duration,
+}
+
+pub const DEFAULT_FAILOVER_BUDGET: FailoverBudget = FailoverBudget {
+    max_attempts: 25,
+    // Clients that are served while the job is running are disconnected if the file stops growing for too long,
+    // so there's little point in retrying for much longer than that.
+    retry_timeout: Duration::from_secs(20),
+    provider_cooldown: Duration::from_secs(30),
+};
 
 pub const LOGICAL_CLOCK_INITIAL_VALUE: u32 = 1;
 
@@ -106,8 +131,14 @@ pub trait Provider where
 
     fn punish(&self, mut provider_metrics: MutexGuard<HashMap<ProviderIdentifier, ProviderMetrics>>) {
         provider_metrics.entry(self.identifier())
-            .and_modify(|p| p.num_failures += 1)
-            .or_insert_with(ProviderMetrics::default);
+            .and_modify(|p| {
+                p.num_failures += 1;
+                p.last_failure = Some(Instant::now());
+            })
+            .or_insert_with(|| ProviderMetrics {
+                last_failure: Some(Instant::now()),
+                ..ProviderMetrics::default()
+            });
     }
 }
 
@@ -140,13 +171,23 @@ pub trait Job where Self: std::marker::Sized + std::fmt::Debug + std::marker::Se
     fn properties(&self)-> Self::PR;
     fn cache_state(order: &<Self as Job>::O, properties: &Self::PR) -> Option<CachedItem>;
     fn serve_from_provider(self, channel: Self::C, properties: &Self::PR) -> JobResult<Self>;
+
+    /// Like serve_from_provider, but helper providers may be selected to fetch parts of the order in parallel.
+    fn serve_from_providers(
+        self,
+        channel: Self::C,
+        properties: &Self::PR,
+        _helper_selector: HelperSelector<Self::P>,
+    ) -> JobResult<Self> {
+        self.serve_from_provider(channel, properties)
+    }
     fn handle_error(self, error: Self::OE) -> JobResult<Self>;
     fn acquire_resources(order: &Self::O, properties: &Self::PR, last_chance: bool) -> std::io::Result<Self::JS>;
 
     fn get_channel(
         &self,
         channels: &Arc<Mutex<HashMap<Self::P, Self::C>>>,
-        tx: Sender<FlexoProgress>,
+        tx: ProgressSender,
         last_chance: bool
     ) -> Result<(Self::C, ChannelEstablishment), Self::OE> {
         let mut channels = channels.lock().unwrap();
@@ -169,19 +210,47 @@ pub trait Job where Self: std::marker::Sized + std::fmt::Debug + std::marker::Se
     }
 }
 
+/// Selects the providers that fetch parts of an order in parallel to the main provider, see
+/// [`Job::serve_from_providers`]. Providers are only selected once the job has decided to split the order into parts:
+/// A selected provider counts as being in use for as long as it is held.
+pub struct HelperSelector<P> {
+    max_providers: usize,
+    select: Box<dyn Fn(usize) -> Vec<Arc<P>> + Send>,
+}
+
+impl <P> HelperSelector<P> {
+    /// The maximum number of helper providers that may be used for a single order.
+    pub fn max_providers(&self) -> usize {
+        self.max_providers
+    }
+
+    /// Selects up to num_providers providers. Fewer providers are returned if not enough providers are available.
+    pub fn select(&self, num_providers: usize) -> Vec<Arc<P>> {
+        (self.select)(std::cmp::min(num_providers, self.max_providers))
+    }
+}
+
+impl <P> fmt::Debug for HelperSelector<P> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HelperSelector")
+            .field("max_providers", &self.max_providers)
+            .finish_non_exhaustive()
+    }
+}
+
 pub trait Order where Self: std::marker::Sized + std::clone::Clone + std::cmp::Eq + std::hash::Hash + std::fmt::Debug + std::marker::Send + 'static {
     type J: Job<O=Self>;
     fn new_channel(
         self,
         properties: <<Self as Order>::J as Job>::PR,
-        tx: Sender<FlexoProgress>,
+        tx: ProgressSender,
         last_chance: bool,
     ) -> Result<<<Self as Order>::J as Job>::C, <<Self as Order>::J as Job>::OE>;
 
     fn reuse_channel(
         self,
         properties: <<Self as Order>::J as Job>::PR,
-        tx: Sender<FlexoProgress>,
+        tx: ProgressSender,
         last_chance: bool,
         channel: <<Self as Order>::J as Job>::C,
     ) -> Result<<<Self as Order>::J as Job>::C, <<Self as Order>::J as Job>::OE>;
@@ -192,37 +261,68 @@ pub trait Order where Self: std::marker::Sized + std::clone::Clone + std::cmp::E
         true
     }
 
+    /// Returns true if the fallback provider may be used after this order was unavailable at all regular providers.
+    fn fallback_permitted(&self) -> bool {
+        self.is_cacheable()
+    }
+
     fn description(&self) -> &str;
 
     fn try_until_success(
         self,
         provider_guards: Arc<ProviderGuards<<<Self as Order>::J as Job>::P>>,
         provider_metrics: &mut Arc<Mutex<HashMap<ProviderIdentifier, ProviderMetrics>>>,
-        custom_provider: Option<<<Self as Order>::J as Job>::P>,
+        order_providers: OrderProviders<<<Self as Order>::J as Job>::P>,
         channels: Arc<Mutex<HashMap<<<Self as Order>::J as Job>::P, <<Self as Order>::J as Job>::C>>>,
-        tx_integration_test: Sender<IntegrationTestMessage>,
-        tx_progress: Sender<FlexoProgress>,
+        reporting: JobReporting,
         properties: <<Self as Order>::J as Job>::PR,
-    ) -> JobResult<Self::J> {
+    ) -> JobResult<Self::J> where <<Self as Order>::J as Job>::P: Sync {
+        let OrderProviders { custom_provider, fallback_provider } = order_providers;
+        let JobReporting { tx_integration_test, tx_progress, attempt } = reporting;
         let mut num_attempt = 0;
         let mut punished_providers = Vec::new();
-        let start_time = Instant::now();
+        let failover_budget = properties.failover_budget();
+        let mut first_unsuccessful_attempt: Option<Instant> = None;
         let mut unsuccessful_providers = HashSet::<ProviderIdentifier>::new();
+        // The fallback provider is only used if the order was unavailable at all regular providers.
+        let mut fallback_provider = match custom_provider {
+            None if self.fallback_permitted() => fallback_provider,
+            _ => None,
+        };
+        let mut use_fallback_provider = false;
+        // True if the connection for the latest attempt could not be established.
+        let mut order_error;
         let result = loop {
             num_attempt += 1;
+            order_error = false;
             debug!("Attempt number {}", num_attempt);
-            if num_attempt > 1 && start_time.elapsed() > TIMEOUT_ALL_RETRIES {
-                warn!("Unable to complete attempt number {}: The timeout has elapsed.", num_attempt);
+            let retry_timeout_elapsed = match first_unsuccessful_attempt {
+                Some(t) => t.elapsed() >= failover_budget.retry_timeout,
+                None => false,
+            };
+            if retry_timeout_elapsed {
+                info!("The retry timeout has elapsed: Attempt number {} is the last attempt.", num_attempt);
             }
-            let (provider_guard, is_last_provider) = self.select_provider(
-                &provider_guards,
-                &mut provider_metrics.lock().unwrap(),
-                &custom_provider,
-                &unsuccessful_providers,
-            );
+            let (provider_guard, is_last_provider) = if use_fallback_provider {
+                (ProviderGuard::new(fallback_provider.take().unwrap()), true)
+            } else {
+                self.select_provider(
+                    &provider_guards,
+                    &mut provider_metrics.lock().unwrap(),
+                    &custom_provider,
+                    &unsuccessful_providers,
+                    failover_budget.provider_cooldown,
+                )
+            };
             debug!("Trying to serve {} via {}", &self.description(), provider_guard.guarded_provider.identifier());
+            *attempt.lock().unwrap() = Attempt {
+                provider: Some(provider_guard.guarded_provider.identifier()),
+                num_attempt,
+            };
             debug!("No providers are left after this provider? {}", is_last_provider);
-            let last_chance = num_attempt >= NUM_MAX_ATTEMPTS || is_last_provider || !self.retryable();
+            let no_regular_providers_left = num_attempt >= failover_budget.max_attempts || retry_timeout_elapsed ||
+                is_last_provider || !self.retryable();
+            let last_chance = no_regular_providers_left && fallback_provider.is_none();
             send(
                 IntegrationTestMessage::ProviderSelected(provider_guard.guarded_provider.identifier()),
                 &tx_integration_test
@@ -237,7 +337,31 @@ pub trait Order where Self: std::marker::Sized + std::clone::Clone + std::cmp::E
                         IntegrationTestMessage::ChannelEstablished(channel_establishment),
                         &tx_integration_test
                     );
-                    job.serve_from_provider(channel, &properties)
+                    let max_helper_providers = properties.max_parallel_providers().saturating_sub(1);
+                    if max_helper_providers > 0 && self.is_cacheable() && custom_provider.is_none() &&
+                        !use_fallback_provider {
+                        let mut exclude_providers = unsuccessful_providers.clone();
+                        exclude_providers.insert(provider_guard.guarded_provider.identifier());
+                        let order = self.clone();
+                        let provider_guards = Arc::clone(&provider_guards);
+                        let provider_metrics = Arc::clone(provider_metrics);
+                        let provider_cooldown = failover_budget.provider_cooldown;
+                        let helper_selector = HelperSelector {
+                            max_providers: max_helper_providers,
+                            select: Box::new(move |num_providers| {
+                                order.select_helper_providers(
+                                    &provider_guards,
+                                    &provider_metrics.lock().unwrap(),
+                                    exclude_providers.clone(),
+                                    num_providers,
+                                    provider_cooldown,
+                                ).into_iter().map(|guard| guard.guarded_provider).collect()
+                            }),
+                        };
+                        job.serve_from_providers(channel, &properties, helper_selector)
+                    } else {
+                        job.serve_from_provider(channel, &properties)
+                    }
                 }
                 Err(e) => {
                     warn!("Error while attempting to establish a new connection: {:?}", e);
@@ -245,7 +369,7 @@ pub trait Order where Self: std::marker::Sized + std::clone::Clone + std::cmp::E
                         IntegrationTestMessage::OrderError,
                         &tx_integration_test
                     );
-                    let _ = tx_progress.send(FlexoProgress::OrderError);
+                    order_error = true;
                     job.handle_error(e)
                 }
             };
@@ -276,15 +400,36 @@ pub trait Order where Self: std::marker::Sized + std::clone::Clone + std::cmp::E
                     break result;
                 },
             };
-            if result.is_success() || last_chance {
+            // The fallback provider is meant for orders that no regular provider has, not as a replacement for
+            // regular providers that are currently unreliable.
+            match &result {
+                JobResult::Unavailable(_) => {}
+                _ => fallback_provider = None,
+            }
+            if result.is_success() || last_chance || (no_regular_providers_left && fallback_provider.is_none()) {
                 break result;
             }
+            if no_regular_providers_left {
+                info!("{} is unavailable at all providers, try the fallback provider.", &self.description());
+                use_fallback_provider = true;
+            }
             if !result.is_success() {
                 unsuccessful_providers.insert(provider_guard.guarded_provider.identifier());
+                first_unsuccessful_attempt.get_or_insert_with(Instant::now);
             }
         };
         if !result.is_success() {
             Self::pardon(punished_providers, provider_metrics.lock().unwrap());
+            // Consumers receive exactly one event that tells them why the order was not completed. Jobs that are
+            // started with last_chance report an unavailable order themselves, as soon as they know about it.
+            let terminal_event = match &result {
+                JobResult::Unavailable(_) => None,
+                _ if order_error => Some(FlexoProgress::OrderError),
+                _ => Some(FlexoProgress::Failed),
+            };
+            if let Some(terminal_event) = terminal_event {
+                tx_progress.send(terminal_event);
+            }
         }
 
         result
@@ -296,7 +441,12 @@ pub trait Order where Self: std::marker::Sized + std::clone::Clone + std::cmp::E
         provider_metrics: &'a mut HashMap<ProviderIdentifier, ProviderMetrics>,
         custom_provider: &'a Option<<<Self as Order>::J as Job>::P>,
         exclude_providers: &HashSet<ProviderIdentifier>,
+        provider_cooldown: Duration,
     ) -> (ProviderGuard<<<Self as Order>:: J as Job>::P>, bool) {
+        let is_cooling_down = |provider_metric: &ProviderMetrics| match provider_metric.last_failure {
+            Some(t) => t.elapsed() < provider_cooldown,
+            None => false,
+        };
         match custom_provider {
             Some(p) => (ProviderGuard::new(p.clone()), true),
             None => {
@@ -314,7 +464,11 @@ pub trait Order where Self: std::marker::Sized + std::clone::Clone + std::cmp::E
                             };
                             let score: <<Self as Order>:: J as Job>::DSC =
                                 DynamicScoreCacheable::from_dynamic_provider_metrics(dynamic_metric);
-                            ProviderChoice::Include(score)
+                            if is_cooling_down(&provider_metric) {
+                                ProviderChoice::Fallback(score)
+                            } else {
+                                ProviderChoice::Include(score)
+                            }
                         }
                     })
                 } else {
@@ -331,7 +485,11 @@ pub trait Order where Self: std::marker::Sized + std::clone::Clone + std::cmp::E
                             };
                             let score: <<Self as Order>:: J as Job>::DSU =
                                 DynamicScoreUncacheable::from_dynamic_provider_metrics(dynamic_metric);
-                            ProviderChoice::Include(score)
+                            if is_cooling_down(&provider_metric) {
+                                ProviderChoice::Fallback(score)
+                            } else {
+                                ProviderChoice::Include(score)
+                            }
                         }
                     })
                 };
@@ -342,13 +500,57 @@ pub trait Order where Self: std::marker::Sized + std::clone::Clone + std::cmp::E
                     })
                     .or_insert(ProviderMetrics {
                         num_usages: 1,
-                        num_failures: 0
+                        num_failures: 0,
+                        last_failure: None,
                     });
                 (provider_guard, num_remaining <= 1)
             }
         }
     }
 
+    /// Selects up to num_providers providers which can fetch parts of the order in parallel to the main provider.
+    /// Providers that have failed recently are not selected.
+    fn select_helper_providers(
+        &self,
+        provider_guards: &ProviderGuards<<<Self as Order>::J as Job>::P>,
+        provider_metrics: &HashMap<ProviderIdentifier, ProviderMetrics>,
+        mut exclude_providers: HashSet<ProviderIdentifier>,
+        num_providers: usize,
+        provider_cooldown: Duration,
+    ) -> Vec<ProviderGuard<<<Self as Order>::J as Job>::P>> {
+        let mut helper_guards = Vec::new();
+        while helper_guards.len() < num_providers {
+            let result = provider_guards.try_get_provider_guard(|p, num_current_usages| {
+                let provider_metric = *(provider_metrics.get(&p.identifier()))
+                    .unwrap_or(&ProviderMetrics::default());
+                let is_cooling_down = match provider_metric.last_failure {
+                    Some(t) => t.elapsed() < provider_cooldown,
+                    None => false,
+                };
+                if exclude_providers.contains(&p.identifier()) || is_cooling_down {
+                    ProviderChoice::Exclude
+                } else {
+                    let dynamic_metric = DynamicProviderMetrics {
+                        num_failures: provider_metric.num_failures,
+                        num_current_usages,
+                        initial_score: p.initial_score(),
+                    };
+                    let score: <<Self as Order>:: J as Job>::DSC =
+                        DynamicScoreCacheable::from_dynamic_provider_metrics(dynamic_metric);
+                    ProviderChoice::Include(score)
+                }
+            });
+            match result {
+                None => break,
+                Some((provider_guard, _)) => {
+                    exclude_providers.insert(provider_guard.guarded_provider.identifier());
+                    helper_guards.push(provider_guard);
+                }
+            }
+        }
+        helper_guards
+    }
+
     fn pardon(
         punished_providers: Vec<ProviderIdentifier>,
         mut provider_metrics: MutexGuard<HashMap<ProviderIdentifier, ProviderMetrics>>,
@@ -358,6 +560,7 @@ pub trait Order where Self: std::marker::Sized + std::clone::Clone + std::cmp::E
                 Entry::Occupied(mut value) => {
                     let value = value.get_mut();
                     value.num_failures -= 1;
+                    value.last_failure = None;
                 },
                 Entry::Vacant(_) => {},
             }
@@ -389,8 +592,16 @@ pub trait Channel where Self: std::marker::Sized + std::fmt::Debug + std::marker
     fn job_state(&mut self) -> &mut JobState<Self::J>;
 }
 
-/// Marker trait.
-pub trait Properties {}
+pub trait Properties {
+    fn failover_budget(&self) -> FailoverBudget {
+        DEFAULT_FAILOVER_BUDGET
+    }
+
+    /// The number of providers that may be used at the same time to fetch a single cacheable order.
+    fn max_parallel_providers(&self) -> usize {
+        1
+    }
+}
 
 #[derive(Debug)]
 pub struct JobState<J> where J: Job {
@@ -400,7 +611,7 @@ pub struct JobState<J> where J: Job {
     /// reason for using Optional (rather than just JS) is that this way, drop() will called on the JS as soon as we
     /// reset the state to None, so that acquired resources are released as soon as possible.
     pub job_resources: Option<J::JS>,
-    pub tx: Sender<FlexoProgress>,
+    pub tx: ProgressSender,
 }
 
 impl <J> JobState<J> where J: Job {
@@ -428,15 +639,57 @@ pub struct JobContext<J> where J: Job {
     provider_guards: Arc<ProviderGuards<J::P>>,
     channels: Arc<Mutex<HashMap<J::P, J::C>>>,
     orders_in_progress: Arc<Mutex<HashSet<J::O>>>,
+    /// Used to subscribe to the progress of orders in progress.
+    progress_receivers: Arc<Mutex<HashMap<J::O, OrderProgress>>>,
     provider_metrics: Arc<Mutex<HashMap<ProviderIdentifier, ProviderMetrics>>>,
     panic_monitor: Vec<Arc<Mutex<i32>>>,
+    fallback_provider: Option<J::P>,
+    thread_pool: ThreadPool,
     pub properties: J::PR,
 }
 
+/// The providers that are used instead of the regular providers: The custom provider is the only provider used
+/// for the order, the fallback provider is used if the order is unavailable at all regular providers.
+pub struct OrderProviders<P> {
+    custom_provider: Option<P>,
+    fallback_provider: Option<P>,
+}
+
+/// Everything a job reports while it tries to fetch an order: The progress for the consumers, the attempt for
+/// the /api/downloads endpoint, and the messages the integration tests wait for.
+pub struct JobReporting {
+    tx_integration_test: Sender<IntegrationTestMessage>,
+    tx_progress: ProgressSender,
+    attempt: Arc<Mutex<Attempt>>,
+}
+
+/// The provider that is currently used to fetch an order.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct Attempt {
+    pub provider: Option<ProviderIdentifier>,
+    /// Starts at 1 and is incremented each time the job switches to another provider.
+    pub num_attempt: u32,
+}
+
+struct OrderProgress {
+    receiver: ProgressReceiver,
+    attempt: Arc<Mutex<Attempt>>,
+}
+
+/// The state of an order in progress, as returned by JobContext::orders_in_progress_status.
+#[derive(Debug)]
+pub struct OrderStatus<O> {
+    pub order: O,
+    pub attempt: Attempt,
+    pub progress: ProgressSnapshot,
+}
+
 #[derive(PartialEq, Eq, Hash, Clone, Debug, Copy, Default, Serialize)]
 pub struct ProviderMetrics {
     pub num_usages: u32,
     pub num_failures: u32,
+    #[serde(skip)]
+    pub last_failure: Option<Instant>,
 }
 
 impl <J> JobContext<J> where J: Job {
@@ -447,11 +700,71 @@ impl <J> JobContext<J> where J: Job {
     pub fn reset_provider_metrics(&mut self) {
         self.provider_metrics.lock().unwrap().clear();
     }
+
+    /// Removes all providers for which the given function returns false. Jobs that are already running are not
+    /// affected. Returns the number of removed providers, or None if no provider would remain, in which case no
+    /// provider is removed.
+    pub fn retain_providers<F>(&mut self, f: F) -> Option<usize> where F: Fn(&J::P) -> bool {
+        let provider_guards = self.provider_guards.retain(f);
+        if provider_guards.is_empty() {
+            return None;
+        }
+        let num_removed = self.provider_guards.len() - provider_guards.len();
+        self.provider_guards = Arc::new(provider_guards);
+        Some(num_removed)
+    }
+
+    /// Runs the given function with the orders currently in progress. No order can be scheduled while the function
+    /// runs, which allows to modify cached files without interfering with downloads.
+    pub fn with_orders_in_progress<F, T>(&self, f: F) -> T where F: FnOnce(&HashSet<J::O>) -> T {
+        let orders_in_progress = self.orders_in_progress.lock().unwrap();
+        f(&orders_in_progress)
+    }
+
+    /// Returns the state of all orders in progress. Orders that have not started yet are included with an empty
+    /// attempt and progress.
+    pub fn orders_in_progress_status(&self) -> Vec<OrderStatus<J::O>> {
+        let orders = self.orders_in_progress.lock().unwrap().iter().cloned().collect::<Vec<_>>();
+        let order_progress = {
+            let progress_receivers = self.progress_receivers.lock().unwrap();
+            orders.iter()
+                .map(|order| progress_receivers.get(order)
+                    .map(|p| (p.receiver.subscribe(), Arc::clone(&p.attempt))))
+                .collect::<Vec<_>>()
+        };
+        orders.into_iter().zip(order_progress).map(|(order, progress)| match progress {
+            None => OrderStatus { order, attempt: Attempt::default(), progress: ProgressSnapshot::default() },
+            Some((receiver, attempt)) => OrderStatus {
+                order,
+                attempt: attempt.lock().unwrap().clone(),
+                progress: receiver.snapshot(),
+            },
+        }).collect()
+    }
+
+    /// Closes all channels that are currently not in use, so that subsequent jobs establish new channels.
+    pub fn reset_channels(&mut self) {
+        self.channels.lock().unwrap().clear();
+    }
+}
+/// Used to wait for a job that runs in the thread pool.
+pub struct JobHandle<T> {
+    rx_outcome: Receiver<T>,
 }
+
+impl <T> JobHandle<T> {
+    /// Waits for the job to finish. Returns an error if the job has panicked.
+    pub fn join(self) -> thread::Result<T> {
+        self.rx_outcome.recv().map_err(|_| {
+            Box::new("The job has terminated without an outcome") as Box<dyn std::any::Any + Send>
+        })
+    }
+}
+
 pub struct ScheduledItem<J> where J: Job {
-    pub join_handle: JoinHandle<JobOutcome<J>>,
+    pub join_handle: JobHandle<JobOutcome<J>>,
     pub rx_integration_test: Receiver<IntegrationTestMessage>,
-    pub rx_progress: Receiver<FlexoProgress>,
+    pub rx_progress: ProgressReceiver,
 }
 
 pub enum ScheduleOutcome<J> where J: Job {
@@ -463,6 +776,8 @@ pub enum ScheduleOutcome<J> where J: Job {
     Cached,
     /// the order cannot be cached
     Uncacheable(ProviderGuard<J::P>),
+    /// All threads of the thread pool are busy and the queue is full.
+    Overloaded,
 }
 
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
@@ -481,6 +796,10 @@ pub enum FlexoProgress {
     Progress(u64),
     Completed,
     OrderError,
+    /// The job has waited in the queue of the thread pool for too long and was not run.
+    Overloaded,
+    /// The job has terminated without completing the order.
+    Failed,
 }
 
 #[derive(PartialEq, Eq, Hash, Clone, Debug, Copy)]
@@ -502,12 +821,32 @@ impl <J> JobContext<J> where J: Job {
             provider_guards,
             channels,
             orders_in_progress,
+            progress_receivers: Arc::new(Mutex::new(HashMap::new())),
             provider_metrics,
             panic_monitor: thread_mutexes,
+            fallback_provider: None,
+            thread_pool: ThreadPool::new("job", DEFAULT_JOB_THREAD_POOL_CONFIG),
             properties,
         }
     }
 
+    /// Sets a provider that is used as a last resort, after an order was unavailable at all other providers.
+    pub fn with_fallback_provider(mut self, fallback_provider: J::P) -> Self {
+        self.fallback_provider = Some(fallback_provider);
+        self
+    }
+
+    /// Sets the thread pool in which all jobs are run.
+    pub fn with_thread_pool(mut self, thread_pool: ThreadPool) -> Self {
+        self.thread_pool = thread_pool;
+        self
+    }
+
+    /// Returns a receiver for the progress messages of the given order, if the order is in progress.
+    pub fn subscribe(&self, order: &J::O) -> Option<ProgressReceiver> {
+        self.progress_receivers.lock().unwrap().get(order).map(|p| p.receiver.subscribe())
+    }
+
     fn check_duplicates(providers: &[J::P]) {
         let mut identifiers: HashSet<ProviderIdentifier> = HashSet::new();
         for p in providers.iter() {
@@ -604,28 +943,39 @@ impl <J> JobContext<J> where J: Job {
         self.panic_monitor.push(mutex);
 
         let (tx_integration_test, rx_integration_test) = unbounded();
-        let (tx_progress, rx_progress) = unbounded::<FlexoProgress>();
+        let (tx_progress, rx_progress) = progress::channel();
+        let tx_progress_cloned = tx_progress.clone();
+        let attempt = Arc::new(Mutex::new(Attempt::default()));
+        let order_progress = OrderProgress { receiver: rx_progress.subscribe(), attempt: Arc::clone(&attempt) };
+        self.progress_receivers.lock().unwrap().insert(order.clone(), order_progress);
+        let progress_receivers = Arc::clone(&self.progress_receivers);
+        let progress_receivers_cloned = Arc::clone(&self.progress_receivers);
         let channels_cloned = Arc::clone(&self.channels);
         let mut provider_metrics_cloned = Arc::clone(&self.provider_metrics);
         let order_states = Arc::clone(&self.orders_in_progress);
         let provider_guards = Arc::clone(&self.provider_guards);
         let order_cloned = order.clone();
         let properties = self.properties.clone();
+        let fallback_provider = self.fallback_provider.clone();
+        let (tx_outcome, rx_outcome) = bounded::<JobOutcome<J>>(1);
+        let order_states_cloned = Arc::clone(&order_states);
+        let order_expired = order.clone();
+        let order_rejected = order.clone();
 
-        let thread = thread::spawn(move || {
+        let run_job = move || {
             let _lock = mutex_cloned.lock().unwrap();
             let order: <J as Job>::O = order.clone();
+            let order_providers = OrderProviders { custom_provider, fallback_provider };
+            let reporting = JobReporting { tx_integration_test, tx_progress, attempt };
             let result = order.try_until_success(
                 provider_guards,
                 &mut provider_metrics_cloned,
-                custom_provider,
+                order_providers,
                 channels_cloned.clone(),
-                tx_integration_test,
-                tx_progress,
+                reporting,
                 properties,
             );
-            order_states.lock().unwrap().remove(&order_cloned);
-            match result {
+            let outcome = match result {
                 JobResult::Complete(mut complete_job) => {
                     complete_job.channel.job_state().release_job_resources();
                     let mut channels_cloned = channels_cloned.lock().unwrap();
@@ -656,16 +1006,42 @@ impl <J> JobContext<J> where J: Job {
                     let provider_metrics = provider_metrics_cloned.lock().unwrap().clone();
                     JobOutcome::Error(provider_metrics)
                 }
-            }
+            };
+            // The job's resources have been released, so subscribers that arrive too late can rely on the
+            // file being final.
+            progress_receivers.lock().unwrap().remove(&order_cloned);
+            order_states.lock().unwrap().remove(&order_cloned);
+            outcome
+        };
+        let tx_progress_expired = tx_progress_cloned;
+        let execute_result = self.thread_pool.execute(move |task_start| {
+            let outcome = match task_start {
+                TaskStart::InTime => run_job(),
+                TaskStart::Expired => {
+                    warn!("{} has waited too long for a thread to become available.", order_expired.description());
+                    progress_receivers_cloned.lock().unwrap().remove(&order_expired);
+                    order_states_cloned.lock().unwrap().remove(&order_expired);
+                    tx_progress_expired.send(FlexoProgress::Overloaded);
+                    JobOutcome::Error(HashMap::new())
+                }
+            };
+            let _ = tx_outcome.send(outcome);
         });
-
-        ScheduleOutcome::Scheduled(
-            ScheduledItem {
-                join_handle: thread,
-                rx_integration_test,
-                rx_progress,
+        match execute_result {
+            Ok(()) => ScheduleOutcome::Scheduled(
+                ScheduledItem {
+                    join_handle: JobHandle { rx_outcome },
+                    rx_integration_test,
+                    rx_progress,
+                }
+            ),
+            Err(_) => {
+                warn!("Unable to schedule {}: All threads are busy.", order_rejected.description());
+                self.orders_in_progress.lock().unwrap().remove(&order_rejected);
+                self.progress_receivers.lock().unwrap().remove(&order_rejected);
+                ScheduleOutcome::Overloaded
             }
-        )
+        }
     }
 }
 
@@ -10,32 +10,47 @@ use std::fs::File;
 use std::io;
 use std::io::ErrorKind;
 use std::io::prelude::*;
-use std::net::{TcpListener, TcpStream};
+use std::net::{Shutdown, TcpListener, TcpStream};
 use std::os::unix::io::AsRawFd;
 use std::path;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 use std::cmp;
 
-use crossbeam::channel::Receiver;
 use crossbeam::channel::RecvTimeoutError;
 use glob::glob;
 use humantime::format_duration;
 use libc::off64_t;
+use serde::Serialize;
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
 #[cfg(test)]
 use tempfile::tempfile;
 
 use flexo::*;
 use mirror_flexo::*;
-use crate::http_headers::{PayloadOrigin, redirect_header, reply_header_bad_request, reply_header_forbidden, reply_header_internal_server_error, reply_header_not_found, reply_header_partial, reply_header_success};
+use crate::http_headers::{PayloadOrigin, redirect_header, reply_header_bad_request, reply_header_conflict, reply_header_forbidden, reply_header_internal_server_error, reply_header_not_found, reply_header_partial, reply_header_service_unavailable, reply_header_success, reply_header_unauthorized, reply_header_unprocessable_entity};
 
 use crate::mirror_cache::{DemarshallError, TimestampedDownloadProviders};
-use crate::mirror_config::{CustomRepo, MirrorConfig, MirrorSelectionMethod};
+use crate::mirror_config::{ConfigSource, CustomRepo, MirrorConfig, MirrorSelectionMethod};
 use crate::mirror_fetch::{Mirror, MirrorFetchError};
-use crate::mirror_flexo::RequestMethod::Post;
+use crate::mirror_flexo::RequestMethod::{Delete, Post};
+use crate::access_control::AccessControl;
+use flexo::progress::ProgressReceiver;
+use flexo::thread_pool::{PoolOverloaded, TaskStart, ThreadPool};
+use crate::client_limits::TooManyConnections;
+use crate::client_stream::ClientStream;
+use crate::credentials::Credentials;
+use crate::prefetch::Prefetcher;
+use crate::snapshot::SnapshotRequest;
 use crate::str_path::StrPath;
+use crate::tls::TlsAcceptor;
+use crate::config_reload::{ConfigReloader, ReloadableContext, ReloadReport};
+use crate::cli::Command as CliCommand;
+use crate::mirrorlist::MirrorlistFormat;
+use crate::cache_eviction::EvictionOutcome;
 
 mod mirror_config;
 mod mirror_fetch;
@@ -44,6 +59,29 @@ mod mirror_flexo;
 mod str_path;
 mod fs_utils;
 mod http_headers;
+mod package_version;
+mod prefetch;
+mod snapshot;
+mod client_stream;
+mod tls;
+mod access_control;
+mod bandwidth;
+mod client_limits;
+mod credentials;
+mod curl_handle;
+mod segmented_download;
+mod response_header;
+mod upstream_proxy;
+mod upstream_tls;
+mod upstream_transfers;
+mod config_reload;
+mod config_check;
+mod cli;
+mod cache_stats;
+mod cache_eviction;
+mod mirrorlist;
+#[cfg(test)]
+mod test_fixtures;
 
 // man 2 read: read() (and similar system calls) will transfer at most 0x7ffff000 bytes.
 #[cfg(not(test))]
@@ -55,6 +93,10 @@ const MAX_SENDFILE_COUNT: usize = 128;
 
 const TIMEOUT_RECEIVE_CONTENT_LENGTH: Duration = Duration::from_secs(7);
 
+// The file size is checked at least this often while serving a file that is still being downloaded, even without
+// any progress being reported.
+const TIMEOUT_PROGRESS: Duration = Duration::from_secs(1);
+
 fn main() {
     env_logger::builder().format_timestamp_millis().init();
 
@@ -65,11 +107,47 @@ fn main() {
         std::process::exit(1);
     }));
 
-    let properties = mirror_config::load_config();
+    let cli = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    let config_source = ConfigSource::new(cli.config_file);
+    match cli.command {
+        CliCommand::Serve => serve(config_source),
+        CliCommand::RankMirrors(format) => rank_mirrors(&config_source, format),
+        CliCommand::CacheStats => print_cache_stats(&config_source),
+        CliCommand::CachePurge => purge_cache_now(&config_source),
+        CliCommand::CheckConfig => std::process::exit(config_check::check_config_command(&config_source)),
+        CliCommand::Version => println!("flexo {}", env!("CARGO_PKG_VERSION")),
+        CliCommand::Help => println!("{}", cli::USAGE),
+    }
+}
+
+/// Loads and prepares the configuration, or exits if the configuration is invalid.
+fn load_config_or_exit(config_source: &ConfigSource) -> MirrorConfig {
+    let properties = mirror_config::try_load_config(config_source).map_err(|e| e.to_string())
+        .and_then(|properties| config_reload::prepare_config(properties, None));
+    match properties {
+        Ok(p) => p,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn serve(config_source: ConfigSource) {
+    let properties = load_config_or_exit(&config_source);
     debug!("The following settings were fetched from the TOML file or environment variables: {:#?}", &properties);
     inspect_and_initialize_cache(&properties);
     match properties.low_speed_limit() {
         None => {}
+        Some(_) if properties.upstream_bandwidth_limiter.is_limited() => {
+            warn!("The setting low_speed_limit is ignored because the upstream bandwidth is limited.");
+        }
         Some(limit) => {
             info!("Will switch mirror if download speed falls below {}/s", size_to_human_readable(limit.into()));
         }
@@ -87,26 +165,178 @@ fn main() {
     let listen_ip_address =
         job_context.lock().unwrap().properties.listen_ip_address.clone().unwrap_or_else(|| "0.0.0.0".to_owned());
     debug!("Listen on address {}", listen_ip_address);
+    let listener = bind(&listen_ip_address, port);
+    // Synchronize file system access: We only want one cache purging process running at any given time.
+    let cache_purge_mutex = Arc::new(Mutex::new(()));
+    let prefetcher = properties.prefetch.as_ref()
+        .filter(|prefetch_config| prefetch_config.enabled)
+        .map(|prefetch_config| Prefetcher::start(job_context.clone(), cache_purge_mutex.clone(), prefetch_config));
+    let reloadable_context = match ReloadableContext::from_config(properties.clone(), None) {
+        Ok(c) => c,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let config_reloader = ConfigReloader::new(reloadable_context, job_context.clone(), config_source);
+    let server_context = ServerContext {
+        job_context,
+        config_reloader: config_reloader.clone(),
+        cache_purge_mutex,
+        prefetcher,
+        client_thread_pool: ThreadPool::new("client", properties.client_thread_pool_config()),
+    };
+
+    let mut tls_acceptor_for_sighup = None;
+    if let Some(tls_config) = &properties.tls {
+        let tls_acceptor = match TlsAcceptor::new(tls_config.clone()) {
+            Ok(a) => a,
+            Err(e) => {
+                error!("Unable to load the TLS certificate {} or private key {}: {:?}",
+                       tls_config.certificate_file, tls_config.private_key_file, e);
+                std::process::exit(1);
+            }
+        };
+        tls_acceptor_for_sighup = Some(tls_acceptor.clone());
+        let tls_listener = bind(&listen_ip_address, tls_config.port);
+        info!("Accepting HTTPS connections on port {}", tls_config.port);
+        let server_context = server_context.clone();
+        std::thread::spawn(move || {
+            accept_connections(tls_listener, Some(tls_acceptor), server_context);
+        });
+    }
+    reload_on_sighup(config_reloader, tls_acceptor_for_sighup);
+    accept_connections(listener, None, server_context);
+}
+
+/// State shared by all client connections.
+#[derive(Clone)]
+struct ServerContext {
+    job_context: Arc<Mutex<JobContext<DownloadJob>>>,
+    config_reloader: ConfigReloader,
+    cache_purge_mutex: Arc<Mutex<()>>,
+    prefetcher: Option<Prefetcher>,
+    client_thread_pool: ThreadPool,
+}
+
+fn bind(listen_ip_address: &str, port: u16) -> TcpListener {
     let addr = format!("{}:{}", listen_ip_address, port);
-    let listener = match TcpListener::bind(&addr) {
+    match TcpListener::bind(&addr) {
         Ok(l) => l,
         Err(e) => panic!("Unable to listen on address {}: {:?}", &addr, e),
+    }
+}
+
+fn reload_on_sighup(config_reloader: ConfigReloader, tls_acceptor: Option<TlsAcceptor>) {
+    let mut signals = match Signals::new([SIGHUP]) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Unable to register signal handler, the configuration will not be reloaded on SIGHUP: {:?}", e);
+            return;
+        }
     };
-    // Synchronize file system access: We only want one cache purging process running at any given time.
-    let cache_purge_mutex = Arc::new(Mutex::new(()));
+    std::thread::spawn(move || {
+        for _signal in signals.forever() {
+            info!("Received SIGHUP, reloading the configuration.");
+            log_reload_result(config_reloader.reload());
+            if let Some(tls_acceptor) = &tls_acceptor {
+                info!("Reloading TLS certificate.");
+                tls_acceptor.reload();
+            }
+        }
+    });
+}
+
+fn log_reload_result(result: Result<ReloadReport, String>) {
+    match result {
+        Ok(report) => {
+            info!("The configuration has been reloaded.");
+            if report.num_removed_mirrors > 0 {
+                info!("{} blacklisted mirrors will no longer be used.", report.num_removed_mirrors);
+            }
+            if !report.restart_required.is_empty() {
+                warn!("The following settings have changed, but will only be applied after a restart: {}",
+                      report.restart_required.join(", "));
+            }
+        }
+        Err(e) => error!("Unable to reload the configuration, the current configuration remains in use: {}", e),
+    }
+}
 
+fn accept_connections(listener: TcpListener, tls_acceptor: Option<TlsAcceptor>, server_context: ServerContext) {
     for client_stream in listener.incoming() {
-        let client_stream: TcpStream = client_stream.unwrap();
+        let client_stream: TcpStream = match client_stream {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Unable to accept connection: {:?}", e);
+                continue;
+            }
+        };
+        let reloadable_context = server_context.config_reloader.current();
+        let client_ip_addr = match client_stream.peer_addr() {
+            Ok(addr) if reloadable_context.access_control.is_client_permitted(addr.ip()) => addr.ip(),
+            Ok(addr) => {
+                info!("Rejected connection from {}: The client is not permitted.", addr.ip());
+                let _ = client_stream.shutdown(Shutdown::Both);
+                continue;
+            }
+            Err(e) => {
+                warn!("Unable to determine the address of the client: {:?}", e);
+                continue;
+            }
+        };
         debug!("Established connection with client.");
-        let job_context = job_context.clone();
-        let properties = properties.clone();
+        let ServerContext { job_context, config_reloader, cache_purge_mutex, prefetcher, .. } = server_context.clone();
+        let properties = reloadable_context.properties.clone();
+        let tls_acceptor = tls_acceptor.clone();
         let num_versions_retain = properties.num_versions_retain;
         let cache_directory = properties.cache_directory.clone();
-        debug!("All set, spawning new thread.");
-        let cache_purge_mutex = cache_purge_mutex.clone();
-        std::thread::spawn(move || {
-            debug!("Started new thread.");
-            let cache_tainted_result = serve_client(job_context, client_stream, properties);
+        // Used to inform the client if the connection cannot be served. With TLS, we cannot send anything before
+        // the handshake, so the connection is just closed.
+        let rejection_stream = match tls_acceptor {
+            None => client_stream.try_clone().ok(),
+            Some(_) => None,
+        };
+        debug!("All set, passing connection to the thread pool.");
+        let execute_result = server_context.client_thread_pool.execute(move |task_start| {
+            debug!("Started serving connection.");
+            let mut client_stream = match tls_acceptor {
+                None => ClientStream::plain(client_stream),
+                Some(tls_acceptor) => match tls_acceptor.accept(client_stream) {
+                    Ok(s) => ClientStream::tls(s),
+                    Err(e) => {
+                        info!("TLS handshake with client failed: {:?}", e);
+                        return;
+                    }
+                },
+            };
+            if task_start == TaskStart::Expired {
+                warn!("Rejected connection from {}: Waited too long for a thread to become available.",
+                      client_ip_addr);
+                let _ = serve_503_header(&mut client_stream);
+                client_stream.shutdown();
+                return;
+            }
+            // Keep the slot until the connection is closed, so that the connection is counted.
+            let _client_slot = match reloadable_context.client_limits.acquire(client_ip_addr) {
+                Ok(slot) => {
+                    client_stream.set_throttle(slot.throttle.clone());
+                    slot
+                }
+                Err(TooManyConnections) => {
+                    info!("Rejected connection from {}: Maximum number of connections exceeded.", client_ip_addr);
+                    let _ = serve_503_header(&mut client_stream);
+                    client_stream.shutdown();
+                    return;
+                }
+            };
+            let admin_access = AdminAccess {
+                access_control: &reloadable_context.access_control,
+                credentials: reloadable_context.admin_credentials.as_ref(),
+                config_reloader: &config_reloader,
+            };
+            let cache_tainted_result =
+                serve_client(job_context, client_stream, properties, prefetcher.as_ref(), &admin_access);
             match (cache_tainted_result, num_versions_retain) {
                 (Ok(true), Some(0)) => {}
                 (Ok(true), Some(v)) => {
@@ -125,6 +355,13 @@ fn main() {
                 }
             }
         });
+        if let Err(PoolOverloaded) = execute_result {
+            warn!("Rejected connection from {}: All threads are busy and the queue is full.", client_ip_addr);
+            if let Some(mut stream) = rejection_stream {
+                let _ = stream.write_all(reply_header_service_unavailable().as_bytes());
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
     }
 }
 
@@ -156,6 +393,93 @@ fn purge_cache(directory: &str, num_versions_retain: u32) {
     }
 }
 
+/// Runs the latency tests on all mirrors that match the [mirrors_auto] settings and prints the mirrors from best to
+/// worst. In contrast to the latency tests at startup, previous results are neither used nor overwritten.
+fn rank_mirrors(config_source: &ConfigSource, format: MirrorlistFormat) {
+    let properties = load_config_or_exit(config_source);
+    let mirrors_auto = match &properties.mirrors_auto {
+        Some(mirrors_auto) => mirrors_auto.clone(),
+        None => {
+            eprintln!("The [mirrors_auto] section is required to run the latency tests.");
+            std::process::exit(1);
+        }
+    };
+    let mirrors = match fetch_mirrors(&properties) {
+        Ok(mirrors) => mirrors,
+        Err(e) => {
+            eprintln!("Unable to fetch the mirrors from {}: {:?}", mirrors_auto.mirrors_status_json_endpoint, e);
+            std::process::exit(1);
+        }
+    };
+    let country_filter = allowed_countries_filter(&properties);
+    let providers = rated_providers_retry(mirrors, mirrors_auto, &country_filter, Limit::NoLimit, &properties);
+    if providers.is_empty() {
+        eprintln!("No remote mirrors match the selected criteria.");
+        std::process::exit(1);
+    }
+    let current_primary_mirror = mirror_cache::fetch_download_providers(&properties).ok()
+        .and_then(|cached| cached.download_providers.into_iter().next())
+        .map(|provider| provider.uri);
+    let timestamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    print!("{}", mirrorlist::format_mirrorlist(&providers, current_primary_mirror.as_deref(), format, &timestamp));
+}
+
+fn print_cache_stats(config_source: &ConfigSource) {
+    let properties = load_config_or_exit(config_source);
+    let stats = match cache_stats::cache_stats(Path::new(&properties.cache_directory)) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Unable to read the cache directory {}: {}", properties.cache_directory, e);
+            std::process::exit(1);
+        }
+    };
+    let width = stats.directories.keys().map(|directory| directory.len()).max().unwrap_or(0).max(5);
+    for (directory, directory_stats) in &stats.directories {
+        println!("{:width$}  {:>7} files  {:>10}", directory, directory_stats.num_files,
+                 size_to_human_readable(directory_stats.size), width = width);
+    }
+    println!("{:width$}  {:>7} files  {:>10}", "Total", stats.num_files, size_to_human_readable(stats.size),
+             width = width);
+    if !stats.incomplete_files.is_empty() {
+        println!("{} files are incomplete, because they are still being downloaded or their download was aborted.",
+                 stats.incomplete_files.len());
+    }
+    if !stats.orphaned_cfs_files.is_empty() {
+        println!("{} cfs files belong to files that no longer exist.", stats.orphaned_cfs_files.len());
+    }
+}
+
+/// Purges the cache in the same way as after a download, e.g. from a cron job.
+fn purge_cache_now(config_source: &ConfigSource) {
+    let properties = load_config_or_exit(config_source);
+    let listen_ip_address = properties.listen_ip_address.as_deref().unwrap_or("0.0.0.0");
+    let tls_port = properties.tls.as_ref().map(|tls_config| tls_config.port);
+    for port in std::iter::once(properties.port).chain(tls_port) {
+        if let Err(e) = check_port_available(listen_ip_address, port) {
+            // Files could be removed while they are being downloaded or served to clients.
+            eprintln!("Flexo may be running, since port {} cannot be bound: {}. Flexo purges the cache after each \
+                download while it is running, so stop Flexo before running this command.", port, e);
+            std::process::exit(1);
+        }
+    }
+    match properties.num_versions_retain {
+        None | Some(0) => {
+            eprintln!("Nothing to purge: num_versions_retain is not set to a positive number.");
+            std::process::exit(1);
+        }
+        Some(v) => {
+            purge_cache(&properties.cache_directory, v);
+            purge_cfs_files(&properties.cache_directory);
+        }
+    }
+}
+
+/// Returns an error if Flexo would be unable to bind the given port. Any error is returned, not only "address in use":
+/// For example, an unprivileged user cannot tell whether Flexo is running on a privileged port.
+fn check_port_available(listen_ip_address: &str, port: u16) -> std::io::Result<()> {
+    TcpListener::bind((listen_ip_address, port)).map(drop)
+}
+
 fn purge_cfs_files(directory: &str) {
     for glob_result in glob(&format!("{}/**/.*.cfs", directory)).unwrap() {
         match &glob_result {
@@ -227,6 +551,250 @@ fn permitted_path(path: &Path) -> bool {
     path.components().all(|c| matches!(c, path::Component::Normal(_) | path::Component::RootDir))
 }
 
+/// Admin endpoints are used to inspect or change Flexo's state, as opposed to downloading files.
+fn is_admin_request(request: &Request) -> bool {
+    matches!(request.path.to_str(), "metrics" | "reset-metrics" | "prefetch" | "reload-config")
+        || request.path.to_str().starts_with("api/")
+}
+
+/// A request to one of the /api/cache endpoints.
+#[derive(Debug, PartialEq, Eq)]
+enum CacheApiRequest {
+    /// GET /api/cache
+    Stats,
+    /// GET or DELETE /api/cache?package=NAME
+    Package(String),
+    /// GET or DELETE /api/cache?pattern=PATTERN, e.g. core/os/x86_64/glibc-*
+    Pattern(String),
+    /// GET or DELETE /api/cache/PATH
+    File(String),
+}
+
+fn cache_api_request(request: &Request) -> Option<CacheApiRequest> {
+    let (path, query) = match request.path.to_str().split_once('?') {
+        None => (request.path.to_str(), None),
+        Some((path, query)) => (path, Some(query)),
+    };
+    let parameter = |name: &str| query.and_then(|query| {
+        query.split('&').find_map(|parameter| parameter.strip_prefix(name).and_then(|p| p.strip_prefix('=')))
+    }).map(percent_decode);
+    match (path, parameter("package"), parameter("pattern")) {
+        ("api/cache", Some(package), _) => Some(CacheApiRequest::Package(package)),
+        ("api/cache", None, Some(pattern)) => Some(CacheApiRequest::Pattern(pattern)),
+        ("api/cache", None, None) => Some(CacheApiRequest::Stats),
+        _ => path.strip_prefix("api/cache/").map(|path| CacheApiRequest::File(path.to_owned())),
+    }
+}
+
+/// Decodes %XX sequences, e.g. "glibc-%2A" to "glibc-*". Invalid sequences are kept as they are. Unlike in HTML
+/// forms, "+" is not decoded to a space, since package names such as "libc++" contain "+", but never spaces.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes.get(i + 1..i + 3)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Returns the cached files selected by the request, relative to the cache directory, or None if the request does not
+/// select any files.
+fn selected_cached_files(
+    cache_directory: &Path,
+    cache_api_request: &CacheApiRequest,
+) -> io::Result<Option<Vec<String>>> {
+    let files = match cache_api_request {
+        CacheApiRequest::Stats => return Ok(None),
+        CacheApiRequest::Package(name) => {
+            cache_stats::find_package(cache_directory, name)?.into_iter().map(|f| f.path).collect()
+        }
+        CacheApiRequest::Pattern(pattern) => match glob::Pattern::new(pattern) {
+            Ok(pattern) => cache_eviction::matching_files(cache_directory, &pattern)?,
+            Err(e) => {
+                info!("Invalid pattern {:?}: {}", pattern, e);
+                return Ok(None);
+            }
+        },
+        CacheApiRequest::File(path) => {
+            cache_stats::cached_file(cache_directory, path)?.into_iter().map(|f| f.path).collect()
+        }
+    };
+    Ok(Some(files))
+}
+
+fn serve_cache_api_request(
+    client_stream: &mut ClientStream,
+    properties: &MirrorConfig,
+    cache_api_request: CacheApiRequest,
+) -> io::Result<()> {
+    let cache_directory = Path::new(&properties.cache_directory);
+    let serialized = match cache_api_request {
+        CacheApiRequest::Stats => serde_json::to_string_pretty(&cache_stats::cache_stats(cache_directory)?),
+        CacheApiRequest::Package(name) => {
+            serde_json::to_string_pretty(&cache_stats::find_package(cache_directory, &name)?)
+        }
+        CacheApiRequest::Pattern(_) => {
+            let paths = match selected_cached_files(cache_directory, &cache_api_request)? {
+                None => {
+                    info!("Invalid cache request: Serve 400");
+                    return serve_400_header(client_stream);
+                }
+                Some(paths) => paths,
+            };
+            let mut cached_files = Vec::new();
+            for path in paths {
+                cached_files.extend(cache_stats::cached_file(cache_directory, &path)?);
+            }
+            serde_json::to_string_pretty(&cached_files)
+        }
+        CacheApiRequest::File(path) => match cache_stats::cached_file(cache_directory, &path)? {
+            None => {
+                info!("File {:?} is not cached: Serve 404", path);
+                return serve_404_header(client_stream);
+            }
+            Some(cached_file) => serde_json::to_string_pretty(&cached_file),
+        },
+    }.unwrap();
+    serve_200_ok_body(client_stream, serialized.as_bytes())
+}
+
+/// An order in progress, as listed by the /api/downloads endpoint.
+#[derive(Serialize, Debug)]
+struct DownloadInProgress {
+    path: String,
+    provider: Option<String>,
+    num_attempt: u32,
+    size_written: Option<u64>,
+    expected_size: Option<u64>,
+    bytes_per_second: Option<u64>,
+    num_attached_clients: usize,
+}
+
+fn downloads_in_progress(
+    job_context: &Mutex<JobContext<DownloadJob>>,
+    properties: &MirrorConfig,
+) -> Vec<DownloadInProgress> {
+    let orders_in_progress_status = job_context.lock().unwrap().orders_in_progress_status();
+    let mut downloads = orders_in_progress_status.into_iter().map(|status| {
+        // The file of uncacheable orders has no cfs file, and its name is only known to the job.
+        let filepath = match status.order.cacheability {
+            Cacheability::NonCacheable(_) => None,
+            _ => Some(status.order.filepath(properties)),
+        };
+        let size_written = status.progress.size_written.or_else(|| {
+            filepath.as_ref().and_then(|path| path.metadata().ok()).map(|metadata| metadata.len())
+        });
+        let expected_size = status.progress.job_size.or_else(|| {
+            filepath.as_ref().and_then(|path| get_complete_size_from_cfs_file(path))
+        });
+        DownloadInProgress {
+            path: status.order.requested_path.to_str().to_owned(),
+            provider: status.attempt.provider.map(|provider| provider.identifier),
+            num_attempt: status.attempt.num_attempt,
+            size_written,
+            expected_size,
+            bytes_per_second: status.progress.bytes_per_second,
+            num_attached_clients: status.progress.num_attached_clients,
+        }
+    }).collect::<Vec<_>>();
+    downloads.sort_by(|a, b| a.path.cmp(&b.path));
+    downloads
+}
+
+/// Removes the selected files from the cache, unless any of them is currently being downloaded.
+fn serve_cache_eviction_request(
+    client_stream: &mut ClientStream,
+    job_context: &Mutex<JobContext<DownloadJob>>,
+    properties: &MirrorConfig,
+    cache_api_request: CacheApiRequest,
+) -> io::Result<()> {
+    let cache_directory = Path::new(&properties.cache_directory);
+    let paths = match selected_cached_files(cache_directory, &cache_api_request)? {
+        None => {
+            info!("Invalid cache eviction request: Serve 400");
+            return serve_400_header(client_stream);
+        }
+        Some(paths) if paths.is_empty() => {
+            info!("No cached files match {:?}: Serve 404", cache_api_request);
+            return serve_404_header(client_stream);
+        }
+        Some(paths) => paths,
+    };
+    let outcome = job_context.lock().unwrap().with_orders_in_progress(|orders_in_progress| {
+        cache_eviction::evict_unless_in_progress(cache_directory, &paths, |path| {
+            orders_in_progress.iter().any(|order| {
+                order.cacheability == Cacheability::Cacheable && order.requested_path.to_str() == path
+            })
+        })
+    })?;
+    match outcome {
+        EvictionOutcome::Evicted(evicted) => {
+            info!("Removed from the cache: {:?}", evicted);
+            let serialized = serde_json::to_string_pretty(&serde_json::json!({ "evicted": evicted })).unwrap();
+            serve_200_ok_body(client_stream, serialized.as_bytes())
+        }
+        EvictionOutcome::InProgress(in_progress) => {
+            info!("Files are being downloaded and cannot be removed: {:?}", in_progress);
+            let serialized = serde_json::to_string_pretty(&serde_json::json!({ "in_progress": in_progress })).unwrap();
+            client_stream.write_all(reply_header_conflict(serialized.len() as u64).as_bytes())?;
+            client_stream.write_all(serialized.as_bytes())
+        }
+    }
+}
+
+/// Everything required to decide whether a client may use the admin endpoints.
+struct AdminAccess<'a> {
+    access_control: &'a AccessControl,
+    credentials: Option<&'a Credentials>,
+    config_reloader: &'a ConfigReloader,
+}
+
+enum AdminAccessDenial {
+    ClientNotPermitted,
+    MissingCredentials(&'static str),
+    InvalidCredentials,
+}
+
+/// Returns None if the request can be served, or the reason why it must be denied otherwise.
+fn admin_access_denial(
+    request: &Request,
+    client_stream: &ClientStream,
+    admin_access: &AdminAccess,
+) -> Option<AdminAccessDenial> {
+    if !is_admin_request(request) {
+        return None;
+    }
+    let is_client_permitted = match client_stream.tcp_stream().peer_addr() {
+        Ok(addr) => admin_access.access_control.is_admin_permitted(addr.ip()),
+        Err(e) => {
+            warn!("Unable to determine the address of the client: {:?}", e);
+            false
+        }
+    };
+    if !is_client_permitted {
+        return Some(AdminAccessDenial::ClientNotPermitted);
+    }
+    match (admin_access.credentials, &request.authorization) {
+        (None, _) => None,
+        (Some(credentials), None) => Some(AdminAccessDenial::MissingCredentials(credentials.challenge())),
+        (Some(credentials), Some(authorization)) if credentials.matches(authorization) => None,
+        (Some(_), Some(_)) => Some(AdminAccessDenial::InvalidCredentials),
+    }
+}
+
 fn valid_path(path: &Path) -> bool {
     match path.components().last() {
         Some(path::Component::Normal(_)) => true,
@@ -236,12 +804,32 @@ fn valid_path(path: &Path) -> bool {
 
 fn serve_request(
     job_context: Arc<Mutex<JobContext<DownloadJob>>>,
-    client_stream: &mut TcpStream,
+    client_stream: &mut ClientStream,
     properties: MirrorConfig,
     get_request: Request,
+    prefetcher: Option<&Prefetcher>,
+    admin_access: &AdminAccess,
 ) -> Result<PayloadOrigin, ClientError> {
-    let (custom_provider, request) =
-        custom_provider_from_request(get_request.clone(), &properties.custom_repo.as_ref().unwrap_or(&vec![]));
+    let snapshot_request = match properties.snapshot_config() {
+        None => None,
+        Some(snapshot_config) => SnapshotRequest::from_path(&get_request.path)
+            .map(|snapshot_request| (snapshot_config, snapshot_request)),
+    };
+    let (custom_provider, request) = match &snapshot_request {
+        None => {
+            custom_provider_from_request(get_request.clone(), &properties.custom_repo.as_ref().unwrap_or(&vec![]))
+        }
+        Some((snapshot_config, snapshot_request)) => {
+            info!("Request {:?} will be served from the snapshot of {}", get_request.path.to_str(),
+                  snapshot_request.date);
+            let provider = snapshot_request.archive_provider(snapshot_config);
+            let new_get_request = Request {
+                path: snapshot_request.path.clone(),
+                ..get_request.clone()
+            };
+            (Some(provider), new_get_request)
+        }
+    };
     if !permitted_path(&request.path.as_ref()) {
         info!("Forbidden path: Serve 403");
         serve_403_header(client_stream)?;
@@ -250,6 +838,22 @@ fn serve_request(
         info!("Invalid path: Serve 400");
         serve_400_header(client_stream)?;
         Ok(PayloadOrigin::NoPayload)
+    } else if let Some(denial) = admin_access_denial(&request, client_stream, admin_access) {
+        match denial {
+            AdminAccessDenial::ClientNotPermitted => {
+                info!("Client is not permitted to use admin endpoints: Serve 403");
+                serve_403_header(client_stream)?;
+            }
+            AdminAccessDenial::MissingCredentials(challenge) => {
+                info!("Admin endpoint requested without credentials: Serve 401");
+                serve_401_header(client_stream, challenge)?;
+            }
+            AdminAccessDenial::InvalidCredentials => {
+                warn!("Admin endpoint requested with invalid credentials: Serve 403");
+                serve_403_header(client_stream)?;
+            }
+        }
+        Ok(PayloadOrigin::NoPayload)
     } else if request.path.to_str() == "status" {
         serve_200_ok_empty(client_stream)?;
         Ok(PayloadOrigin::NoPayload)
@@ -269,28 +873,93 @@ fn serve_request(
         }
         serve_200_ok_empty(client_stream)?;
         Ok(PayloadOrigin::NoPayload)
+    } else if request.path.to_str() == "reload-config" && request.method == Post {
+        let result = admin_access.config_reloader.reload();
+        let serialized = match &result {
+            Ok(report) => serde_json::to_string_pretty(report).unwrap(),
+            Err(e) => serde_json::to_string_pretty(&serde_json::json!({ "error": e })).unwrap(),
+        };
+        match result {
+            Ok(_) => serve_200_ok_body(client_stream, serialized.as_bytes())?,
+            Err(_) => {
+                client_stream.write_all(reply_header_unprocessable_entity(serialized.len() as u64).as_bytes())?;
+                client_stream.write_all(serialized.as_bytes())?;
+            }
+        }
+        log_reload_result(result);
+        Ok(PayloadOrigin::NoPayload)
+    } else if request.path.to_str() == "api/downloads" {
+        let downloads = downloads_in_progress(&job_context, &properties);
+        serve_200_ok_body(client_stream, serde_json::to_string_pretty(&downloads).unwrap().as_bytes())?;
+        Ok(PayloadOrigin::NoPayload)
+    } else if let Some(cache_api_request) = cache_api_request(&request) {
+        match request.method {
+            Delete => serve_cache_eviction_request(client_stream, &job_context, &properties, cache_api_request)?,
+            _ => serve_cache_api_request(client_stream, &properties, cache_api_request)?,
+        }
+        Ok(PayloadOrigin::NoPayload)
+    } else if request.path.to_str() == "prefetch" && request.method == Post {
+        match prefetcher {
+            None => {
+                info!("Prefetching is disabled: Serve 404");
+                serve_404_header(client_stream)?;
+            }
+            Some(prefetcher) => {
+                prefetcher.run_now();
+                serve_200_ok_empty(client_stream)?;
+            }
+        }
+        Ok(PayloadOrigin::NoPayload)
     } else {
-        let order = DownloadOrder::new(request.path);
+        let order = match &snapshot_request {
+            None => DownloadOrder::new(request.path),
+            Some((_, snapshot_request)) => snapshot_request.order(),
+        };
         debug!("Schedule new job");
-        let result = job_context.lock().unwrap().try_schedule(order.clone(), custom_provider, request.resume_from);
+        let result = job_context.lock().unwrap()
+            .try_schedule(order.clone(), custom_provider.clone(), request.resume_from);
         match result {
             ScheduleOutcome::AlreadyInProgress => {
                 debug!("Job is already in progress");
+                let progress = job_context.lock().unwrap().subscribe(&order);
                 let path = order.filepath(&properties);
                 let complete_filesize: u64 = try_complete_filesize_from_path(&path)?;
                 let content_length = complete_filesize - request.resume_from.unwrap_or(0);
                 let file = File::open(&path)?;
-                serve_from_growing_file(file, content_length, request.resume_from, client_stream)?;
+                serve_from_growing_file(
+                    file, content_length, request.resume_from, client_stream, progress.as_ref(), &properties
+                )?;
                 Ok(PayloadOrigin::RemoteMirror)
             }
             ScheduleOutcome::Scheduled(ScheduledItem { rx_progress, .. }) => {
                 // TODO this branch is also executed when the server returns 404.
                 debug!("Job was scheduled, will serve from growing file");
-                match receive_content_length(rx_progress) {
+                match receive_content_length(&rx_progress, content_length_timeout(&properties)) {
                     Ok(ContentLengthResult::ContentLength(content_length)) => {
                         info!("Content length of path \"{}\" is {}", get_request.path.to_str(), content_length);
                         let file = File::open(order.filepath(&properties))?;
-                        serve_from_growing_file(file, content_length, request.resume_from, client_stream)?;
+                        serve_from_growing_file(
+                            file, content_length, request.resume_from, client_stream, Some(&rx_progress), &properties
+                        )?;
+                        let is_database = order.requested_path.to_str().ends_with(".db");
+                        let is_official_database = is_database && custom_provider.is_none() &&
+                            matches!(order.cacheability, Cacheability::NonCacheable(_));
+                        if let (Some(snapshot_config), true) = (properties.snapshot_config(), is_official_database) {
+                            let database_path = order.filepath(&properties);
+                            match snapshot::store_snapshot(snapshot_config, &database_path, &order.requested_path) {
+                                Ok(path) => debug!("Stored snapshot of database file: {:?}", path),
+                                Err(e) => {
+                                    warn!("Unable to store snapshot of {}: {:?}", order.requested_path.to_str(), e)
+                                }
+                            }
+                        }
+                        if let Some(prefetcher) = prefetcher {
+                            if is_database {
+                                prefetcher.database_refreshed(
+                                    order.filepath(&properties), order.requested_path.clone(), custom_provider
+                                );
+                            }
+                        }
                         Ok(PayloadOrigin::RemoteMirror)
                     }
                     Ok(ContentLengthResult::AlreadyCached) => {
@@ -300,24 +969,46 @@ fn serve_request(
                         Ok(PayloadOrigin::Cache)
                     }
                     Err(ContentLengthError::Unavailable) => {
-                        debug!("Will send 404 reply to client.");
-                        serve_404_header(client_stream)?;
-                        Ok(PayloadOrigin::NoPayload)
+                        let latest_local_snapshot = snapshot_request.as_ref()
+                            .and_then(|(_, snapshot_request)| snapshot_request.latest_local_snapshot(&properties));
+                        match latest_local_snapshot {
+                            None => {
+                                debug!("Will send 404 reply to client.");
+                                serve_404_header(client_stream)?;
+                                Ok(PayloadOrigin::NoPayload)
+                            }
+                            Some(path) => {
+                                // The archive does not have this snapshot (e.g. because it has not been
+                                // synchronized yet), but we do have an older snapshot.
+                                info!("Serve the most recent snapshot available before the requested date: {:?}",
+                                      &path);
+                                let file = File::open(&path)?;
+                                serve_from_complete_file(file, request.resume_from, client_stream)?;
+                                Ok(PayloadOrigin::Cache)
+                            }
+                        }
                     }
                     Err(ContentLengthError::OrderError) => {
                         debug!("Will send 400 reply to client.");
                         serve_400_header(client_stream)?;
                         Ok(PayloadOrigin::NoPayload)
                     }
-                    Err(ContentLengthError::TransmissionError(RecvTimeoutError::Disconnected)) => {
+                    Err(ContentLengthError::Failed) => {
+                        error!("Unable to download the file from any remote mirror.");
+                        serve_500_header(client_stream)?;
+                        Ok(PayloadOrigin::NoPayload)
+                    }
+                    Err(ContentLengthError::Overloaded) => {
+                        info!("Download has not started in time: Serve 503");
+                        serve_503_header(client_stream)?;
+                        Ok(PayloadOrigin::NoPayload)
+                    }
+                    Err(ContentLengthError::Disconnected) => {
                         error!("Remote server has disconnected unexpectedly.");
                         serve_500_header(client_stream)?;
                         Ok(PayloadOrigin::NoPayload)
                     }
-                    Err(ContentLengthError::TransmissionError(RecvTimeoutError::Timeout)) => {
-                        // TODO we should not immediately return 500, and instead try another mirror.
-                        // TODO the problem is that the entire logic about retrying other mirrors is
-                        // inside lib.rs
+                    Err(ContentLengthError::Timeout) => {
                         error!("Timeout: Unable to obtain content length.");
                         serve_500_header(client_stream)?;
                         Ok(PayloadOrigin::NoPayload)
@@ -343,14 +1034,21 @@ fn serve_request(
                 serve_via_redirect(uri_string, client_stream)?;
                 Ok(PayloadOrigin::NoPayload)
             }
+            ScheduleOutcome::Overloaded => {
+                info!("Unable to schedule download: Serve 503");
+                serve_503_header(client_stream)?;
+                Ok(PayloadOrigin::NoPayload)
+            }
         }
     }
 }
 
 fn serve_client(
     job_context: Arc<Mutex<JobContext<DownloadJob>>>,
-    mut client_stream: TcpStream,
+    mut client_stream: ClientStream,
     properties: MirrorConfig,
+    prefetcher: Option<&Prefetcher>,
+    admin_access: &AdminAccess,
 ) -> Result<bool, ClientError> {
     let mut cache_tainted = false;
     // Loop for persistent connections: Will wait for subsequent requests instead of closing immediately.
@@ -365,7 +1063,9 @@ fn serve_client(
                     info!("Received request for path \"{}\". Range start: {}", get_request.path.to_str(), resume_from);
                 }
                 let request_path = get_request.path.clone();
-                match serve_request(job_context.clone(), &mut client_stream, properties.clone(), get_request) {
+                match serve_request(
+                    job_context.clone(), &mut client_stream, properties.clone(), get_request, prefetcher, admin_access
+                ) {
                     Ok(payload_origin) => {
                         let payload_origin_human_readable = match payload_origin {
                             PayloadOrigin::Cache => "CACHE HIT",
@@ -420,10 +1120,14 @@ fn custom_provider_from_request(
                 name: custom_repo.name.clone(),
                 mirror_results: Default::default(),
                 country_code: "Unknown".to_string(),
+                layout: ProviderLayout::Mirror,
+                tls: custom_repo.tls.clone(),
+                credentials: custom_repo.credentials.clone(),
             };
             let new_get_request = Request {
                 resume_from: get_request.resume_from,
                 method: get_request.method,
+                authorization: get_request.authorization,
                 path,
             };
             (Some(provider), new_get_request)
@@ -432,7 +1136,7 @@ fn custom_provider_from_request(
 }
 
 /// Returns Ok if it is save to continue serving requests to this client, or Err otherwise.
-fn handle_client_error(mut client_stream: &mut TcpStream, client_error: ClientError) -> Result<(), ClientError> {
+fn handle_client_error(mut client_stream: &mut ClientStream, client_error: ClientError) -> Result<(), ClientError> {
     let result = match client_error {
         ClientError::Other(kind) if kind == ErrorKind::ConnectionReset => {
             debug!("Socket closed by client.");
@@ -469,11 +1173,11 @@ fn handle_client_error(mut client_stream: &mut TcpStream, client_error: ClientEr
     match result {
         Err(ClientError::Other(ErrorKind::ConnectionReset)) => {
             debug!("Connection reset by client");
-            let _ = client_stream.shutdown(std::net::Shutdown::Both);
+            client_stream.shutdown();
         }
         Err(ref e) => {
             warn!("Closing TCP socket due to error: {:?}", e);
-            let _ = client_stream.shutdown(std::net::Shutdown::Both);
+            client_stream.shutdown();
         }
         Ok(()) => {
             // nothing to do.
@@ -514,7 +1218,27 @@ fn initialize_job_context(properties: MirrorConfig) -> Result<JobContext<Downloa
             providers,
     };
 
-    Ok(JobContext::new(providers, properties))
+    let archive_fallback = properties.archive_fallback.as_ref()
+        .filter(|archive_fallback| archive_fallback.enabled)
+        .map(|archive_fallback| archive_fallback.url().to_owned());
+    let download_thread_pool = properties.download_thread_pool.clone();
+    let job_context = JobContext::new(providers, properties).with_thread_pool(download_thread_pool);
+    match archive_fallback {
+        None => Ok(job_context),
+        Some(url) => {
+            info!("Packages that are unavailable at all mirrors will be fetched from {}", url);
+            let fallback_provider = DownloadProvider {
+                uri: url.clone(),
+                name: url,
+                mirror_results: Default::default(),
+                country_code: "Unknown".to_owned(),
+                layout: ProviderLayout::Archive,
+                tls: None,
+                credentials: None,
+            };
+            Ok(job_context.with_fallback_provider(fallback_provider))
+        }
+    }
 }
 
 fn rated_providers(mirror_config: &MirrorConfig) -> Vec<DownloadProvider> {
@@ -531,46 +1255,55 @@ fn rated_providers(mirror_config: &MirrorConfig) -> Vec<DownloadProvider> {
                 name: uri,
                 mirror_results: default_mirror_result,
                 country_code: "Unknown".to_owned(),
+                layout: ProviderLayout::Mirror,
+                tls: None,
+                credentials: None,
             }
         }).collect()
     }
 }
 
 fn fetch_auto(mirror_config: &MirrorConfig) -> Vec<DownloadProvider> {
+    match fetch_mirrors(mirror_config) {
+        Ok(mirror_urls) =>
+            rated_mirrors(mirror_urls, allowed_countries_filter(mirror_config), &mirror_config),
+        Err(e) => {
+            info!("Unable to fetch mirrors remotely: {:?}\nWill try to fetch them from cache.", e);
+            mirrors_from_cache(&mirror_config)
+        }
+    }
+}
+
+fn allowed_countries_filter(mirror_config: &MirrorConfig) -> CountryFilter {
     let country_codes = mirror_config.mirrors_auto.as_ref()
         .map(|ma| ma.allowed_countries.clone());
-    let country_filter_uncached = match country_codes {
+    match country_codes {
         None =>
             CountryFilter::AllCountries,
         Some(v) if v.is_empty() =>
             CountryFilter::AllCountries,
         Some(v) =>
             CountryFilter::SelectedCountries(v),
-    };
+    }
+}
+
+/// Fetches the mirrors from the JSON endpoint, or from the fallback endpoints if the primary endpoint fails.
+fn fetch_mirrors(mirror_config: &MirrorConfig) -> Result<Vec<Mirror>, MirrorFetchError> {
     let mirrors_auto = mirror_config.mirrors_auto.as_ref().unwrap();
     let mut fallbacks = mirrors_auto.mirrors_status_json_endpoint_fallbacks.iter();
     let primary_endpoint_uri = &mirrors_auto.mirrors_status_json_endpoint;
 
-    let mut result = mirror_fetch::fetch_providers_from_json_endpoint(primary_endpoint_uri);
-    let final_result: Result<Vec<Mirror>, MirrorFetchError> = loop {
+    let mut result = mirror_fetch::fetch_providers_from_json_endpoint(primary_endpoint_uri, mirror_config);
+    loop {
         let maybe_fallback = fallbacks.next();
         match (result.is_err(), maybe_fallback) {
             (true, Some(fallback)) => {
-                result = mirror_fetch::fetch_providers_from_json_endpoint(&fallback);
+                result = mirror_fetch::fetch_providers_from_json_endpoint(&fallback, mirror_config);
             },
             _ => {
                 break result;
             }
         };
-    };
-
-    match final_result {
-        Ok(mirror_urls) =>
-            rated_mirrors(mirror_urls, country_filter_uncached, &mirror_config),
-        Err(e) => {
-            info!("Unable to fetch mirrors remotely: {:?}\nWill try to fetch them from cache.", e);
-            mirrors_from_cache(&mirror_config)
-        }
     }
 }
 
@@ -627,6 +1360,7 @@ fn rated_mirrors(
         mirror_config.mirrors_auto.as_ref().unwrap().clone(),
         &country_filter,
         limit,
+        mirror_config,
     )
 }
 
@@ -676,9 +1410,12 @@ fn mirrors_from_cache(mirror_config: &MirrorConfig) -> Vec<DownloadProvider> {
 
 #[derive(Debug)]
 enum ContentLengthError {
-    TransmissionError(RecvTimeoutError),
+    Disconnected,
     Unavailable,
     OrderError,
+    Overloaded,
+    Failed,
+    Timeout,
 }
 
 enum ContentLengthResult {
@@ -686,9 +1423,14 @@ enum ContentLengthResult {
     AlreadyCached,
 }
 
-fn receive_content_length(rx: Receiver<FlexoProgress>) -> Result<ContentLengthResult, ContentLengthError> {
+fn receive_content_length(
+    rx: &ProgressReceiver,
+    timeout: Duration,
+) -> Result<ContentLengthResult, ContentLengthError> {
+    let deadline = Instant::now() + timeout;
     loop {
-        match rx.recv_timeout(TIMEOUT_RECEIVE_CONTENT_LENGTH) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match rx.recv_timeout(std::cmp::min(remaining, TIMEOUT_RECEIVE_CONTENT_LENGTH)) {
             Ok(FlexoProgress::JobSize(content_length)) => {
                 break Ok(ContentLengthResult::ContentLength(content_length));
             }
@@ -701,10 +1443,24 @@ fn receive_content_length(rx: Receiver<FlexoProgress>) -> Result<ContentLengthRe
             Ok(FlexoProgress::OrderError) => {
                 break Err(ContentLengthError::OrderError);
             }
+            Ok(FlexoProgress::Overloaded) => {
+                break Err(ContentLengthError::Overloaded);
+            }
+            Ok(FlexoProgress::Failed) => {
+                break Err(ContentLengthError::Failed);
+            }
             Ok(msg) => {
                 panic!("Unexpected message: {:?}", msg);
             }
-            Err(e) => break Err(ContentLengthError::TransmissionError(e)),
+            Err(RecvTimeoutError::Timeout) if Instant::now() >= deadline => {
+                break Err(ContentLengthError::Timeout);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                // Remote mirrors that do not send the header in time are skipped by the job, so we keep waiting
+                // until the job has either received the header from some mirror or given up.
+                debug!("Content length not received yet: Keep waiting for the job.");
+            }
+            Err(RecvTimeoutError::Disconnected) => break Err(ContentLengthError::Disconnected),
         }
     }
 }
@@ -729,11 +1485,15 @@ fn try_complete_filesize_from_path(path: &Path) -> Result<u64, FileAttrError> {
     Err(FileAttrError::TimeoutError)
 }
 
+/// Serves a file while it is being downloaded. The client thread is blocked until the file has been served
+/// completely, but it only wakes up when the download makes progress, or after TIMEOUT_PROGRESS.
 fn serve_from_growing_file(
     mut file: File,
     content_length: u64,
     resume_from: Option<u64>,
-    client_stream: &mut TcpStream,
+    client_stream: &mut ClientStream,
+    progress: Option<&ProgressReceiver>,
+    properties: &MirrorConfig,
 ) -> io::Result<()> {
     let header = match resume_from {
         None => reply_header_success(content_length, PayloadOrigin::RemoteMirror),
@@ -741,14 +1501,17 @@ fn serve_from_growing_file(
     };
     client_stream.write_all(header.as_bytes())?;
     debug!("Header was sent to the client.");
+    let _attached_client = progress.map(|progress| progress.attach_client());
     let resume_from = resume_from.unwrap_or(0);
     let mut client_received = resume_from;
     let complete_filesize = content_length + resume_from;
+    let stall_timeout = properties.stall_timeout();
+    let mut last_growth = Instant::now();
     while client_received < complete_filesize {
         let filesize = file.metadata()?.len();
         if filesize > client_received {
-            // TODO note that this while loop runs indefinitely if the file stops growing for whatever reason.
-            let result = send_payload_and_flush(&mut file, filesize, client_received as i64, client_stream);
+            last_growth = Instant::now();
+            let result = client_stream.send_file(&mut file, filesize, client_received as i64);
             match result {
                 Ok(size) => {
                     client_received = size as u64;
@@ -763,40 +1526,79 @@ fn serve_from_growing_file(
                 }
             }
         }
-        if client_received < content_length {
-            std::thread::sleep(std::time::Duration::from_micros(500));
+        if client_received < complete_filesize {
+            if last_growth.elapsed() > stall_timeout {
+                warn!("The file has not grown for {:?}: Abort the connection so that the client can retry.",
+                      stall_timeout);
+                return Err(io::Error::new(ErrorKind::TimedOut, "The download has stalled."));
+            }
+            wait_for_progress(progress, &file, client_received)?;
         }
     }
     debug!("File completely served from growing file.");
     Ok(())
 }
 
-fn serve_404_header(client_stream: &mut TcpStream) -> io::Result<()> {
+/// Waits until the file has grown beyond the given size, or the job has made some other progress.
+fn wait_for_progress(progress: Option<&ProgressReceiver>, file: &File, size: u64) -> io::Result<()> {
+    match progress {
+        // The job has finished and released the file before we could subscribe to it, so the file is final.
+        None => ended_job_progress(file, size),
+        Some(progress) => match progress.recv_timeout(TIMEOUT_PROGRESS) {
+            Ok(FlexoProgress::Failed) => {
+                warn!("The download has failed: Abort the connection so that the client can retry.");
+                Err(io::Error::other("The download has failed."))
+            }
+            Ok(_) | Err(RecvTimeoutError::Timeout) => Ok(()),
+            Err(RecvTimeoutError::Disconnected) => ended_job_progress(file, size),
+        },
+    }
+}
+
+fn ended_job_progress(file: &File, size: u64) -> io::Result<()> {
+    if file.metadata()?.len() > size {
+        Ok(())
+    } else {
+        Err(io::Error::new(ErrorKind::UnexpectedEof, "The job has ended before the file was complete."))
+    }
+}
+
+fn serve_404_header(client_stream: &mut ClientStream) -> io::Result<()> {
     let header = reply_header_not_found();
     client_stream.write_all(header.as_bytes())
 }
 
-fn serve_400_header(client_stream: &mut TcpStream) -> io::Result<()> {
+fn serve_400_header(client_stream: &mut ClientStream) -> io::Result<()> {
     let header = reply_header_bad_request();
     client_stream.write_all(header.as_bytes())
 }
 
-fn serve_500_header(client_stream: &mut TcpStream) -> io::Result<()> {
+fn serve_500_header(client_stream: &mut ClientStream) -> io::Result<()> {
     let header = reply_header_internal_server_error();
     client_stream.write_all(header.as_bytes())
 }
 
-fn serve_403_header(client_stream: &mut TcpStream) -> io::Result<()> {
+fn serve_401_header(client_stream: &mut ClientStream, challenge: &str) -> io::Result<()> {
+    let header = reply_header_unauthorized(challenge);
+    client_stream.write_all(header.as_bytes())
+}
+
+fn serve_403_header(client_stream: &mut ClientStream) -> io::Result<()> {
     let header = reply_header_forbidden();
     client_stream.write_all(header.as_bytes())
 }
 
-fn serve_200_ok_empty(client_stream: &mut TcpStream) -> io::Result<()> {
+fn serve_503_header(client_stream: &mut ClientStream) -> io::Result<()> {
+    let header = reply_header_service_unavailable();
+    client_stream.write_all(header.as_bytes())
+}
+
+fn serve_200_ok_empty(client_stream: &mut ClientStream) -> io::Result<()> {
     let header = reply_header_success(0, PayloadOrigin::NoPayload);
     client_stream.write_all(header.as_bytes())
 }
 
-fn serve_200_ok_body(client_stream: &mut TcpStream, body: &[u8]) -> io::Result<()> {
+fn serve_200_ok_body(client_stream: &mut ClientStream, body: &[u8]) -> io::Result<()> {
     let content_length = body.len() as u64;
     let header = reply_header_success(content_length, PayloadOrigin::NoPayload);
     client_stream.write_all(header.as_bytes())?;
@@ -806,7 +1608,7 @@ fn serve_200_ok_body(client_stream: &mut TcpStream, body: &[u8]) -> io::Result<(
 fn serve_from_complete_file(
     mut file: File,
     resume_from: Option<u64>,
-    client_stream: &mut TcpStream,
+    client_stream: &mut ClientStream,
 ) -> io::Result<i64> {
     let filesize = file.metadata()?.len();
     let content_length = filesize - resume_from.unwrap_or(0);
@@ -816,7 +1618,7 @@ fn serve_from_complete_file(
     };
     client_stream.write_all(header.as_bytes())?;
     let bytes_sent = resume_from.unwrap_or(0) as i64;
-    let result = send_payload_and_flush(&mut file, filesize, bytes_sent, client_stream);
+    let result = client_stream.send_file(&mut file, filesize, bytes_sent);
     match &result {
         Ok(s) => debug!("{} bytes have been transmitted to the client.", s),
         Err(e) => warn!("Error while sending payload: {:?}", e),
@@ -824,27 +1626,12 @@ fn serve_from_complete_file(
     result
 }
 
-fn serve_via_redirect(uri: String, client_stream: &mut TcpStream) -> io::Result<()> {
+fn serve_via_redirect(uri: String, client_stream: &mut ClientStream) -> io::Result<()> {
     debug!("Attempting to serve from {}", &uri);
     let header = redirect_header(&uri, SystemTime::now());
     client_stream.write_all(header.as_bytes())
 }
 
-fn send_payload_and_flush(
-    mut source: &mut File,
-    filesize: u64,
-    bytes_sent: i64,
-    receiver: &mut TcpStream,
-) -> io::Result<i64> {
-    let result = send_payload(&mut source, filesize, bytes_sent, receiver);
-    // Enabling and then disabling the nodelay option results in a flush.
-    // For some reason, receiver.flush() does not have this effect.
-    receiver.set_nodelay(true)?;
-    receiver.set_nodelay(false)?;
-
-    result
-}
-
 fn send_payload<T>(source: &mut File, filesize: u64, bytes_sent: i64, receiver: &mut T) -> io::Result<i64>
     where T: AsRawFd {
     let fd = source.as_raw_fd();
@@ -864,6 +1651,17 @@ fn send_payload<T>(source: &mut File, filesize: u64, bytes_sent: i64, receiver:
     Ok(size)
 }
 
+#[test]
+fn check_port_available_test() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    assert_eq!(ErrorKind::AddrInUse, check_port_available("127.0.0.1", port).unwrap_err().kind());
+    drop(listener);
+    assert!(check_port_available("127.0.0.1", port).is_ok());
+    // Errors other than "address in use" are returned as well, e.g. for an address that does not belong to this host.
+    assert!(check_port_available("192.0.2.1", port).is_err());
+}
+
 #[test]
 fn test_filesize_exceeds_sendfile_count() {
     let mut source: File = tempfile().unwrap();
@@ -881,11 +1679,15 @@ fn custom_provider_from_request_test() {
     let request = Request {
         resume_from: None,
         path: StrPath::new("/custom_repo/archzfs/foo/bar/baz".to_owned()),
-        method: RequestMethod::Get
+        method: RequestMethod::Get,
+        authorization: None,
     };
     let custom_repo = CustomRepo {
         name: "archzfs".to_owned(),
         url: "https://archzfs.com".to_owned(),
+        tls: None,
+        credentials_file: None,
+        credentials: None,
     };
     let repos = vec![custom_repo];
     let (provider, new_get_request) = custom_provider_from_request(request, &repos);
@@ -894,13 +1696,98 @@ fn custom_provider_from_request_test() {
         name: "archzfs".to_owned(),
         mirror_results: Default::default(),
         country_code: "Unknown".to_string(),
+        layout: ProviderLayout::Mirror,
+        tls: None,
+        credentials: None,
     };
     let expected_get_request = Request {
         resume_from: None,
         path: StrPath::new("/foo/bar/baz".to_owned()),
-        method: RequestMethod::Get
+        method: RequestMethod::Get,
+        authorization: None,
     };
 
     assert_eq!(provider, Some(expected_provider));
     assert_eq!(new_get_request, expected_get_request);
 }
+
+#[test]
+fn custom_provider_from_request_credentials_test() {
+    let request = Request {
+        resume_from: None,
+        path: StrPath::new("/custom_repo/private/foo/bar/baz".to_owned()),
+        method: RequestMethod::Get,
+        authorization: None,
+    };
+    let credentials = Credentials::parse("user:secret").unwrap();
+    let custom_repo = CustomRepo {
+        name: "private".to_owned(),
+        url: "https://repo.example.com".to_owned(),
+        tls: None,
+        credentials_file: None,
+        credentials: Some(credentials.clone()),
+    };
+    let (provider, _) = custom_provider_from_request(request, &[custom_repo]);
+    assert_eq!(Some(credentials), provider.unwrap().credentials);
+}
+
+#[test]
+fn cache_api_request_test() {
+    let request = |path: &str| Request {
+        resume_from: None,
+        path: StrPath::new(path.to_owned()),
+        method: RequestMethod::Get,
+        authorization: None,
+    };
+    assert_eq!(Some(CacheApiRequest::Stats), cache_api_request(&request("/api/cache")));
+    assert_eq!(Some(CacheApiRequest::Package("glibc".to_owned())),
+               cache_api_request(&request("/api/cache?package=glibc")));
+    assert_eq!(Some(CacheApiRequest::File("core/os/x86_64/glibc-2.33-3-x86_64.pkg.tar.zst".to_owned())),
+               cache_api_request(&request("/api/cache/core/os/x86_64/glibc-2.33-3-x86_64.pkg.tar.zst")));
+    assert_eq!(Some(CacheApiRequest::Pattern("core/os/x86_64/glibc-*".to_owned())),
+               cache_api_request(&request("/api/cache?pattern=core/os/x86_64/glibc-*")));
+    assert_eq!(Some(CacheApiRequest::Package("libc++".to_owned())),
+               cache_api_request(&request("/api/cache?package=libc%2B+")));
+    assert_eq!(Some(CacheApiRequest::Pattern("core/os/x86_64/glibc-[0-9]*".to_owned())),
+               cache_api_request(&request("/api/cache?pattern=core%2Fos%2Fx86_64%2Fglibc-%5B0-9%5D%2a")));
+    assert_eq!(Some(CacheApiRequest::Package("100%".to_owned())),
+               cache_api_request(&request("/api/cache?package=100%")));
+    assert_eq!(None, cache_api_request(&request("/core/os/x86_64/glibc-2.33-3-x86_64.pkg.tar.zst")));
+    assert!(is_admin_request(&request("/api/cache")));
+}
+
+#[test]
+fn selected_cached_files_test() {
+    let request = |path: &str| Request {
+        resume_from: None,
+        path: StrPath::new(path.to_owned()),
+        method: RequestMethod::Delete,
+        authorization: None,
+    };
+    let directory = tempfile::tempdir().unwrap();
+    let cache_directory = directory.path().join("cache");
+    fs::create_dir_all(&cache_directory).unwrap();
+    let secret = directory.path().join("secret");
+    fs::write(&secret, "").unwrap();
+    // Nothing is selected, so that the eviction request is answered with 404.
+    for path in [format!("/api/cache/{}", secret.to_str().unwrap()), "/api/cache/../secret".to_owned()].iter() {
+        let cache_api_request = cache_api_request(&request(path)).unwrap();
+        assert_eq!(Some(vec![]), selected_cached_files(&cache_directory, &cache_api_request).unwrap());
+    }
+    assert!(secret.exists());
+    // An invalid pattern is answered with 400.
+    let cache_api_request = cache_api_request(&request("/api/cache?pattern=core/[")).unwrap();
+    assert_eq!(None, selected_cached_files(&cache_directory, &cache_api_request).unwrap());
+}
+
+#[test]
+fn receive_content_length_timeout_test() {
+    let (tx, rx) = flexo::progress::channel();
+    let start = Instant::now();
+    let result = receive_content_length(&rx, Duration::from_millis(50));
+    assert!(matches!(result, Err(ContentLengthError::Timeout)));
+    assert!(start.elapsed() < TIMEOUT_RECEIVE_CONTENT_LENGTH);
+    tx.send(FlexoProgress::JobSize(100));
+    let result = receive_content_length(&rx, Duration::from_millis(50));
+    assert!(matches!(result, Ok(ContentLengthResult::ContentLength(100))));
+}
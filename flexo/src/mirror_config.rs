@@ -1,17 +1,47 @@
-static CONFIG_FILE: &str = "/etc/flexo/flexo.toml";
+pub static DEFAULT_CONFIG_FILE: &str = "/etc/flexo/flexo.toml";
 
 extern crate serde;
 
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
 use std::fs;
+use std::io;
 use serde::Deserialize;
-use flexo::Properties;
+use flexo::{FailoverBudget, Properties, DEFAULT_FAILOVER_BUDGET, DEFAULT_JOB_THREAD_POOL_CONFIG};
+use flexo::thread_pool::{ThreadPool, ThreadPoolConfig};
 use std::time::Duration;
 use regex::Regex;
+use chrono::NaiveTime;
+use crate::bandwidth::UpstreamBandwidth;
+use crate::upstream_proxy::UpstreamProxy;
+use crate::upstream_transfers::UpstreamTransfers;
+use crate::credentials::Credentials;
+#[cfg(test)]
+use crate::test_fixtures;
 
 static DEFAULT_JSON_URI: &str = "https://archlinux.org/mirrors/status/json/";
 
+static DEFAULT_SNAPSHOT_DIRECTORY: &str = "/var/cache/flexo/snapshots";
+
+static DEFAULT_ARCHIVE_URL: &str = "https://archive.archlinux.org";
+
 static DEFAULT_REFRESH_AFTER_SECONDS: u64 = 3600 * 24 * 14;
 
+static DEFAULT_STALL_TIMEOUT_SECS: u64 = 30;
+
+static DEFAULT_TLS_HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+
+static DEFAULT_MAX_SEGMENTS: usize = 4;
+
+static DEFAULT_MIN_SEGMENT_SIZE_MIB: u64 = 16;
+
+const DEFAULT_CLIENT_THREAD_POOL_CONFIG: ThreadPoolConfig = ThreadPoolConfig {
+    max_threads: 256,
+    max_queue_size: 1024,
+    queue_timeout: Duration::from_secs(30),
+};
+
 #[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum MirrorSelectionMethod {
@@ -39,6 +69,8 @@ impl TomlValue for u64 { }
 impl TomlValue for u32 { }
 impl TomlValue for u16 { }
 impl TomlValue for Vec<String> { }
+impl TomlValue for Vec<ClientLimitConfig> { }
+impl TomlValue for Vec<BandwidthScheduleEntry> { }
 impl TomlValue for String {
     fn toml_value_from_str(s: String) -> String {
         quote_str(s)
@@ -55,6 +87,21 @@ impl TomlValue for MirrorSelectionMethod {
         quote_str(s)
     }
 }
+impl TomlValue for UpstreamHttpVersion {
+    fn toml_value_from_str(s: String) -> String {
+        quote_str(s)
+    }
+}
+
+/// The HTTP version used for downloads from remote mirrors. HTTP/2 is only used if the remote mirror supports it,
+/// otherwise, curl falls back to HTTP/1.1.
+#[derive(Deserialize, Debug, PartialEq, Eq, Copy, Clone)]
+pub enum UpstreamHttpVersion {
+    #[serde(rename = "1.1")]
+    Http11,
+    #[serde(rename = "2")]
+    Http2,
+}
 
 #[derive(Deserialize, Debug, PartialEq, Eq, Copy, Clone)]
 #[serde(rename_all = "lowercase")]
@@ -63,7 +110,7 @@ pub enum MirrorsRandomOrSort {
     Random,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct MirrorsAutoConfig {
     pub mirrors_status_json_endpoint: String,
     #[serde(default)]
@@ -90,7 +137,27 @@ impl MirrorsAutoConfig {
     }
 }
 
-impl Properties for MirrorConfig {}
+impl Properties for MirrorConfig {
+    fn failover_budget(&self) -> FailoverBudget {
+        match &self.failover {
+            None => DEFAULT_FAILOVER_BUDGET,
+            Some(f) => FailoverBudget {
+                max_attempts: f.max_attempts.unwrap_or(DEFAULT_FAILOVER_BUDGET.max_attempts).max(1),
+                retry_timeout: f.retry_timeout_secs.map(Duration::from_secs)
+                    .unwrap_or(DEFAULT_FAILOVER_BUDGET.retry_timeout),
+                provider_cooldown: f.provider_cooldown_secs.map(Duration::from_secs)
+                    .unwrap_or(DEFAULT_FAILOVER_BUDGET.provider_cooldown),
+            },
+        }
+    }
+
+    fn max_parallel_providers(&self) -> usize {
+        match self.segmented_downloads_config() {
+            None => 1,
+            Some(config) => config.max_segments(),
+        }
+    }
+}
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct MirrorConfig {
@@ -104,12 +171,55 @@ pub struct MirrorConfig {
     pub mirrors_predefined: Vec<String>,
     pub custom_repo: Option<Vec<CustomRepo>>,
     low_speed_limit: Option<u32>,
-    low_speed_limit_formatted: Option<String>,
+    pub low_speed_limit_formatted: Option<String>,
     pub low_speed_time_secs: Option<u64>,
     pub connect_timeout: Option<u64>,
+    pub upstream_http_version: Option<UpstreamHttpVersion>,
     pub max_speed_limit: Option<u64>,
+    /// Clients are disconnected if the file they are served has not grown for this many seconds.
+    pub stall_timeout_secs: Option<u64>,
     pub num_versions_retain: Option<u32>,
     pub mirrors_auto: Option<MirrorsAutoConfig>,
+    pub prefetch: Option<PrefetchConfig>,
+    pub snapshots: Option<SnapshotConfig>,
+    pub archive_fallback: Option<ArchiveFallbackConfig>,
+    pub tls: Option<TlsConfig>,
+    /// Networks in CIDR notation. If set, only clients from those networks are permitted.
+    pub allowed_clients: Option<Vec<String>>,
+    /// Networks in CIDR notation. Clients from those networks are rejected, even if they are also allowed.
+    pub denied_clients: Option<Vec<String>>,
+    /// Networks in CIDR notation. If set, only clients from those networks can use the admin endpoints.
+    pub admin_allowed_clients: Option<Vec<String>>,
+    /// File containing the credentials required for the admin endpoints, see Credentials::from_file.
+    pub admin_credentials_file: Option<String>,
+    pub client_limits: Option<Vec<ClientLimitConfig>>,
+    pub upstream_bandwidth: Option<UpstreamBandwidthConfig>,
+    pub upstream_proxy: Option<UpstreamProxyConfig>,
+    pub upstream_tls: Option<UpstreamTlsConfig>,
+    /// The thread pool used to serve client connections.
+    pub client_threads: Option<ThreadPoolSettings>,
+    /// The thread pool used to download files from remote mirrors.
+    pub download_threads: Option<ThreadPoolSettings>,
+    pub failover: Option<FailoverConfig>,
+    pub segmented_downloads: Option<SegmentedDownloadsConfig>,
+    /// Shared by all downloads, see UpstreamBandwidth::from_config.
+    #[serde(skip)]
+    pub upstream_bandwidth_limiter: UpstreamBandwidth,
+    /// Resolved from upstream_proxy at startup, see UpstreamProxy::from_config.
+    #[serde(skip)]
+    pub upstream_proxy_settings: Option<UpstreamProxy>,
+    /// Runs all downloads from remote mirrors, including the segments of segmented downloads. Created from
+    /// download_threads by prepare_config.
+    #[serde(skip, default = "default_download_thread_pool")]
+    pub download_thread_pool: ThreadPool,
+    /// Performs the downloads from remote mirrors, so that they can share connections. Carried over by
+    /// prepare_config when the configuration is reloaded.
+    #[serde(skip)]
+    pub upstream_transfers: UpstreamTransfers,
+}
+
+fn default_download_thread_pool() -> ThreadPool {
+    ThreadPool::new("download", DEFAULT_JOB_THREAD_POOL_CONFIG)
 }
 
 impl MirrorConfig {
@@ -128,12 +238,256 @@ impl MirrorConfig {
             None => self.low_speed_limit
         }
     }
+
+    /// Returns the snapshot configuration, or None if snapshots are disabled.
+    pub fn snapshot_config(&self) -> Option<&SnapshotConfig> {
+        self.snapshots.as_ref().filter(|snapshot_config| snapshot_config.enabled)
+    }
+
+    /// Returns the configuration for segmented downloads, or None if segmented downloads are disabled.
+    pub fn segmented_downloads_config(&self) -> Option<&SegmentedDownloadsConfig> {
+        self.segmented_downloads.as_ref().filter(|config| config.enabled)
+    }
+
+    pub fn stall_timeout(&self) -> Duration {
+        Duration::from_secs(self.stall_timeout_secs.unwrap_or(DEFAULT_STALL_TIMEOUT_SECS))
+    }
+
+    pub fn client_thread_pool_config(&self) -> ThreadPoolConfig {
+        thread_pool_config(&self.client_threads, DEFAULT_CLIENT_THREAD_POOL_CONFIG)
+    }
+
+    pub fn download_thread_pool_config(&self) -> ThreadPoolConfig {
+        thread_pool_config(&self.download_threads, DEFAULT_JOB_THREAD_POOL_CONFIG)
+    }
+
+    pub fn snapshot_directory(&self) -> &str {
+        match &self.snapshots {
+            None => DEFAULT_SNAPSHOT_DIRECTORY,
+            Some(snapshot_config) => snapshot_config.directory(),
+        }
+    }
+}
+
+/// Limits that apply to each client within the given network.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ClientLimitConfig {
+    /// A network in CIDR notation or a single IP address.
+    pub network: String,
+    /// A bandwidth such as "2 MiB/s", shared by all connections of the client.
+    pub max_bandwidth: Option<String>,
+    pub max_connections: Option<u32>,
+}
+
+/// Settings for a thread pool. Unset values are replaced by defaults.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ThreadPoolSettings {
+    pub max_threads: Option<usize>,
+    pub max_queue_size: Option<usize>,
+    pub queue_timeout_secs: Option<u64>,
+}
+
+fn thread_pool_config(settings: &Option<ThreadPoolSettings>, default: ThreadPoolConfig) -> ThreadPoolConfig {
+    match settings {
+        None => default,
+        Some(s) => ThreadPoolConfig {
+            max_threads: s.max_threads.unwrap_or(default.max_threads).max(1),
+            max_queue_size: s.max_queue_size.unwrap_or(default.max_queue_size),
+            queue_timeout: s.queue_timeout_secs.map(Duration::from_secs).unwrap_or(default.queue_timeout),
+        },
+    }
+}
+
+fn thread_pool_settings_from_env(env: &EnvReader, prefix: &str) -> Option<ThreadPoolSettings> {
+    let max_threads = env.optional::<usize>(&format!("{}_MAX_THREADS", prefix));
+    let max_queue_size = env.optional::<usize>(&format!("{}_MAX_QUEUE_SIZE", prefix));
+    let queue_timeout_secs = env.optional::<u64>(&format!("{}_QUEUE_TIMEOUT_SECS", prefix));
+    match (max_threads, max_queue_size, queue_timeout_secs) {
+        (None, None, None) => None,
+        (max_threads, max_queue_size, queue_timeout_secs) => Some(ThreadPoolSettings {
+            max_threads,
+            max_queue_size,
+            queue_timeout_secs,
+        }),
+    }
+}
+
+/// Large files can be downloaded from multiple remote mirrors in parallel, each providing one segment of the file.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SegmentedDownloadsConfig {
+    pub enabled: bool,
+    pub max_segments: Option<usize>,
+    pub min_segment_size_mib: Option<u64>,
+}
+
+impl SegmentedDownloadsConfig {
+    pub fn max_segments(&self) -> usize {
+        self.max_segments.unwrap_or(DEFAULT_MAX_SEGMENTS).max(1)
+    }
+
+    /// The minimum size of a segment, in bytes.
+    pub fn min_segment_size(&self) -> u64 {
+        self.min_segment_size_mib.unwrap_or(DEFAULT_MIN_SEGMENT_SIZE_MIB) * 1024 * 1024
+    }
+}
+
+/// TLS settings for connections to remote mirrors. Settings that are not set keep curl's defaults, or the global
+/// settings if these are the settings of a custom repo.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UpstreamTlsConfig {
+    /// PEM file containing the CA certificates used instead of the system's CA certificates.
+    pub ca_bundle_file: Option<String>,
+    /// PEM file containing the client certificate, for remote mirrors that require mutual TLS.
+    pub client_certificate_file: Option<String>,
+    /// PEM file containing the private key of the client certificate.
+    pub client_private_key_file: Option<String>,
+    /// Disables the verification of the remote mirror's certificate. This is insecure, only use this if you
+    /// have no other choice.
+    pub insecure_disable_verification: Option<bool>,
+}
+
+/// A proxy for all connections to remote mirrors, see UpstreamProxy::from_config.
+#[derive(Deserialize, Debug, Clone)]
+pub struct UpstreamProxyConfig {
+    /// A URL such as "http://proxy.example.com:3128". Supported schemes are http, https, socks4, socks4a, socks5
+    /// and socks5h.
+    pub url: String,
+    /// File containing the credentials for the proxy in the format "username:password".
+    pub credentials_file: Option<String>,
+    /// Hosts that are accessed without the proxy.
+    pub no_proxy: Option<Vec<String>>,
+}
+
+/// Determines how long a download keeps trying other remote mirrors. Unset values are replaced by defaults.
+#[derive(Deserialize, Debug, Clone)]
+pub struct FailoverConfig {
+    pub max_attempts: Option<u32>,
+    pub retry_timeout_secs: Option<u64>,
+    pub provider_cooldown_secs: Option<u64>,
+}
+
+/// Limits the bandwidth of all downloads from remote mirrors in sum.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct UpstreamBandwidthConfig {
+    /// A bandwidth such as "20 MBit/s". If unset, downloads are not limited outside of the scheduled time windows.
+    pub max_bandwidth: Option<String>,
+    pub schedule: Option<Vec<BandwidthScheduleEntry>>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct BandwidthScheduleEntry {
+    /// A time window such as "23:00-06:00", in local time.
+    pub time_window: String,
+    /// The bandwidth during the time window. If unset, downloads are not limited during the time window.
+    pub max_bandwidth: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct PrefetchConfig {
+    pub enabled: bool,
+    /// A time window such as "01:00-06:00", in local time. If unset, prefetching may run at any time.
+    pub time_window: Option<String>,
+}
+
+impl PrefetchConfig {
+    pub fn time_window(&self) -> Option<TimeWindow> {
+        let s = self.time_window.as_ref()?;
+        match TimeWindow::parse(s) {
+            None => {
+                error!("Unable to parse time window {:?}: Expected a format like \"01:00-06:00\".", s);
+                None
+            }
+            Some(time_window) => Some(time_window),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct SnapshotConfig {
+    pub enabled: bool,
+    /// Copies of the database files are stored in this directory, with one subdirectory per day.
+    pub directory: Option<String>,
+    /// Snapshots that are not available locally are fetched from this archive.
+    pub archive_url: Option<String>,
+}
+
+impl SnapshotConfig {
+    pub fn directory(&self) -> &str {
+        self.directory.as_deref().unwrap_or(DEFAULT_SNAPSHOT_DIRECTORY)
+    }
+
+    pub fn archive_url(&self) -> &str {
+        self.archive_url.as_deref().unwrap_or(DEFAULT_ARCHIVE_URL)
+    }
+}
+
+/// Settings for the HTTPS listener, which runs in addition to the plain HTTP listener.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TlsConfig {
+    pub port: u16,
+    /// PEM file containing the certificate, optionally followed by the intermediate certificates.
+    pub certificate_file: String,
+    /// PEM file containing the private key.
+    pub private_key_file: String,
+    /// Clients that have not completed the TLS handshake within this time are disconnected.
+    pub handshake_timeout_secs: Option<u64>,
+}
+
+impl TlsConfig {
+    pub fn handshake_timeout(&self) -> Duration {
+        Duration::from_secs(self.handshake_timeout_secs.unwrap_or(DEFAULT_TLS_HANDSHAKE_TIMEOUT_SECS))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ArchiveFallbackConfig {
+    pub enabled: bool,
+    /// The base URL of the archive. Packages are expected at <url>/packages/<initial>/<name>/<filename>.
+    pub url: Option<String>,
+}
+
+impl ArchiveFallbackConfig {
+    pub fn url(&self) -> &str {
+        self.url.as_deref().unwrap_or(DEFAULT_ARCHIVE_URL)
+    }
+}
+
+/// A daily recurring period of time, in local time. The end may be before the start, in which case the time window
+/// extends beyond midnight.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn parse(s: &str) -> Option<Self> {
+        let (start, end) = s.split_once('-')?;
+        let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").ok()?;
+        let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").ok()?;
+        Some(TimeWindow { start, end })
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct CustomRepo {
     pub name: String,
     pub url: String,
+    /// Takes precedence over the global upstream_tls settings.
+    pub tls: Option<UpstreamTlsConfig>,
+    /// File containing the credentials sent to the custom repo, see Credentials::from_file.
+    pub credentials_file: Option<String>,
+    /// Read from the credentials_file at startup, or from an environment variable.
+    #[serde(skip)]
+    pub credentials: Option<Credentials>,
 }
 
 impl MirrorConfig {
@@ -154,54 +508,229 @@ impl MirrorConfig {
     }
 }
 
-fn mirror_config_from_toml() -> MirrorConfig {
-    let config_contents = fs::read_to_string(CONFIG_FILE)
-        .unwrap_or_else(|_| panic!("Unable to read file: {}", CONFIG_FILE));
-    match toml::from_str(&config_contents) {
-        Ok(v) => v,
-        Err(e) => panic!("Unable to parse file {}: {:?}\nPlease make sure that the file contains \
-        valid TOML syntax and that all required attributes are set.", CONFIG_FILE, e)
+/// A problem with a single setting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigProblem {
+    /// The key of the setting, e.g. "upstream_bandwidth.max_bandwidth", or the name of the environment variable.
+    pub location: String,
+    /// The line in the TOML file, if known.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl ConfigProblem {
+    pub fn new(location: &str, message: String) -> Self {
+        ConfigProblem {
+            location: location.to_owned(),
+            line: None,
+            message,
+        }
+    }
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            None => write!(f, "{}: {}", self.location, self.message),
+            Some(line) => write!(f, "line {}, {}: {}", line, self.location, self.message),
+        }
+    }
+}
+
+/// Where the settings are read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigSource {
+    TomlFile(String),
+    Environment,
+}
+
+impl ConfigSource {
+    /// A file given on the command line takes precedence over environment variables. Otherwise, the settings are
+    /// read from environment variables if any variable starts with FLEXO_, or from the default file.
+    pub fn new(config_file: Option<String>) -> Self {
+        match config_file {
+            Some(path) => ConfigSource::TomlFile(path),
+            None if std::env::vars_os().any(|(key, _value)| key.to_string_lossy().starts_with("FLEXO_")) => {
+                ConfigSource::Environment
+            }
+            None => ConfigSource::TomlFile(DEFAULT_CONFIG_FILE.to_owned()),
+        }
     }
 }
 
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::TomlFile(path) => write!(f, "{}", path),
+            ConfigSource::Environment => write!(f, "the environment variables"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    UnreadableFile(String, io::Error),
+    InvalidToml(String, toml::de::Error),
+    InvalidEnvironment(Vec<ConfigProblem>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::UnreadableFile(path, e) => write!(f, "Unable to read file {}: {}", path, e),
+            ConfigError::InvalidToml(path, e) => write!(f, "Unable to parse file {}: {}\nPlease make sure that the \
+                file contains valid TOML syntax and that all required attributes are set.", path, e),
+            ConfigError::InvalidEnvironment(problems) => {
+                write!(f, "Invalid environment variables:")?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn mirror_config_from_toml(path: &str) -> Result<(MirrorConfig, Vec<ConfigProblem>), ConfigError> {
+    let config_contents = fs::read_to_string(path).map_err(|e| ConfigError::UnreadableFile(path.to_owned(), e))?;
+    parse_toml_config(&config_contents).map_err(|e| ConfigError::InvalidToml(path.to_owned(), e))
+}
+
+/// Parses the TOML file. Unknown keys do not prevent Flexo from starting, so they are returned as warnings.
+fn parse_toml_config(contents: &str) -> Result<(MirrorConfig, Vec<ConfigProblem>), toml::de::Error> {
+    let mut unknown_keys = Vec::new();
+    let mut deserializer = toml::Deserializer::new(contents);
+    let config = serde_ignored::deserialize(&mut deserializer, |path| {
+        // Optional values show up as "?" in the path.
+        let key = path.to_string().split('.').filter(|c| *c != "?").collect::<Vec<_>>().join(".");
+        unknown_keys.push(key);
+    })?;
+    let warnings = unknown_keys.into_iter().map(|key| {
+        ConfigProblem {
+            line: line_of_key(contents, &key),
+            location: key,
+            message: "unknown setting, this setting is ignored".to_owned(),
+        }
+    }).collect();
+    Ok((config, warnings))
+}
+
+/// Returns the line that defines the given key, either as a key-value pair or as a table. Indices of arrays are
+/// ignored, so the first matching element is used.
+fn line_of_key(contents: &str, key: &str) -> Option<usize> {
+    let components = key.split('.').filter(|c| c.parse::<usize>().is_err()).collect::<Vec<_>>();
+    let (last_component, parents) = components.split_last()?;
+    let (key, table) = (components.join("."), parents.join("."));
+    let mut current_table = String::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            current_table = line.trim_matches(&['[', ']'][..]).trim().to_owned();
+            if current_table == key {
+                return Some(index + 1);
+            }
+        } else if current_table == table {
+            if let Some(rest) = line.strip_prefix(last_component) {
+                if rest.trim_start().starts_with(&['=', '.'][..]) {
+                    return Some(index + 1);
+                }
+            }
+        }
+    }
+    None
+}
+
 #[derive(Deserialize)]
 struct DValue <T> {
     value: T
 }
 
-fn parse_env_toml<T>(s: &str) -> Option<T> where
-          T: serde::de::DeserializeOwned + TomlValue + 'static,
-{
-    let env_var = std::env::var(s).ok()?;
-    let toml_document = format!("value = {}", T::toml_value_from_str(env_var));
-    // Our actual intent is to parse the environment variable as a TOML value, but the parser accepts only complete
-    // TOML documents with key-value pairs. So we construct a TOML document with a single key-value pair, and
-    // then extract the value.
-    let deserialized = toml::from_str::<DValue<T>>(&toml_document).unwrap();
-    Some(deserialized.value)
-}
-
-fn mirrors_auto_config_from_env() -> MirrorsAutoConfig {
-    let https_required = parse_env_toml::<bool>("FLEXO_MIRRORS_AUTO_HTTPS_REQUIRED").unwrap();
-    let ipv4 = parse_env_toml::<bool>("FLEXO_MIRRORS_AUTO_IPV4").unwrap();
-    let ipv6 = parse_env_toml::<bool>("FLEXO_MIRRORS_AUTO_IPV6").unwrap();
-    let max_score = parse_env_toml::<f64>("FLEXO_MIRRORS_AUTO_MAX_SCORE").unwrap();
-    let num_mirrors = parse_env_toml::<usize>("FLEXO_MIRRORS_AUTO_NUM_MIRRORS").unwrap();
-    let mirrors_random_or_sort = parse_env_toml::<MirrorsRandomOrSort>("FLEXO_MIRRORS_AUTO_MIRRORS_RANDOM_OR_SORT")
-        .unwrap();
-    let timeout = parse_env_toml::<u64>("FLEXO_MIRRORS_AUTO_TIMEOUT").unwrap();
-    let mirrors_status_json_endpoint = parse_env_toml::<String>("FLEXO_MIRRORS_AUTO_MIRRORS_STATUS_JSON_ENDPOINT")
+/// Reads the settings from environment variables. Problems are collected instead of aborting at the first one, so
+/// that all of them can be reported at once.
+#[derive(Default)]
+struct EnvReader {
+    problems: RefCell<Vec<ConfigProblem>>,
+    /// All variables that were consulted, so that unknown variables can be detected.
+    keys_read: RefCell<HashSet<String>>,
+}
+
+impl EnvReader {
+    fn raw(&self, key: &str) -> Option<String> {
+        self.keys_read.borrow_mut().insert(key.to_owned());
+        std::env::var(key).ok()
+    }
+
+    fn optional<T>(&self, key: &str) -> Option<T> where
+        T: serde::de::DeserializeOwned + TomlValue + 'static,
+    {
+        let env_var = self.raw(key)?;
+        let toml_document = format!("value = {}", T::toml_value_from_str(env_var.clone()));
+        // Our actual intent is to parse the environment variable as a TOML value, but the parser accepts only complete
+        // TOML documents with key-value pairs. So we construct a TOML document with a single key-value pair, and
+        // then extract the value.
+        match toml::from_str::<DValue<T>>(&toml_document) {
+            Ok(deserialized) => Some(deserialized.value),
+            Err(e) => {
+                self.report(key, format!("unable to parse the value {:?}: {}", env_var, e));
+                None
+            }
+        }
+    }
+
+    fn required<T>(&self, key: &str) -> Option<T> where
+        T: serde::de::DeserializeOwned + TomlValue + 'static,
+    {
+        let value = self.optional(key);
+        if std::env::var_os(key).is_none() {
+            self.report(key, "this variable is required, but not set".to_owned());
+        }
+        value
+    }
+
+    fn report(&self, key: &str, message: String) {
+        self.problems.borrow_mut().push(ConfigProblem::new(key, message));
+    }
+
+    /// Variables that start with FLEXO_ but were not consulted, either because of a typo, or because they are
+    /// ignored with the current settings, e.g. FLEXO_MIRRORS_AUTO_* with the predefined mirror selection method.
+    fn unused_keys(&self) -> Vec<ConfigProblem> {
+        let keys_read = self.keys_read.borrow();
+        let mut unused_keys = std::env::vars_os()
+            .filter_map(|(key, _value)| key.into_string().ok())
+            .filter(|key| key.starts_with("FLEXO_") && !keys_read.contains(key))
+            .collect::<Vec<_>>();
+        unused_keys.sort();
+        unused_keys.iter().map(|key| {
+            ConfigProblem::new(key, "unknown setting, or not used with the current settings".to_owned())
+        }).collect()
+    }
+}
+
+fn mirrors_auto_config_from_env(env: &EnvReader) -> Option<MirrorsAutoConfig> {
+    let https_required = env.required::<bool>("FLEXO_MIRRORS_AUTO_HTTPS_REQUIRED");
+    let ipv4 = env.required::<bool>("FLEXO_MIRRORS_AUTO_IPV4");
+    let ipv6 = env.required::<bool>("FLEXO_MIRRORS_AUTO_IPV6");
+    let max_score = env.required::<f64>("FLEXO_MIRRORS_AUTO_MAX_SCORE");
+    let num_mirrors = env.required::<usize>("FLEXO_MIRRORS_AUTO_NUM_MIRRORS");
+    let mirrors_random_or_sort = env.required::<MirrorsRandomOrSort>("FLEXO_MIRRORS_AUTO_MIRRORS_RANDOM_OR_SORT");
+    let timeout = env.required::<u64>("FLEXO_MIRRORS_AUTO_TIMEOUT");
+    let mirrors_status_json_endpoint = env.optional::<String>("FLEXO_MIRRORS_AUTO_MIRRORS_STATUS_JSON_ENDPOINT")
             .unwrap_or_else(|| DEFAULT_JSON_URI.to_owned());
     let mirrors_status_json_endpoint_fallbacks =
-        parse_env_toml::<String>("FLEXO_MIRRORS_AUTO_MIRRORS_STATUS_JSON_ENDPOINT_FALLBACKS")
+        env.optional::<String>("FLEXO_MIRRORS_AUTO_MIRRORS_STATUS_JSON_ENDPOINT_FALLBACKS")
             .map(comma_separated_to_vec)
             .unwrap_or_default();
-    let allowed_countries = parse_env_toml::<String>("FLEXO_MIRRORS_AUTO_ALLOWED_COUNTRIES")
+    let allowed_countries = env.optional::<String>("FLEXO_MIRRORS_AUTO_ALLOWED_COUNTRIES")
         .map(comma_separated_to_vec)
         .unwrap_or_default();
     let mirrors_blacklist =
-        parse_env_toml::<Vec<String>>("FLEXO_MIRRORS_AUTO_MIRRORS_BLACKLIST").unwrap_or_else(Vec::new);
-    MirrorsAutoConfig {
+        env.optional::<Vec<String>>("FLEXO_MIRRORS_AUTO_MIRRORS_BLACKLIST").unwrap_or_else(Vec::new);
+    let required_settings = (https_required, ipv4, ipv6, max_score, num_mirrors, mirrors_random_or_sort, timeout);
+    let (https_required, ipv4, ipv6, max_score, num_mirrors, mirrors_random_or_sort, timeout) = match required_settings {
+        (Some(a), Some(b), Some(c), Some(d), Some(e), Some(f), Some(g)) => (a, b, c, d, e, f, g),
+        _ => return None,
+    };
+    Some(MirrorsAutoConfig {
         mirrors_status_json_endpoint,
         mirrors_status_json_endpoint_fallbacks,
         mirrors_blacklist,
@@ -213,32 +742,114 @@ fn mirrors_auto_config_from_env() -> MirrorsAutoConfig {
         mirrors_random_or_sort,
         timeout,
         allowed_countries,
-    }
+    })
 }
 
-fn mirror_config_from_env() -> MirrorConfig {
-    let cache_directory = parse_env_toml::<String>("FLEXO_CACHE_DIRECTORY").unwrap();
-    let mirrorlist_fallback_file = parse_env_toml::<String>("FLEXO_MIRRORLIST_FALLBACK_FILE").unwrap();
-    let mirrorlist_latency_test_results_file = parse_env_toml::<String>("FLEXO_MIRRORLIST_LATENCY_TEST_RESULTS_FILE");
-    let listen_ip_address = parse_env_toml::<String>("FLEXO_LISTEN_IP_ADDRESS");
-    let port = parse_env_toml::<u16>("FLEXO_PORT").unwrap();
-    let mirror_selection_method = parse_env_toml::<MirrorSelectionMethod>("FLEXO_MIRROR_SELECTION_METHOD").unwrap();
-    let mirrors_predefined = parse_env_toml::<Vec<String>>("FLEXO_MIRRORS_PREDEFINED").unwrap();
-    let connect_timeout = parse_env_toml::<u64>("FLEXO_CONNECT_TIMEOUT");
-    let low_speed_limit = parse_env_toml::<u32>("FLEXO_LOW_SPEED_LIMIT");
-    let low_speed_limit_formatted = parse_env_toml::<String>("FLEXO_LOW_SPEED_LIMIT_FORMATTED");
-    let low_speed_time_secs = parse_env_toml::<u64>("FLEXO_LOW_SPEED_TIME_SECS");
-    let max_speed_limit = parse_env_toml::<u64>("FLEXO_MAX_SPEED_LIMIT");
-    let refresh_latency_tests_after = parse_env_toml::<String>("FLEXO_REFRESH_LATENCY_TESTS_AFTER");
-    let custom_repo_env = parse_env_toml::<String>("FLEXO_CUSTOM_REPO");
-    let num_versions_retain = parse_env_toml::<u32>("FLEXO_NUM_VERSIONS_RETAIN");
-    let custom_repo = custom_repos_from_env(custom_repo_env);
+fn mirror_config_from_env() -> Result<(MirrorConfig, Vec<ConfigProblem>), ConfigError> {
+    let env = EnvReader::default();
+    let cache_directory = env.required::<String>("FLEXO_CACHE_DIRECTORY");
+    let mirrorlist_fallback_file = env.required::<String>("FLEXO_MIRRORLIST_FALLBACK_FILE");
+    let mirrorlist_latency_test_results_file = env.optional::<String>("FLEXO_MIRRORLIST_LATENCY_TEST_RESULTS_FILE");
+    let listen_ip_address = env.optional::<String>("FLEXO_LISTEN_IP_ADDRESS");
+    let port = env.required::<u16>("FLEXO_PORT");
+    let mirror_selection_method = env.required::<MirrorSelectionMethod>("FLEXO_MIRROR_SELECTION_METHOD");
+    let mirrors_predefined = env.required::<Vec<String>>("FLEXO_MIRRORS_PREDEFINED");
+    let connect_timeout = env.optional::<u64>("FLEXO_CONNECT_TIMEOUT");
+    let upstream_http_version = env.optional::<UpstreamHttpVersion>("FLEXO_UPSTREAM_HTTP_VERSION");
+    let low_speed_limit = env.optional::<u32>("FLEXO_LOW_SPEED_LIMIT");
+    let low_speed_limit_formatted = env.optional::<String>("FLEXO_LOW_SPEED_LIMIT_FORMATTED");
+    let low_speed_time_secs = env.optional::<u64>("FLEXO_LOW_SPEED_TIME_SECS");
+    let max_speed_limit = env.optional::<u64>("FLEXO_MAX_SPEED_LIMIT");
+    let stall_timeout_secs = env.optional::<u64>("FLEXO_STALL_TIMEOUT_SECS");
+    let refresh_latency_tests_after = env.optional::<String>("FLEXO_REFRESH_LATENCY_TESTS_AFTER");
+    let custom_repo_env = env.optional::<String>("FLEXO_CUSTOM_REPO");
+    let num_versions_retain = env.optional::<u32>("FLEXO_NUM_VERSIONS_RETAIN");
+    let custom_repo = custom_repos_from_env(&env, custom_repo_env);
+    let prefetch = env.optional::<bool>("FLEXO_PREFETCH_ENABLED").map(|enabled| {
+        PrefetchConfig {
+            enabled,
+            time_window: env.optional::<String>("FLEXO_PREFETCH_TIME_WINDOW"),
+        }
+    });
+    let allowed_clients = env.optional::<String>("FLEXO_ALLOWED_CLIENTS").map(comma_separated_to_vec);
+    let denied_clients = env.optional::<String>("FLEXO_DENIED_CLIENTS").map(comma_separated_to_vec);
+    let admin_allowed_clients = env.optional::<String>("FLEXO_ADMIN_ALLOWED_CLIENTS").map(comma_separated_to_vec);
+    let admin_credentials_file = env.optional::<String>("FLEXO_ADMIN_CREDENTIALS_FILE");
+    let client_limits = env.optional::<Vec<ClientLimitConfig>>("FLEXO_CLIENT_LIMITS");
+    let upstream_max_bandwidth = env.optional::<String>("FLEXO_UPSTREAM_BANDWIDTH_MAX_BANDWIDTH");
+    let upstream_schedule = env.optional::<Vec<BandwidthScheduleEntry>>("FLEXO_UPSTREAM_BANDWIDTH_SCHEDULE");
+    let upstream_proxy = env.optional::<String>("FLEXO_UPSTREAM_PROXY_URL").map(|url| {
+        UpstreamProxyConfig {
+            url,
+            credentials_file: env.optional::<String>("FLEXO_UPSTREAM_PROXY_CREDENTIALS_FILE"),
+            no_proxy: env.optional::<String>("FLEXO_UPSTREAM_PROXY_NO_PROXY").map(comma_separated_to_vec),
+        }
+    });
+    let upstream_tls = upstream_tls_config_from_env(&env, "FLEXO_UPSTREAM_TLS");
+    let client_threads = thread_pool_settings_from_env(&env, "FLEXO_CLIENT_THREADS");
+    let download_threads = thread_pool_settings_from_env(&env, "FLEXO_DOWNLOAD_THREADS");
+    let failover_max_attempts = env.optional::<u32>("FLEXO_FAILOVER_MAX_ATTEMPTS");
+    let failover_retry_timeout_secs = env.optional::<u64>("FLEXO_FAILOVER_RETRY_TIMEOUT_SECS");
+    let failover_provider_cooldown_secs = env.optional::<u64>("FLEXO_FAILOVER_PROVIDER_COOLDOWN_SECS");
+    let failover = match (failover_max_attempts, failover_retry_timeout_secs, failover_provider_cooldown_secs) {
+        (None, None, None) => None,
+        (max_attempts, retry_timeout_secs, provider_cooldown_secs) => Some(FailoverConfig {
+            max_attempts,
+            retry_timeout_secs,
+            provider_cooldown_secs,
+        }),
+    };
+    let segmented_downloads = env.optional::<bool>("FLEXO_SEGMENTED_DOWNLOADS_ENABLED").map(|enabled| {
+        SegmentedDownloadsConfig {
+            enabled,
+            max_segments: env.optional::<usize>("FLEXO_SEGMENTED_DOWNLOADS_MAX_SEGMENTS"),
+            min_segment_size_mib: env.optional::<u64>("FLEXO_SEGMENTED_DOWNLOADS_MIN_SEGMENT_SIZE_MIB"),
+        }
+    });
+    let upstream_bandwidth = match (upstream_max_bandwidth, upstream_schedule) {
+        (None, None) => None,
+        (max_bandwidth, schedule) => Some(UpstreamBandwidthConfig { max_bandwidth, schedule }),
+    };
+    let tls = env.optional::<u16>("FLEXO_TLS_PORT").and_then(|port| {
+        let certificate_file = env.required::<String>("FLEXO_TLS_CERTIFICATE_FILE");
+        let private_key_file = env.required::<String>("FLEXO_TLS_PRIVATE_KEY_FILE");
+        Some(TlsConfig {
+            port,
+            certificate_file: certificate_file?,
+            private_key_file: private_key_file?,
+            handshake_timeout_secs: env.optional::<u64>("FLEXO_TLS_HANDSHAKE_TIMEOUT_SECS"),
+        })
+    });
+    let archive_fallback = env.optional::<bool>("FLEXO_ARCHIVE_FALLBACK_ENABLED").map(|enabled| {
+        ArchiveFallbackConfig {
+            enabled,
+            url: env.optional::<String>("FLEXO_ARCHIVE_FALLBACK_URL"),
+        }
+    });
+    let snapshots = env.optional::<bool>("FLEXO_SNAPSHOTS_ENABLED").map(|enabled| {
+        SnapshotConfig {
+            enabled,
+            directory: env.optional::<String>("FLEXO_SNAPSHOTS_DIRECTORY"),
+            archive_url: env.optional::<String>("FLEXO_SNAPSHOTS_ARCHIVE_URL"),
+        }
+    });
 
     let mirrors_auto = match mirror_selection_method {
-        MirrorSelectionMethod::Auto => Some(mirrors_auto_config_from_env()),
-        MirrorSelectionMethod::Predefined => None,
+        Some(MirrorSelectionMethod::Auto) => mirrors_auto_config_from_env(&env),
+        Some(MirrorSelectionMethod::Predefined) | None => None,
     };
-    MirrorConfig {
+    let required_settings = (cache_directory, mirrorlist_fallback_file, port, mirror_selection_method, mirrors_predefined);
+    let (cache_directory, mirrorlist_fallback_file, port, mirror_selection_method, mirrors_predefined) =
+        match required_settings {
+            (Some(a), Some(b), Some(c), Some(d), Some(e)) if env.problems.borrow().is_empty() => (a, b, c, d, e),
+            _ => {
+                let unused_keys = env.unused_keys();
+                let mut problems = env.problems.into_inner();
+                problems.extend(unused_keys);
+                return Err(ConfigError::InvalidEnvironment(problems));
+            }
+        };
+    let config = MirrorConfig {
         cache_directory,
         mirrorlist_fallback_file,
         mirrorlist_latency_test_results_file,
@@ -252,10 +863,33 @@ fn mirror_config_from_env() -> MirrorConfig {
         low_speed_limit_formatted,
         low_speed_time_secs,
         connect_timeout,
+        upstream_http_version,
         max_speed_limit,
+        stall_timeout_secs,
         num_versions_retain,
         mirrors_auto,
-    }
+        prefetch,
+        snapshots,
+        archive_fallback,
+        tls,
+        allowed_clients,
+        denied_clients,
+        admin_allowed_clients,
+        admin_credentials_file,
+        client_limits,
+        upstream_bandwidth,
+        upstream_bandwidth_limiter: UpstreamBandwidth::default(),
+        upstream_proxy,
+        upstream_proxy_settings: None,
+        download_thread_pool: default_download_thread_pool(),
+        upstream_transfers: UpstreamTransfers::default(),
+        upstream_tls,
+        client_threads,
+        download_threads,
+        failover,
+        segmented_downloads,
+    };
+    Ok((config, env.unused_keys()))
 }
 
 fn comma_separated_to_vec(comma_separated: String) -> Vec<String> {
@@ -267,15 +901,19 @@ fn comma_separated_to_vec(comma_separated: String) -> Vec<String> {
         .collect::<Vec<String>>()
 }
 
-fn custom_repos_from_env(maybe_env: Option<String>) -> Option<Vec<CustomRepo>> {
+fn custom_repos_from_env(env: &EnvReader, maybe_env: Option<String>) -> Option<Vec<CustomRepo>> {
     match maybe_env {
         None => None,
         Some(cr) => {
             cr.split(' ').map(|s| {
                 s.split_once('@').map(|(name, url)| {
+                    let prefix = custom_repo_env_prefix(name);
                     CustomRepo {
                         name: name.to_owned(),
                         url: url.to_owned(),
+                        tls: upstream_tls_config_from_env(env, &format!("{}_TLS", prefix)),
+                        credentials_file: env.optional::<String>(&format!("{}_CREDENTIALS_FILE", prefix)),
+                        credentials: credentials_from_env(env, &format!("{}_CREDENTIALS", prefix)),
                     }
                 })
             }).collect()
@@ -283,15 +921,59 @@ fn custom_repos_from_env(maybe_env: Option<String>) -> Option<Vec<CustomRepo>> {
     }
 }
 
-pub fn load_config() -> MirrorConfig {
-    if std::env::vars().any(|(key, _value)| key.starts_with("FLEXO_")) {
-        mirror_config_from_env()
-    } else {
-        mirror_config_from_toml()
+/// The prefix of the environment variables that contain the settings of the given custom repo, e.g.,
+/// FLEXO_CUSTOM_REPO_MY_REPO for the custom repo named "my-repo".
+fn custom_repo_env_prefix(name: &str) -> String {
+    let name = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect::<String>();
+    format!("FLEXO_CUSTOM_REPO_{}", name)
+}
+
+fn credentials_from_env(env: &EnvReader, key: &str) -> Option<Credentials> {
+    let credentials = Credentials::parse(&env.raw(key)?);
+    if credentials.is_none() {
+        env.report(key, "the credentials must not be empty".to_owned());
+    }
+    credentials
+}
+
+fn upstream_tls_config_from_env(env: &EnvReader, prefix: &str) -> Option<UpstreamTlsConfig> {
+    let ca_bundle_file = env.optional::<String>(&format!("{}_CA_BUNDLE_FILE", prefix));
+    let client_certificate_file = env.optional::<String>(&format!("{}_CLIENT_CERTIFICATE_FILE", prefix));
+    let client_private_key_file = env.optional::<String>(&format!("{}_CLIENT_PRIVATE_KEY_FILE", prefix));
+    let insecure_disable_verification =
+        env.optional::<bool>(&format!("{}_INSECURE_DISABLE_VERIFICATION", prefix));
+    match (&ca_bundle_file, &client_certificate_file, &client_private_key_file, insecure_disable_verification) {
+        (None, None, None, None) => None,
+        _ => Some(UpstreamTlsConfig {
+            ca_bundle_file,
+            client_certificate_file,
+            client_private_key_file,
+            insecure_disable_verification,
+        }),
+    }
+}
+
+/// Loads the configuration from the TOML file or from environment variables. Problems that do not prevent Flexo from
+/// starting, e.g. unknown settings, are logged as warnings.
+pub fn try_load_config(source: &ConfigSource) -> Result<MirrorConfig, ConfigError> {
+    let (config, warnings) = try_load_config_with_warnings(source)?;
+    for warning in warnings {
+        warn!("{}", warning);
     }
+    Ok(config)
 }
 
-fn parse_bandwidth(s: &str) -> Option<u32> {
+/// Same as try_load_config, but returns the warnings instead of logging them.
+pub fn try_load_config_with_warnings(source: &ConfigSource) -> Result<(MirrorConfig, Vec<ConfigProblem>), ConfigError> {
+    match source {
+        ConfigSource::TomlFile(path) => mirror_config_from_toml(path),
+        ConfigSource::Environment => mirror_config_from_env(),
+    }
+}
+
+pub fn parse_bandwidth(s: &str) -> Option<u32> {
     let re = Regex::new(r"(?P<numeric_value>\d+) *(?P<si_unit>.*)/s").ok()?;
     let caps = re.captures(s)?;
     let numeric_value = caps["numeric_value"].parse::<u32>().ok()?;
@@ -344,3 +1026,37 @@ fn test_parse_bandwidth() {
 
     assert_eq!(Some(125_000_000), parse_bandwidth("1 GBit/s"));
 }
+
+#[test]
+fn test_time_window() {
+    let time = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+    let night = TimeWindow::parse("22:00-06:00").unwrap();
+    assert!(night.contains(time(23, 30)));
+    assert!(night.contains(time(2, 0)));
+    assert!(!night.contains(time(6, 0)));
+    assert!(!night.contains(time(12, 0)));
+    let morning = TimeWindow::parse("01:00 - 05:30").unwrap();
+    assert!(morning.contains(time(1, 0)));
+    assert!(!morning.contains(time(5, 30)));
+    assert_eq!(None, TimeWindow::parse("01:00"));
+}
+
+#[test]
+fn test_parse_toml_config_unknown_keys() {
+    let contents = test_fixtures::config_toml("\
+port = 7878
+low_sped_limit = 1000
+
+[upstream_bandwith]
+max_bandwidth = \"20 MBit/s\"
+
+[prefetch]
+enabled = true
+port = 7878
+");
+    let (config, warnings) = parse_toml_config(&contents).unwrap();
+    assert_eq!(7878, config.port);
+    let locations = warnings.iter().map(|w| (w.location.as_str(), w.line)).collect::<Vec<_>>();
+    let expected = vec![("low_sped_limit", Some(6)), ("upstream_bandwith", Some(8)), ("prefetch.port", Some(13))];
+    assert_eq!(expected, locations);
+}
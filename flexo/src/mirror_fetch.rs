@@ -1,7 +1,8 @@
 extern crate serde;
 use serde::Deserialize;
-use crate::mirror_config::MirrorsAutoConfig;
-use curl::easy::{Easy, HttpVersion};
+use crate::mirror_config::{MirrorConfig, MirrorsAutoConfig};
+use crate::mirror_flexo::curl_http_version;
+use curl::easy::Easy;
 use std::time::Duration;
 use std::str;
 use crate::MirrorResults;
@@ -145,13 +146,14 @@ impl Mirror {
     }
 }
 
-fn fetch_json(json_endpoint_uri: &str) -> Result<String, MirrorFetchError> {
+fn fetch_json(json_endpoint_uri: &str, properties: &MirrorConfig) -> Result<String, MirrorFetchError> {
     debug!("Fetch json from {:?}", json_endpoint_uri);
     try_num_attempts(INITIAL_CONNECTIVITY_NUM_ATTEMPTS, || {
         let mut received = Vec::new();
         let mut easy = Easy::new();
         easy.follow_location(true).unwrap();
         easy.url(json_endpoint_uri)?;
+        apply_upstream_options(&mut easy, properties)?;
         {
             let mut transfer = easy.transfer();
             transfer.write_function(|data| {
@@ -164,6 +166,17 @@ fn fetch_json(json_endpoint_uri: &str) -> Result<String, MirrorFetchError> {
     })
 }
 
+/// Applies the proxy and TLS settings used for all connections to remote mirrors.
+fn apply_upstream_options(easy: &mut Easy, properties: &MirrorConfig) -> Result<(), curl::Error> {
+    if let Some(upstream_proxy) = &properties.upstream_proxy_settings {
+        upstream_proxy.apply(easy)?;
+    }
+    if let Some(upstream_tls) = &properties.upstream_tls {
+        upstream_tls.apply(easy)?;
+    }
+    Ok(())
+}
+
 fn try_num_attempts<T, F, E>(max_num_attempts: i32, action: F) -> Result<T, E>
     where F: Fn() -> Result<T, E>, E: std::fmt::Debug
 {
@@ -186,8 +199,11 @@ fn try_num_attempts<T, F, E>(max_num_attempts: i32, action: F) -> Result<T, E>
     }
 }
 
-pub fn fetch_providers_from_json_endpoint(json_endpoint_uri: &str) -> Result<Vec<Mirror>, MirrorFetchError> {
-    let json = fetch_json(json_endpoint_uri)?;
+pub fn fetch_providers_from_json_endpoint(
+    json_endpoint_uri: &str,
+    properties: &MirrorConfig,
+) -> Result<Vec<Mirror>, MirrorFetchError> {
+    let json = fetch_json(json_endpoint_uri, properties)?;
     let mirror_list_option: MirrorListOption = serde_json::from_str(&json)?;
     let mirror_list: MirrorList = MirrorList::from(mirror_list_option);
     let mut mirrors = vec![];
@@ -201,7 +217,9 @@ pub fn fetch_providers_from_json_endpoint(json_endpoint_uri: &str) -> Result<Vec
     Ok(mirrors)
 }
 
-pub fn measure_latency(url: &str, timeout: Duration) -> Result<MirrorResults, curl::Error> {
+/// Measures the latency of the given mirror. If an upstream proxy is used, the latency is measured through the proxy,
+/// since this is the latency our downloads will experience.
+pub fn measure_latency(url: &str, timeout: Duration, properties: &MirrorConfig) -> Result<MirrorResults, curl::Error> {
     let mut easy = Easy::new();
     let url = url.to_owned() + "core/os/x86_64/core.db";
     easy.url(&url)?;
@@ -213,9 +231,9 @@ pub fn measure_latency(url: &str, timeout: Duration) -> Result<MirrorResults, cu
     let dns_cache_timeout = Duration::from_secs(3600 * 24);
     easy.dns_cache_timeout(dns_cache_timeout)?;
     easy.timeout(timeout)?;
-    // we use httparse to parse the headers, but httparse doesn't support HTTP/2 yet. HTTP/2 shouldn't provide
-    // any benefit for our use case (afaik), so this setting should not have any downsides.
-    easy.http_version(HttpVersion::V11)?;
+    // Use the same HTTP version as for downloads, since the latency may differ between HTTP versions.
+    easy.http_version(curl_http_version(properties))?;
+    apply_upstream_options(&mut easy, properties)?;
     easy.fail_on_error(true)?;
     easy.header_function(move |header: &[u8]| {
         // Exclude Cloudflare mirrors, because they mess up our latency results: Measuring the latency against a
@@ -7,25 +7,35 @@ use std::fs::OpenOptions;
 use std::io::BufWriter;
 use std::io::{ErrorKind, Read, Write};
 use std::num::ParseIntError;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::string::FromUtf8Error;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::os::unix::ffi::OsStrExt;
 
-use crossbeam::channel::Sender;
-use curl::easy::{Easy2, Handler, HttpVersion, WriteError};
+use chrono::NaiveDate;
+use curl::easy::{Easy2, Handler, HttpVersion, List, WriteError};
 use httparse::{Header, Status};
 use serde::{Deserialize, Serialize};
 use walkdir::WalkDir;
 
 use flexo::*;
+use flexo::progress::ProgressSender;
 
-use crate::mirror_config::{MirrorConfig, MirrorsAutoConfig};
+use crate::mirror_config::{MirrorConfig, MirrorsAutoConfig, UpstreamHttpVersion, UpstreamTlsConfig};
 use crate::{fs_utils, mirror_fetch};
+use crate::bandwidth::UpstreamTransfer;
 use crate::mirror_fetch::{MirrorProtocol, Mirror};
+use crate::credentials::{AuthorizationHeader, Credentials};
+use crate::package_version::PackageFile;
+use crate::response_header::{HeaderProgress, ResponseHeader};
+use crate::segmented_download::{plan_segments, SegmentDownload};
 use crate::str_path::StrPath;
+use crate::upstream_proxy::UpstreamProxy;
+use crate::upstream_transfers::{work_channel, WorkSender};
 use uuid::Uuid;
-use crate::mirror_flexo::RequestMethod::{Get, Post};
+use crate::mirror_flexo::RequestMethod::{Delete, Get, Post};
 
 // Since a restriction for the size of header fields is also implemented by web servers like NGINX or Apache,
 // we keep things simple by just setting a fixed buffer length.
@@ -42,6 +52,12 @@ const TEST_REQUEST_HEADER: &[u8] = "GET / HTTP/1.1\r\nHost: www.example.com\r\n\
 
 const CURLE_OPERATION_TIMEDOUT: u32 = 28;
 
+const CURLE_ABORTED_BY_CALLBACK: u32 = 42;
+
+/// The time a remote mirror has to send the header, after the connection has been established. If the header is
+/// not received in time, the download is aborted and another remote mirror is tried.
+const TIMEOUT_RECEIVE_HEADER: Duration = Duration::from_secs(4);
+
 const DEFAULT_LOW_SPEED_TIME_SECS: u64 = 2;
 
 const MAX_REDIRECTIONS: u32 = 40;
@@ -143,12 +159,14 @@ pub struct Request {
     pub resume_from: Option<u64>,
     pub path: StrPath,
     pub method: RequestMethod,
+    pub authorization: Option<AuthorizationHeader>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum RequestMethod {
     Get,
     Post,
+    Delete,
 }
 
 impl Request {
@@ -180,7 +198,8 @@ impl Request {
         }?;
         let request_method = match request.method {
             Some("GET") => Get,
-            Some("POST") if path == "/reset-metrics" => Post,
+            Some("POST") if path == "/reset-metrics" || path == "/prefetch" || path == "/reload-config" => Post,
+            Some("DELETE") if path.starts_with("/api/cache/") || path.starts_with("/api/cache?") => Delete,
             Some(method) => {
                 error!("Unsupported HTTP method: {}", method);
                 return Err(ClientError::UnsupportedHttpMethod(ClientStatus::no_response_headers_sent()));
@@ -190,11 +209,17 @@ impl Request {
                 return Err(ClientError::InvalidHeader(ClientStatus::no_response_headers_sent()));
             },
         };
+        let authorization = request.headers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case("authorization"))
+            .and_then(|h| str::from_utf8(h.value).ok())
+            .map(|v| AuthorizationHeader(v.to_owned()));
         let request_path = StrPath::new(path.to_owned());
         Ok(Self {
             path: request_path,
             method: request_method,
             resume_from,
+            authorization,
         })
     }
 }
@@ -207,13 +232,54 @@ pub struct DownloadProvider {
     // when the country is unknown and no results are available, which has already caused problems, see issue #58.
     pub mirror_results: MirrorResults,
     pub country_code: String,
+    #[serde(default)]
+    pub layout: ProviderLayout,
+    /// The TLS settings of a custom repo, applied in addition to the global settings.
+    #[serde(skip)]
+    pub tls: Option<UpstreamTlsConfig>,
+    /// The credentials of a custom repo, sent with each request.
+    #[serde(skip)]
+    pub credentials: Option<Credentials>,
+}
+
+/// Describes how the files are organized on the remote server.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Copy, Clone, Debug, Default)]
+pub enum ProviderLayout {
+    /// The same layout used by all regular mirrors, e.g. core/os/x86_64/glibc-2.33-3-x86_64.pkg.tar.zst
+    #[default]
+    Mirror,
+    /// The layout of the packages directory of the Arch Linux Archive, which keeps all versions of each package:
+    /// packages/g/glibc/glibc-2.33-3-x86_64.pkg.tar.zst
+    Archive,
+}
+
+impl ProviderLayout {
+    /// Returns the path of the given file relative to the provider's URI, or None if the file is not available
+    /// with this layout.
+    pub fn remote_path(&self, requested_path: &StrPath) -> Option<String> {
+        match self {
+            ProviderLayout::Mirror => Some(requested_path.to_str().to_owned()),
+            ProviderLayout::Archive => {
+                let filename = requested_path.as_ref().file_name()?.to_str()?;
+                let package_filename = filename.strip_suffix(".sig").unwrap_or(filename);
+                let package_file = PackageFile::from_filename(package_filename)?;
+                let initial = package_file.name.chars().next()?;
+                Some(format!("packages/{}/{}/{}", initial, package_file.name, filename))
+            }
+        }
+    }
 }
 
 impl Provider for DownloadProvider {
     type J = DownloadJob;
 
     fn new_job(&self, properties: &<<Self as Provider>::J as Job>::PR, order: DownloadOrder) -> DownloadJob {
-        let uri = uri_from_components(&self.uri, order.requested_path.to_str());
+        let remote_path = self.layout.remote_path(&order.requested_path).unwrap_or_else(|| {
+            // Orders are only assigned to providers that have a matching layout, see DownloadOrder::fallback_permitted.
+            warn!("Unable to map {} to the layout of {}", order.requested_path.to_str(), self.uri);
+            order.requested_path.to_str().to_owned()
+        });
+        let uri = uri_from_components(&self.uri, &remote_path);
         let provider = self.clone();
         let properties = properties.clone();
         DownloadJob {
@@ -349,43 +415,94 @@ impl Job for DownloadJob {
     ) -> JobResult<DownloadJob> {
         debug!("Fetch package from remote mirror: {}.", &self.uri);
         channel.handle.url(&self.uri).unwrap();
-        // we use httparse to parse the headers, but httparse doesn't support HTTP/2 yet. HTTP/2 shouldn't provide
-        // any benefit for our use case (afaik), so this setting should not have any downsides.
-        channel.handle.http_version(HttpVersion::V11).unwrap();
-        let connect_timeout = match properties.connect_timeout {
-            None => DEFAULT_CONNECT_TIMEOUT,
-            Some(timeout) => Duration::from_millis(timeout),
-        };
-        channel.handle.connect_timeout(connect_timeout).unwrap();
-        match properties.low_speed_limit() {
-            None => {},
-            Some(speed) => {
-                channel.handle.low_speed_limit(speed).unwrap();
-                let low_speed_time_secs = properties.low_speed_time_secs.unwrap_or(DEFAULT_LOW_SPEED_TIME_SECS);
-                debug!("Set low_speed_time to {} seconds.", low_speed_time_secs);
-                channel.handle.low_speed_time(std::time::Duration::from_secs(low_speed_time_secs)).unwrap();
-            },
+        apply_transfer_options(&mut channel.handle, properties).unwrap();
+        if let Some(tls) = &self.provider.tls {
+            tls.apply(&mut channel.handle).unwrap();
         }
-        match properties.max_speed_limit {
-            None => {
-                debug!("No speed limit was set.")
-            },
-            Some(speed) => {
-                info!("Apply speed limit of {}/s", size_to_human_readable(speed));
-                channel.handle.max_recv_speed(speed).unwrap();
-            },
+        if let Some(credentials) = &self.provider.credentials {
+            // curl does not send this header to other hosts if the custom repo redirects us.
+            let mut headers = List::new();
+            headers.append(&format!("Authorization: {}", credentials.authorization_header_value())).unwrap();
+            channel.handle.http_headers(headers).unwrap();
         }
-        channel.handle.follow_location(true).unwrap();
-        channel.handle.max_redirections(MAX_REDIRECTIONS).unwrap();
+        channel.handle.get_mut().header_deadline =
+            Some(Instant::now() + connect_timeout(properties) + TIMEOUT_RECEIVE_HEADER);
+        channel.handle.progress(true).unwrap();
         match channel.progress_indicator() {
-            None => {},
+            // The handle may have been used to resume another download before.
+            None => channel.handle.resume_from(0).unwrap(),
             Some(start) => {
                 info!("Resume download of {} from byte {}", &self.uri, start);
                 channel.handle.resume_from(start).unwrap();
             }
         }
         debug!("Start download from {}", self.provider.identifier());
-        match channel.handle.perform() {
+        // Wait for a connection to the same remote mirror that is being established, rather than opening another
+        // one, so that the download can be multiplexed over that connection if it turns out to support HTTP/2.
+        channel.handle.pipewait(true).unwrap();
+        let download_state = channel.handle.get_mut();
+        if self.order.is_cacheable() && download_state.size_written() > 0 {
+            download_state.expected_complete_size =
+                get_complete_size_from_cfs_file(&self.order.filepath(properties));
+        }
+        let (tx_work, rx_work) = work_channel();
+        download_state.tx_work = Some(tx_work);
+        let mut payload_writer = match PayloadWriter::new(download_state) {
+            Ok(w) => w,
+            Err(e) => {
+                error!("Unable to write {}: {:?}", &self.uri, e);
+                return JobResult::UnexpectedInternalError;
+            }
+        };
+        let mut segment_downloads = None;
+        let mut work_error = None;
+        let transfer = properties.upstream_transfers.perform(channel.handle, rx_work, |work| {
+            match self.do_work(work, properties, &mut payload_writer, &mut segment_downloads) {
+                Ok(()) => true,
+                Err(e) => {
+                    work_error = Some(e);
+                    false
+                }
+            }
+        });
+        let (handle, perform_result) = match transfer {
+            Ok(transfer) => transfer,
+            Err(e) => {
+                error!("Unable to download from {}: {}", &self.uri, e);
+                return JobResult::UnexpectedInternalError;
+            }
+        };
+        channel.handle = handle;
+        let download_state = channel.handle.get_mut();
+        download_state.tx_work = None;
+        download_state.helper_selector = None;
+        let write_result = match work_error {
+            Some(e) => Err(e),
+            None => payload_writer.flush(),
+        };
+        if let Err(e) = write_result {
+            error!("Unable to write {}: {:?}", &self.uri, e);
+            return JobResult::UnexpectedInternalError;
+        }
+        if let Some(primary_end) = download_state.primary_end.take() {
+            let size_written = download_state.size_written();
+            match segment_downloads {
+                Some(downloads) if size_written == primary_end => {
+                    return self.merge_segments(channel, properties, payload_writer, downloads);
+                }
+                None if size_written == primary_end => {
+                    // The segment downloads could not be started, so we download the remaining segments ourselves.
+                    download_state.reset_header();
+                    return self.serve_from_provider(channel, properties);
+                }
+                _ => {
+                    // Dropping the segment downloads cancels them.
+                    debug!("Download from {} has stopped at byte {} before the end of its segment.",
+                           self.provider.identifier(), size_written);
+                }
+            }
+        }
+        match perform_result {
             Ok(()) => {
                 let response_code = channel.handle.response_code().unwrap();
                 debug!("Download completed: {} replied with status code {}.",
@@ -406,6 +523,9 @@ impl Job for DownloadJob {
             Err(e) => {
                 if e.code() == CURLE_OPERATION_TIMEDOUT {
                     warn!("Unable to download from {:?}: Timeout reached. Try another remote mirror.", &self.uri);
+                } else if e.code() == CURLE_ABORTED_BY_CALLBACK && channel.handle.get_ref().header_timed_out() {
+                    warn!("Unable to download from {:?}: No header received within {:?}. Try another remote mirror.",
+                          &self.uri, TIMEOUT_RECEIVE_HEADER);
                 } else {
                     warn!("An unknown error occurred while downloading from remote mirror {:?}: {:?}", &self.uri, e);
                 }
@@ -425,6 +545,16 @@ impl Job for DownloadJob {
         }
     }
 
+    fn serve_from_providers(
+        self,
+        mut channel: DownloadChannel,
+        properties: &MirrorConfig,
+        helper_selector: HelperSelector<DownloadProvider>,
+    ) -> JobResult<DownloadJob> {
+        channel.handle.get_mut().helper_selector = Some(helper_selector);
+        self.serve_from_provider(channel, properties)
+    }
+
     fn handle_error(self, error: OrderError) -> JobResult<Self> {
         match error {
             OrderError::IoError(e) if e.kind() == ErrorKind::NotFound => {
@@ -456,14 +586,13 @@ impl Job for DownloadJob {
             }
         };
         let size_written = f.metadata()?.len();
-        let buf_writer = BufWriter::new(f);
         let header_state = HeaderState {
-            received_header: vec![],
+            received_header: ResponseHeader::new(via_proxy_tunnel(properties)),
             header_success: None,
         };
         let filename = path.file_name().unwrap().to_str().unwrap().to_owned();
         let file_state = FileState  {
-            buf_writer,
+            file: f,
             size_written,
             filename,
         };
@@ -575,7 +704,7 @@ pub fn get_complete_size_from_cfs_file(path: &Path) -> Option<u64> {
     }
 }
 
-fn cfs_path_from_pkg_path(path: &Path) -> Option<PathBuf> {
+pub fn cfs_path_from_pkg_path(path: &Path) -> Option<PathBuf> {
     match path.file_name() {
         None => {
             warn!("Unable to determine file name from path {:?}", path);
@@ -600,6 +729,9 @@ pub struct DownloadOrder {
 pub enum Cacheability {
     NonCacheable(Uuid),
     Cacheable,
+    /// A database file as it was available at the given date. Unlike the database files currently available on the
+    /// mirrors, those files never change, so they can be cached.
+    Snapshot(NaiveDate),
 }
 
 impl DownloadOrder {
@@ -617,6 +749,19 @@ impl DownloadOrder {
         }
     }
 
+    /// Creates an order for a file as it was available at the given date. Packages never change, so they are stored
+    /// in the regular cache, while database files are stored separately for each date.
+    pub fn snapshot(requested_path: StrPath, date: NaiveDate) -> Self {
+        if DownloadOrder::is_cacheable_path(&requested_path) {
+            DownloadOrder::new(requested_path)
+        } else {
+            Self {
+                requested_path,
+                cacheability: Cacheability::Snapshot(date),
+            }
+        }
+    }
+
     fn is_cacheable_path(path: &StrPath) -> bool {
         !(path.to_str().ends_with(".db") ||
             path.to_str().ends_with(".db.sig") ||
@@ -625,13 +770,148 @@ impl DownloadOrder {
     }
 }
 
+impl DownloadJob {
+    /// Appends the segments downloaded by other remote mirrors to the file, after this job's remote mirror has
+    /// provided the first segment.
+    fn merge_segments(
+        self,
+        mut channel: DownloadChannel,
+        properties: &MirrorConfig,
+        mut payload_writer: PayloadWriter,
+        downloads: Vec<(Arc<DownloadProvider>, SegmentDownload)>,
+    ) -> JobResult<DownloadJob> {
+        debug!("Received the first segment of {}: Merge the remaining segments.", &self.uri);
+        for (_helper_provider, download) in downloads {
+            let download_state = channel.handle.get_mut();
+            let transfer_result = download.transfer(|data| {
+                payload_writer.append(data)?;
+                download_state.count_written(data.len());
+                Ok(())
+            });
+            if let Err(e) = transfer_result {
+                // The remaining segment downloads are dropped, and thereby cancelled, when we leave the loop.
+                warn!("Unable to download segment of {}: {}. Continue with {}.",
+                      &self.uri, e, self.provider.identifier());
+                if let Err(e) = payload_writer.flush() {
+                    error!("Unable to write {}: {:?}", &self.uri, e);
+                    return JobResult::UnexpectedInternalError;
+                }
+                download_state.reset_header();
+                return self.serve_from_provider(channel, properties);
+            }
+        }
+        if let Err(e) = payload_writer.flush() {
+            error!("Unable to write {}: {:?}", &self.uri, e);
+            return JobResult::UnexpectedInternalError;
+        }
+        let size = channel.progress_indicator().unwrap();
+        JobResult::Complete(JobCompleted::new(channel, self.provider, size as i64))
+    }
+
+    /// Does the work handed over by the callbacks of the download, see [`DownloadWork`].
+    fn do_work(
+        &self,
+        work: DownloadWork,
+        properties: &MirrorConfig,
+        payload_writer: &mut PayloadWriter,
+        segment_downloads: &mut Option<Vec<(Arc<DownloadProvider>, SegmentDownload)>>,
+    ) -> std::io::Result<()> {
+        match work {
+            DownloadWork::Payload(data) => payload_writer.append(&data)?,
+            DownloadWork::CompleteSize(complete_size) => {
+                create_cfs_file(&self.order.filepath(properties), complete_size);
+            }
+            DownloadWork::Segments { segments, complete_size } => {
+                let downloads = segments.into_iter()
+                    .map(|(provider, uri, segment)| {
+                        SegmentDownload::start(uri, segment, complete_size, properties).map(|d| (provider, d))
+                    })
+                    .collect::<std::io::Result<Vec<_>>>();
+                match downloads {
+                    Ok(downloads) => *segment_downloads = Some(downloads),
+                    Err(e) => warn!("Unable to start segment download: {:?}", e),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Applies the settings for downloads from remote mirrors.
+pub fn apply_transfer_options<H>(handle: &mut Easy2<H>, properties: &MirrorConfig) -> Result<(), curl::Error> {
+    handle.http_version(curl_http_version(properties))?;
+    handle.connect_timeout(connect_timeout(properties))?;
+    match properties.low_speed_limit() {
+        None => {},
+        Some(_) if properties.upstream_bandwidth_limiter.is_limited() => {
+            // Downloads may be slowed down by the upstream bandwidth budget, which must not cause Flexo to
+            // switch to another mirror.
+            debug!("Ignore low_speed_limit since the upstream bandwidth is limited.");
+        }
+        Some(speed) => {
+            handle.low_speed_limit(speed)?;
+            let low_speed_time_secs = properties.low_speed_time_secs.unwrap_or(DEFAULT_LOW_SPEED_TIME_SECS);
+            debug!("Set low_speed_time to {} seconds.", low_speed_time_secs);
+            handle.low_speed_time(std::time::Duration::from_secs(low_speed_time_secs))?;
+        },
+    }
+    match properties.max_speed_limit {
+        None => {
+            debug!("No speed limit was set.")
+        },
+        Some(speed) => {
+            info!("Apply speed limit of {}/s", size_to_human_readable(speed));
+            handle.max_recv_speed(speed)?;
+        },
+    }
+    if let Some(upstream_proxy) = &properties.upstream_proxy_settings {
+        upstream_proxy.apply(handle)?;
+    }
+    if let Some(upstream_tls) = &properties.upstream_tls {
+        upstream_tls.apply(handle)?;
+    }
+    handle.follow_location(true)?;
+    handle.max_redirections(MAX_REDIRECTIONS)?;
+    Ok(())
+}
+
+/// Whether curl may pass the reply of a proxy to a CONNECT request to the header callback.
+pub fn via_proxy_tunnel(properties: &MirrorConfig) -> bool {
+    properties.upstream_proxy_settings.as_ref().map(UpstreamProxy::is_http_proxy).unwrap_or(false)
+}
+
+/// HTTP/2 is used by default if libcurl supports it.
+pub fn curl_http_version(properties: &MirrorConfig) -> HttpVersion {
+    match properties.upstream_http_version {
+        Some(UpstreamHttpVersion::Http11) => HttpVersion::V11,
+        Some(UpstreamHttpVersion::Http2) | None if curl::Version::get().feature_http2() => HttpVersion::V2TLS,
+        Some(UpstreamHttpVersion::Http2) | None => HttpVersion::V11,
+    }
+}
+
+/// The longest time a client waits for a download to start: The job may wait for a thread, and it tries remote
+/// mirrors until the retry timeout has elapsed. The last regular remote mirror and the fallback mirror each have
+/// until their header deadline to send the header.
+pub fn content_length_timeout(properties: &MirrorConfig) -> Duration {
+    let header_timeout = connect_timeout(properties) + TIMEOUT_RECEIVE_HEADER;
+    properties.download_thread_pool_config().queue_timeout + properties.failover_budget().retry_timeout +
+        header_timeout * 2
+}
+
+fn connect_timeout(properties: &MirrorConfig) -> Duration {
+    match properties.connect_timeout {
+        None => DEFAULT_CONNECT_TIMEOUT,
+        Some(timeout) => Duration::from_millis(timeout),
+    }
+}
+
 impl Order for DownloadOrder {
     type J = DownloadJob;
 
     fn new_channel(
         self,
         properties: MirrorConfig,
-        tx: Sender<FlexoProgress>,
+        tx: ProgressSender,
         last_chance: bool,
     ) -> Result<DownloadChannel, <Self::J as Job>::OE> {
         let download_state = DownloadState::new(self, properties, tx, last_chance)?;
@@ -643,7 +923,7 @@ impl Order for DownloadOrder {
     fn reuse_channel(
         self,
         properties: MirrorConfig,
-        tx: Sender<FlexoProgress>,
+        tx: ProgressSender,
         last_chance: bool,
         previous_channel: DownloadChannel,
     ) -> Result<DownloadChannel, <Self::J as Job>::OE> {
@@ -659,6 +939,7 @@ impl Order for DownloadOrder {
         match self.cacheability {
             Cacheability::NonCacheable(_) => false,
             Cacheability::Cacheable => true,
+            Cacheability::Snapshot(_) => true,
         }
     }
 
@@ -675,6 +956,11 @@ impl Order for DownloadOrder {
           self.requested_path.to_str().ends_with(".files.sig"))
     }
 
+    fn fallback_permitted(&self) -> bool {
+        // The only fallback provider we use is the Arch Linux Archive, which provides packages, but no database files.
+        self.is_cacheable() && ProviderLayout::Archive.remote_path(&self.requested_path).is_some()
+    }
+
     fn description(&self) -> &str {
         self.requested_path.to_str()
     }
@@ -687,6 +973,11 @@ impl DownloadOrder {
             Cacheability::Cacheable => {
                 Path::new(&properties.cache_directory).join(&self.requested_path)
             }
+            Cacheability::Snapshot(date) => {
+                Path::new(properties.snapshot_directory())
+                    .join(date.format("%Y-%m-%d").to_string())
+                    .join(&self.requested_path)
+            }
             Cacheability::NonCacheable(unique_id) => {
                 let path = Path::join(Path::new(UNCACHEABLE_DIRECTORY), &self.requested_path);
                 fs_utils::create_dir_unless_exists(path.parent().unwrap());
@@ -699,7 +990,9 @@ impl DownloadOrder {
 
 #[derive(Debug)]
 pub struct FileState {
-    buf_writer: BufWriter<File>,
+    /// The payload is written by the job thread, see [`PayloadWriter`].
+    file: File,
+    /// The number of bytes that have been handed over to be written.
     size_written: u64,
     filename: String,
 }
@@ -713,7 +1006,7 @@ pub struct DownloadJobResources {
 
 #[derive(Debug)]
 pub struct HeaderState {
-    received_header: Vec<u8>,
+    received_header: ResponseHeader,
     header_success: Option<HeaderOutcome>,
 }
 
@@ -725,17 +1018,79 @@ enum HeaderOutcome {
     Unavailable,
 }
 
+/// Work of the callbacks of a download that is done by the job thread, so that the thread driving all upstream
+/// transfers never waits for the disk.
+#[derive(Debug)]
+enum DownloadWork {
+    /// The next part of the payload, to be appended to the file.
+    Payload(Vec<u8>),
+    /// The header has been received: The complete size of a cacheable file is stored in its CFS file.
+    CompleteSize(u64),
+    /// The remaining segments are to be downloaded from the given URIs, in order. The remote mirrors count as being
+    /// in use until their segment has been merged.
+    Segments {
+        segments: Vec<(Arc<DownloadProvider>, String, Range<u64>)>,
+        complete_size: u64,
+    },
+}
+
+/// Appends the payload to the file and informs the consumer about the progress. Used by the job thread, while the
+/// transfer itself is performed by the thread that drives all upstream transfers.
+struct PayloadWriter {
+    buf_writer: BufWriter<File>,
+    tx: ProgressSender,
+}
+
+impl PayloadWriter {
+    fn new(download_state: &DownloadState) -> std::io::Result<Self> {
+        let job_state = &download_state.job_state;
+        let file = job_state.job_resources.as_ref().unwrap().file_state.file.try_clone()?;
+        Ok(PayloadWriter {
+            buf_writer: BufWriter::new(file),
+            tx: job_state.tx.clone(),
+        })
+    }
+
+    fn append(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.buf_writer.write_all(data)?;
+        let len = self.buf_writer.get_ref().metadata()?.len();
+        self.tx.send(FlexoProgress::Progress(len));
+        Ok(())
+    }
+
+    /// Writes the buffered part of the payload to the file, so that consumers can be served the complete file.
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.buf_writer.flush()?;
+        let len = self.buf_writer.get_ref().metadata()?.len();
+        self.tx.send(FlexoProgress::Progress(len));
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 struct DownloadState {
     job_state: JobState<DownloadJob>,
     properties: MirrorConfig,
+    /// The download is aborted if the header has not been received until this point in time.
+    header_deadline: Option<Instant>,
+    /// The complete size of a partially downloaded file, as stored in its CFS file: A remote mirror that continues
+    /// the download must send the same file.
+    expected_complete_size: Option<u64>,
+    /// Selects the remote mirrors that download segments of the file in parallel.
+    helper_selector: Option<HelperSelector<DownloadProvider>>,
+    /// Set if the file is downloaded in segments: This download then only provides the first segment, which ends here.
+    primary_end: Option<u64>,
+    /// Set while the transfer is paused because the upstream bandwidth budget is used up.
+    upstream_resume_at: Option<Instant>,
+    /// Set while a transfer is in progress: The callbacks hand over their work to the job thread.
+    tx_work: Option<WorkSender<DownloadWork>>,
 }
 
 impl DownloadState {
     pub fn new(
         order: DownloadOrder,
         properties: MirrorConfig,
-        tx: Sender<FlexoProgress>,
+        tx: ProgressSender,
         last_chance: bool,
     ) -> std::io::Result<Self> {
         let download_job_resources = DownloadJob::acquire_resources(&order, &properties, last_chance)?;
@@ -744,17 +1099,84 @@ impl DownloadState {
             job_resources: Some(download_job_resources),
             tx,
         };
-        Ok(DownloadState { job_state, properties })
+        Ok(DownloadState {
+            job_state,
+            properties,
+            header_deadline: None,
+            expected_complete_size: None,
+            helper_selector: None,
+            primary_end: None,
+            upstream_resume_at: None,
+            tx_work: None,
+        })
     }
 
     pub fn replace(&mut self, new_state: Self) {
         *self = new_state;
     }
+
+    fn size_written(&self) -> u64 {
+        self.job_state.job_resources.as_ref().unwrap().file_state.size_written
+    }
+
+    /// Counts the given number of bytes as written, once they have been handed over to the [`PayloadWriter`].
+    fn count_written(&mut self, len: usize) {
+        self.job_state.job_resources.as_mut().unwrap().file_state.size_written += len as u64;
+    }
+
+    /// Prepares this state for a new request to continue the download.
+    fn reset_header(&mut self) {
+        let header_state = &mut self.job_state.job_resources.as_mut().unwrap().header_state;
+        header_state.received_header.clear();
+        header_state.header_success = None;
+    }
+
+    /// Splits the remainder of the file into segments, if it is large enough and other remote mirrors are available.
+    /// This download then only provides the first segment, while the job thread starts downloading all other segments
+    /// from the selected helper providers.
+    fn plan_segment_downloads(&mut self, start: u64, complete_size: u64) -> Option<DownloadWork> {
+        let config = self.properties.segmented_downloads_config()?;
+        let helper_selector = self.helper_selector.take()?;
+        let max_segments = std::cmp::min(config.max_segments(), helper_selector.max_providers() + 1);
+        let segments = plan_segments(start..complete_size, max_segments, config.min_segment_size());
+        if segments.len() < 2 {
+            return None;
+        }
+        let helper_providers = helper_selector.select(segments.len() - 1);
+        // Fewer segments are needed if not enough remote mirrors are available.
+        let segments = plan_segments(start..complete_size, helper_providers.len() + 1, config.min_segment_size());
+        if segments.len() < 2 {
+            return None;
+        }
+        info!("Download {} in {} segments.", self.job_state.order.requested_path.to_str(), segments.len());
+        self.primary_end = Some(segments[0].end);
+        let segments = helper_providers.into_iter().zip(segments[1..].iter()).map(|(provider, segment)| {
+            let uri = provider.new_job(&self.properties, self.job_state.order.clone()).uri;
+            (provider, uri, segment.clone())
+        }).collect();
+        Some(DownloadWork::Segments { segments, complete_size })
+    }
+
+    fn header_timed_out(&self) -> bool {
+        let header_received = self.job_state.job_resources.as_ref()
+            .map(|job_resources| job_resources.header_state.header_success.is_some())
+            .unwrap_or(false);
+        match self.header_deadline {
+            Some(deadline) => !header_received && Instant::now() > deadline,
+            None => false,
+        }
+    }
+}
+
+impl UpstreamTransfer for DownloadState {
+    fn resume_at(&mut self) -> &mut Option<Instant> {
+        &mut self.upstream_resume_at
+    }
 }
 
 impl Handler for DownloadState {
     fn write(&mut self, data: &[u8]) -> Result<usize, WriteError> {
-        let mut job_resources = self.job_state.job_resources.as_mut().unwrap();
+        let job_resources = self.job_state.job_resources.as_mut().unwrap();
         match job_resources.header_state.header_success {
             Some(HeaderOutcome::Ok(_content_length)) => {},
             Some(HeaderOutcome::Unavailable) => {
@@ -771,43 +1193,44 @@ impl Handler for DownloadState {
         if job_resources.file_state.size_written == 0 {
             debug!("Begin to transfer body to file {}", self.job_state.order.requested_path.to_str());
         }
-        job_resources.file_state.size_written += data.len() as u64;
-        match job_resources.file_state.buf_writer.write_all(data) {
-            Ok(()) => {
-                let len = job_resources.file_state.buf_writer.get_ref().metadata().unwrap().len();
-                let _result = self.job_state.tx.send(FlexoProgress::Progress(len));
-                Ok(data.len())
-            },
-            Err(e) => {
-                error!("Error while writing data: {:?}", e);
-                Err(WriteError::Pause)
+        let data = match self.primary_end {
+            None => data,
+            Some(primary_end) => {
+                // Returning fewer bytes than we were given stops the download at the end of the first segment.
+                let remaining = primary_end.saturating_sub(job_resources.file_state.size_written);
+                &data[..std::cmp::min(data.len() as u64, remaining) as usize]
             }
+        };
+        let tx_work = self.tx_work.as_ref().unwrap();
+        if let Some(resume_at) = tx_work.backlog_resume_at() {
+            self.upstream_resume_at = Some(resume_at);
+            return Err(WriteError::Pause);
+        }
+        if let Some(resume_at) = self.properties.upstream_bandwidth_limiter.try_consume(data.len() as u64) {
+            self.upstream_resume_at = Some(resume_at);
+            return Err(WriteError::Pause);
+        }
+        if !tx_work.send(DownloadWork::Payload(data.to_vec())) {
+            // The job thread was unable to write the payload: Returning fewer bytes than we were given aborts the
+            // download.
+            return Ok(0);
         }
+        self.count_written(data.len());
+        Ok(data.len())
     }
 
     fn header(&mut self, data: &[u8]) -> bool {
         let job_resources = self.job_state.job_resources.as_mut().unwrap();
-        job_resources.header_state.received_header.extend(data);
-
-        let mut headers: [Header; MAX_HEADER_COUNT] = [httparse::EMPTY_HEADER; MAX_HEADER_COUNT];
-        let mut req: httparse::Response = httparse::Response::new(&mut headers);
-        let result: std::result::Result<httparse::Status<usize>, httparse::Error> =
-            req.parse(job_resources.header_state.received_header.as_slice());
-
-        match result {
-            Ok(Status::Complete(_header_size)) => {
+        match job_resources.header_state.received_header.push_line(data) {
+            HeaderProgress::Complete(code) => {
                 debug!("Received complete header from remote mirror");
-                let code = req.code.unwrap();
                 // FIXME this is too noisy: Use log level debug! once #93 has been fixed.
                 info!("HTTP response code is {}", code);
                 if code == 200 || code == 206 {
-                    let maybe_content_length = req.headers.iter().find_map(|header|
-                        if header.name.eq_ignore_ascii_case("content-length") {
-                            str::from_utf8(header.value).ok().and_then(|h| h.parse::<u64>().ok())
-                        } else {
-                            None
-                        }
-                    );
+                    let received_header = &job_resources.header_state.received_header;
+                    let maybe_content_length = received_header.field("content-length")
+                        .and_then(|h| h.parse::<u64>().ok());
+                    let content_range = received_header.field("content-range").and_then(parse_content_range);
                     let content_length = match maybe_content_length {
                         None => {
                             warn!("Remote mirror did not send a valid Content-Length header.");
@@ -818,54 +1241,126 @@ impl Handler for DownloadState {
                     // FIXME this is too noisy: Use log level debug! once #93 has been fixed.
                     info!("Server replied with content length {} for {}",
                         content_length, self.job_state.order.requested_path.to_str());
+                    if job_resources.file_state.size_written > 0 {
+                        // We continue a download that was started with another remote mirror. Make sure that
+                        // this remote mirror has the same file, otherwise we would end up with a corrupt file.
+                        let expected_complete_size = self.expected_complete_size;
+                        if !is_valid_resumption(code, content_range, job_resources.file_state.size_written,
+                                                expected_complete_size) {
+                            warn!("Remote mirror replied with status {} and Content-Range {:?}, but we expected \
+                                   to continue at byte {} of a file of size {:?}: Try another remote mirror.",
+                                  code, content_range, job_resources.file_state.size_written,
+                                  expected_complete_size);
+                            return false;
+                        }
+                    }
                     job_resources.header_state.header_success = Some(HeaderOutcome::Ok(content_length));
                     // TODO it may be safer to obtain the size_written from the job_state, i.e., add a new item to
                     // the job state that stores the size the job should be started with. With the current
                     // implementation, we assume that the header method is always called before anything is written to
                     // the file.
                     let size_written = self.job_state.job_resources.as_ref().unwrap().file_state.size_written;
-                    let path = self.job_state.order.filepath(&self.properties);
                     // TODO stick to a consistent terminology, everywhere: client_content_length = the content length
                     // as communicated to the client, i.e., what the client receives in his headers.
                     // provider_content_length = the content length we send to the provider.
                     let client_content_length = size_written + content_length;
                     if self.job_state.order.is_cacheable() {
-                        create_cfs_file(&path, client_content_length);
+                        let work = DownloadWork::CompleteSize(client_content_length);
+                        if !self.tx_work.as_ref().unwrap().send(work) {
+                            return false;
+                        }
                     }
                     debug!("Sending content length: {}", client_content_length);
-                    let _ = self.job_state.tx.send(FlexoProgress::JobSize(client_content_length));
+                    self.job_state.tx.send(FlexoProgress::JobSize(client_content_length));
+                    if self.job_state.order.is_cacheable() {
+                        if let Some(work) = self.plan_segment_downloads(size_written, client_content_length) {
+                            if !self.tx_work.as_ref().unwrap().send(work) {
+                                return false;
+                            }
+                        }
+                    }
                 }  else if code == 416 {
                     // If the requested file was already cached, but we don't know if the cached file has been
                     // downloaded completely or only partially, we send the Content-Range header in order to not
                     // download anything we already have available in cache.
                     // If the server responds with 416, we assume that the cached file was already complete.
                     job_resources.header_state.header_success = Some(HeaderOutcome::Unavailable);
-                    let _ = self.job_state.tx.send(FlexoProgress::Completed);
+                    self.job_state.tx.send(FlexoProgress::Completed);
                 } else if (300..400).contains(&code) {
+                    // We don't care about the redirect header, we're still waiting for the final header we receive
+                    // after all redirects are finished. The received header is reset when the next one begins.
                     debug!("Server sent a redirect: Waiting for next header.");
-                    // Remove the header data we have received so far: We don't care about the redirect header,
-                    // we're still waiting for the final header we receive after all redirects are finished.
-                    job_resources.header_state.received_header.clear();
                 } else if !job_resources.last_chance {
                     debug!("Sending HeaderOutcome::Unavailable");
                     job_resources.header_state.header_success = Some(HeaderOutcome::Unavailable);
                 } else if job_resources.last_chance {
                     debug!("Sending HeaderOutcome::Unavailable and FlexoProgress::Unavailable");
                     job_resources.header_state.header_success = Some(HeaderOutcome::Unavailable);
-                    let _ = self.job_state.tx.send(FlexoProgress::Unavailable);
+                    self.job_state.tx.send(FlexoProgress::Unavailable);
                 }
             }
-            Ok(Status::Partial) => {
+            HeaderProgress::Partial => {
                 // nothing to do, wait until this function is invoked again.
             }
-            Err(e) => {
-                error!("Unable to parse header: {:?}", e);
+            HeaderProgress::Invalid => {
+                error!("Unable to parse header: {:?}", String::from_utf8_lossy(data));
                 return false;
             }
         }
 
         true
     }
+
+    fn progress(&mut self, _dltotal: f64, _dlnow: f64, _ultotal: f64, _ulnow: f64) -> bool {
+        // Returning false aborts the download, so that the job can continue with the next remote mirror.
+        !self.header_timed_out()
+    }
+}
+
+/// The range of a partial response, as sent in the Content-Range header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentRange {
+    pub first_byte: u64,
+    pub last_byte: u64,
+    /// None if the server does not know the complete size.
+    pub complete_size: Option<u64>,
+}
+
+/// Parses values such as "bytes 100-199/200" or "bytes 100-199/*".
+pub fn parse_content_range(value: &str) -> Option<ContentRange> {
+    let range = value.trim().strip_prefix("bytes ")?;
+    let (range, complete_size) = range.split_once('/')?;
+    let (first_byte, last_byte) = range.split_once('-')?;
+    let complete_size = match complete_size.trim() {
+        "*" => None,
+        s => Some(s.parse::<u64>().ok()?),
+    };
+    Some(ContentRange {
+        first_byte: first_byte.trim().parse::<u64>().ok()?,
+        last_byte: last_byte.trim().parse::<u64>().ok()?,
+        complete_size,
+    })
+}
+
+/// Returns true if the response continues the partially downloaded file exactly where we stopped.
+pub fn is_valid_resumption(
+    code: u16,
+    content_range: Option<ContentRange>,
+    size_written: u64,
+    expected_complete_size: Option<u64>,
+) -> bool {
+    match (code, content_range) {
+        (206, Some(content_range)) => {
+            let size_matches = match (content_range.complete_size, expected_complete_size) {
+                (Some(actual), Some(expected)) => actual == expected,
+                // Without the complete size, we have nothing to compare.
+                _ => true,
+            };
+            content_range.first_byte == size_written && size_matches
+        }
+        // The server has ignored our range request and sends the entire file.
+        _ => false,
+    }
 }
 
 fn create_cfs_file(path: &Path, complete_filesize: u64) {
@@ -974,6 +1469,7 @@ pub fn rated_providers_retry(
     mirrors_auto: MirrorsAutoConfig,
     country_filter: &CountryFilter,
     limit: Limit,
+    properties: &MirrorConfig,
 ) -> Vec<DownloadProvider> {
     for i in 0..LATENCY_TEST_NUM_ATTEMPTS {
         let mirrors_auto = match i {
@@ -987,7 +1483,7 @@ pub fn rated_providers_retry(
                 relaxed
             },
         };
-        let providers = rated_providers(mirrors.clone(), &mirrors_auto, &country_filter, limit);
+        let providers = rated_providers(mirrors.clone(), &mirrors_auto, &country_filter, limit, properties);
         if !providers.is_empty() {
             return providers;
         }
@@ -1001,6 +1497,7 @@ pub fn rated_providers(
     mirrors_auto: &MirrorsAutoConfig,
     country_filter: &CountryFilter,
     limit: Limit,
+    properties: &MirrorConfig,
 ) -> Vec<DownloadProvider> {
     mirrors.sort_by(|a, b| a.score.cmp(&b.score));
     debug!("Mirrors will be filtered according to the following criteria: {:#?}", mirrors_auto);
@@ -1020,7 +1517,7 @@ pub fn rated_providers(
     let mut num_successes = 0;
     let mut num_failures = 0;
     for mirror in filtered_mirror_urls.into_iter() {
-        let is_success = match mirror_fetch::measure_latency(&mirror.url, request_timeout) {
+        let is_success = match mirror_fetch::measure_latency(&mirror.url, request_timeout, properties) {
             Err(e) => {
                 num_failures += 1;
                 if e.code() == CURLE_OPERATION_TIMEDOUT {
@@ -1053,6 +1550,9 @@ pub fn rated_providers(
             name: mirror.url,
             mirror_results,
             country_code: mirror.country_code,
+            layout: ProviderLayout::Mirror,
+            tls: None,
+            credentials: None,
         }
     }).collect()
 }
@@ -1179,6 +1679,31 @@ mod tests {
         assert_eq!(result, Err(ClientError::BufferSizeExceeded));
     }
 
+    #[test]
+    fn test_archive_layout() {
+        let path = StrPath::new("core/os/x86_64/lib32-glibc-2.33-3-x86_64.pkg.tar.zst".to_owned());
+        let expected = "packages/l/lib32-glibc/lib32-glibc-2.33-3-x86_64.pkg.tar.zst";
+        assert_eq!(Some(expected.to_owned()), ProviderLayout::Archive.remote_path(&path));
+        let path = StrPath::new("core/os/x86_64/lib32-glibc-2.33-3-x86_64.pkg.tar.zst.sig".to_owned());
+        let expected = "packages/l/lib32-glibc/lib32-glibc-2.33-3-x86_64.pkg.tar.zst.sig";
+        assert_eq!(Some(expected.to_owned()), ProviderLayout::Archive.remote_path(&path));
+        let path = StrPath::new("core/os/x86_64/core.db".to_owned());
+        assert_eq!(None, ProviderLayout::Archive.remote_path(&path));
+    }
+
+    #[test]
+    fn test_resumption_validated() {
+        let content_range = parse_content_range("bytes 100-199/200");
+        assert_eq!(Some(ContentRange { first_byte: 100, last_byte: 199, complete_size: Some(200) }), content_range);
+        assert!(is_valid_resumption(206, content_range, 100, Some(200)));
+        // A different version of the file, which has a different size.
+        assert!(!is_valid_resumption(206, parse_content_range("bytes 100-249/250"), 100, Some(200)));
+        assert!(!is_valid_resumption(206, parse_content_range("bytes 0-199/200"), 100, Some(200)));
+        assert!(is_valid_resumption(206, parse_content_range("bytes 100-199/*"), 100, Some(200)));
+        assert!(!is_valid_resumption(200, None, 100, Some(200)));
+        assert_eq!(None, parse_content_range("bytes */200"));
+    }
+
     #[test]
     fn test_formatting_two_kilobytes() {
         let result = size_to_human_readable(2048);
@@ -0,0 +1,126 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::mirror_flexo::{uri_from_components, DownloadProvider, MirrorResults};
+
+/// The output format of "flexo rank-mirrors".
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MirrorlistFormat {
+    /// A mirrorlist that can be used as /etc/pacman.d/mirrorlist, with the timings in comments.
+    Pacman,
+    Json,
+}
+
+impl FromStr for MirrorlistFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pacman" => Ok(MirrorlistFormat::Pacman),
+            "json" => Ok(MirrorlistFormat::Json),
+            _ => Err(format!("Unknown format {:?}, expected \"pacman\" or \"json\"", s)),
+        }
+    }
+}
+
+/// The latency test results of a single mirror, in milliseconds.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+struct RankedMirror<'a> {
+    rank: usize,
+    url: &'a str,
+    country_code: &'a str,
+    /// The latency used for the ranking: The total time, without the DNS lookup.
+    latency_ms: u128,
+    namelookup_ms: u128,
+    connect_ms: u128,
+    pretransfer_ms: u128,
+    starttransfer_ms: u128,
+    total_ms: u128,
+}
+
+#[derive(Serialize, Debug)]
+struct RankedMirrors<'a> {
+    /// The primary mirror according to the latency test results stored by the server, if any.
+    current_primary_mirror: Option<&'a str>,
+    mirrors: Vec<RankedMirror<'a>>,
+}
+
+fn latency(mirror_results: &MirrorResults) -> Duration {
+    mirror_results.total_time.saturating_sub(mirror_results.namelookup_duration)
+}
+
+fn ranked_mirrors(providers: &[DownloadProvider]) -> Vec<RankedMirror<'_>> {
+    providers.iter().enumerate().map(|(i, provider)| {
+        let results = &provider.mirror_results;
+        RankedMirror {
+            rank: i + 1,
+            url: &provider.uri,
+            country_code: &provider.country_code,
+            latency_ms: latency(results).as_millis(),
+            namelookup_ms: results.namelookup_duration.as_millis(),
+            connect_ms: results.connect_duration.as_millis(),
+            pretransfer_ms: results.pretransfer_time.as_millis(),
+            starttransfer_ms: results.starttransfer_time.as_millis(),
+            total_ms: results.total_time.as_millis(),
+        }
+    }).collect()
+}
+
+/// Formats the providers, which are expected to be sorted from best to worst.
+pub fn format_mirrorlist(
+    providers: &[DownloadProvider],
+    current_primary_mirror: Option<&str>,
+    format: MirrorlistFormat,
+    timestamp: &str,
+) -> String {
+    let mirrors = ranked_mirrors(providers);
+    match format {
+        MirrorlistFormat::Json => {
+            let ranked_mirrors = RankedMirrors { current_primary_mirror, mirrors };
+            serde_json::to_string_pretty(&ranked_mirrors).unwrap()
+        }
+        MirrorlistFormat::Pacman => {
+            let mut mirrorlist = format!("## Ranked by Flexo {} at {}\n", env!("CARGO_PKG_VERSION"), timestamp);
+            if let Some(uri) = current_primary_mirror {
+                mirrorlist.push_str(&format!("## Flexo currently uses {} as its primary mirror.\n", uri));
+            }
+            for mirror in mirrors {
+                mirrorlist.push_str(&format!(
+                    "\n## {}. {}, latency {} ms (DNS {} ms, connect {} ms, pretransfer {} ms, first byte {} ms)\n",
+                    mirror.rank, mirror.country_code, mirror.latency_ms, mirror.namelookup_ms, mirror.connect_ms,
+                    mirror.pretransfer_ms, mirror.starttransfer_ms));
+                mirrorlist.push_str(&format!("Server = {}\n", uri_from_components(mirror.url, "$repo/os/$arch")));
+            }
+            mirrorlist
+        }
+    }
+}
+
+#[test]
+fn test_format_mirrorlist() {
+    let provider = DownloadProvider {
+        uri: "https://mirror.example.com/archlinux/".to_owned(),
+        name: "https://mirror.example.com/archlinux/".to_owned(),
+        mirror_results: MirrorResults {
+            total_time: Duration::from_millis(80),
+            namelookup_duration: Duration::from_millis(20),
+            connect_duration: Duration::from_millis(30),
+            pretransfer_time: Duration::from_millis(50),
+            starttransfer_time: Duration::from_millis(75),
+        },
+        country_code: "DE".to_owned(),
+        layout: Default::default(),
+        tls: None,
+        credentials: None,
+    };
+    let providers = [provider];
+    let pacman = format_mirrorlist(&providers, None, MirrorlistFormat::Pacman, "2021-08-01T12:00:00Z");
+    assert!(pacman.contains("## 1. DE, latency 60 ms (DNS 20 ms, connect 30 ms, pretransfer 50 ms, first byte 75 ms)\n\
+        Server = https://mirror.example.com/archlinux/$repo/os/$arch\n"));
+    let json = format_mirrorlist(&providers, Some("https://mirror.example.com/archlinux/"), MirrorlistFormat::Json, "");
+    let json = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+    assert_eq!("https://mirror.example.com/archlinux/", json["current_primary_mirror"]);
+    assert_eq!(60, json["mirrors"][0]["latency_ms"]);
+}
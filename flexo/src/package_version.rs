@@ -0,0 +1,144 @@
+use std::cmp::Ordering;
+
+/// A package file as it is stored on the remote mirrors, for example glibc-2.33-3-x86_64.pkg.tar.zst.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PackageFile {
+    pub name: String,
+    /// The full version string, including the epoch (if any) and the pkgrel, e.g. "1:2.33-3".
+    pub version: String,
+    pub arch: String,
+}
+
+impl PackageFile {
+    /// Returns None if the given filename does not look like a package file. Signature files (.sig) are
+    /// not considered package files.
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        if filename.ends_with(".sig") {
+            return None;
+        }
+        let stem = &filename[..filename.find(".pkg.tar")?];
+        // Package names may contain dashes, but neither the version, the pkgrel nor the architecture do.
+        let mut components = stem.rsplitn(4, '-');
+        let arch = components.next()?;
+        let pkgrel = components.next()?;
+        let pkgver = components.next()?;
+        let name = components.next()?;
+        if name.is_empty() || pkgver.is_empty() || pkgrel.is_empty() || arch.is_empty() {
+            return None;
+        }
+        Some(PackageFile {
+            name: name.to_owned(),
+            version: format!("{}-{}", pkgver, pkgrel),
+            arch: arch.to_owned(),
+        })
+    }
+}
+
+/// Compares two version strings the same way pacman does (see vercmp(8)).
+pub fn vercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+    let (epoch_a, version_a, release_a) = split_version(a);
+    let (epoch_b, version_b, release_b) = split_version(b);
+    rpmvercmp(epoch_a, epoch_b)
+        .then_with(|| rpmvercmp(version_a, version_b))
+        .then_with(|| match (release_a, release_b) {
+            (Some(ra), Some(rb)) => rpmvercmp(ra, rb),
+            _ => Ordering::Equal,
+        })
+}
+
+/// Splits a version string of the form [epoch:]pkgver[-pkgrel] into its components.
+fn split_version(version: &str) -> (&str, &str, Option<&str>) {
+    let (epoch, rest) = match version.split_once(':') {
+        Some((epoch, rest)) if epoch.chars().all(|c| c.is_ascii_digit()) => (epoch, rest),
+        _ => ("0", version),
+    };
+    match rest.rsplit_once('-') {
+        Some((pkgver, pkgrel)) => (epoch, pkgver, Some(pkgrel)),
+        None => (epoch, rest, None),
+    }
+}
+
+fn rpmvercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+    let mut one = a.as_bytes();
+    let mut two = b.as_bytes();
+    while !one.is_empty() && !two.is_empty() {
+        let separators_one = one.iter().take_while(|c| !c.is_ascii_alphanumeric()).count();
+        let separators_two = two.iter().take_while(|c| !c.is_ascii_alphanumeric()).count();
+        one = &one[separators_one..];
+        two = &two[separators_two..];
+        if one.is_empty() || two.is_empty() {
+            break;
+        }
+        if separators_one != separators_two {
+            return separators_one.cmp(&separators_two);
+        }
+        let is_numeric = one[0].is_ascii_digit();
+        let segment_len = |s: &[u8]| if is_numeric {
+            s.iter().take_while(|c| c.is_ascii_digit()).count()
+        } else {
+            s.iter().take_while(|c| c.is_ascii_alphabetic()).count()
+        };
+        let (segment_one, rest_one) = one.split_at(segment_len(one));
+        let (segment_two, rest_two) = two.split_at(segment_len(two));
+        if segment_two.is_empty() {
+            // The segments are of different types: Numeric segments are always newer than alpha segments.
+            return if is_numeric { Ordering::Greater } else { Ordering::Less };
+        }
+        let ordering = if is_numeric {
+            let trimmed_one = trim_leading_zeros(segment_one);
+            let trimmed_two = trim_leading_zeros(segment_two);
+            trimmed_one.len().cmp(&trimmed_two.len()).then_with(|| trimmed_one.cmp(trimmed_two))
+        } else {
+            segment_one.cmp(segment_two)
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        one = rest_one;
+        two = rest_two;
+    }
+    if one.is_empty() && two.is_empty() {
+        Ordering::Equal
+    } else if (one.is_empty() && !two[0].is_ascii_alphabetic()) || (!one.is_empty() && one[0].is_ascii_alphabetic()) {
+        // A remaining alpha string never wins against an empty string: 1.0alpha is older than 1.0.
+        Ordering::Less
+    } else {
+        Ordering::Greater
+    }
+}
+
+fn trim_leading_zeros(s: &[u8]) -> &[u8] {
+    let num_zeros = s.iter().take_while(|c| **c == b'0').count();
+    &s[num_zeros..]
+}
+
+#[test]
+fn test_package_file_from_filename() {
+    let expected = PackageFile {
+        name: "lib32-glibc".to_owned(),
+        version: "2.33-3".to_owned(),
+        arch: "x86_64".to_owned(),
+    };
+    assert_eq!(Some(expected), PackageFile::from_filename("lib32-glibc-2.33-3-x86_64.pkg.tar.zst"));
+    assert_eq!(None, PackageFile::from_filename("lib32-glibc-2.33-3-x86_64.pkg.tar.zst.sig"));
+    assert_eq!(None, PackageFile::from_filename("core.db"));
+}
+
+#[test]
+fn test_vercmp() {
+    assert_eq!(Ordering::Equal, vercmp("1.0-1", "1.0-1"));
+    assert_eq!(Ordering::Less, vercmp("1.0-1", "1.0-2"));
+    assert_eq!(Ordering::Less, vercmp("1.0-1", "1.1-1"));
+    assert_eq!(Ordering::Less, vercmp("1.9-1", "1.10-1"));
+    assert_eq!(Ordering::Less, vercmp("1.0alpha-1", "1.0-1"));
+    assert_eq!(Ordering::Less, vercmp("1.0a-1", "1.0b-1"));
+    assert_eq!(Ordering::Greater, vercmp("1:1.0-1", "2.0-1"));
+    assert_eq!(Ordering::Greater, vercmp("1.0.1-1", "1.0-1"));
+    assert_eq!(Ordering::Equal, vercmp("1.0", "1.0-1"));
+}
@@ -0,0 +1,358 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crossbeam::channel::{Receiver, RecvTimeoutError, Sender, unbounded};
+use flate2::read::GzDecoder;
+
+use flexo::*;
+
+use crate::mirror_config::{PrefetchConfig, TimeWindow};
+use crate::mirror_flexo::{DownloadJob, DownloadOrder, DownloadProvider};
+use crate::package_version::{PackageFile, vercmp};
+use crate::str_path::StrPath;
+
+// While packages are waiting to be prefetched outside of the configured time window, we check in regular intervals
+// whether the time window has been entered.
+const TIME_WINDOW_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A new version of a package that is already cached in an older version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PrefetchItem {
+    path: StrPath,
+    custom_provider: Option<DownloadProvider>,
+}
+
+struct RefreshedDatabase {
+    database_path: PathBuf,
+    requested_path: StrPath,
+    custom_provider: Option<DownloadProvider>,
+}
+
+enum PrefetchMessage {
+    DatabaseRefreshed(Box<RefreshedDatabase>),
+    RunNow,
+}
+
+/// Downloads new versions of cached packages in the background, so that subsequent upgrades can be served
+/// entirely from the cache.
+#[derive(Clone)]
+pub struct Prefetcher {
+    tx: Sender<PrefetchMessage>,
+}
+
+impl Prefetcher {
+    pub fn start(
+        job_context: Arc<Mutex<JobContext<DownloadJob>>>,
+        cache_purge_mutex: Arc<Mutex<()>>,
+        prefetch_config: &PrefetchConfig,
+    ) -> Self {
+        let (tx, rx) = unbounded();
+        let time_window = prefetch_config.time_window();
+        match time_window {
+            None => info!("Prefetching of updated packages is enabled."),
+            Some(w) => info!("Prefetching of updated packages is enabled between {} and {}.", w.start, w.end),
+        }
+        std::thread::spawn(move || {
+            run(job_context, cache_purge_mutex, time_window, rx);
+        });
+        Prefetcher {
+            tx
+        }
+    }
+
+    /// Must be invoked after a database file has been downloaded completely.
+    pub fn database_refreshed(
+        &self,
+        database_path: PathBuf,
+        requested_path: StrPath,
+        custom_provider: Option<DownloadProvider>,
+    ) {
+        let refreshed_database = RefreshedDatabase { database_path, requested_path, custom_provider };
+        let message = PrefetchMessage::DatabaseRefreshed(Box::new(refreshed_database));
+        let _ = self.tx.send(message);
+    }
+
+    /// Starts to prefetch all pending packages, regardless of the configured time window.
+    pub fn run_now(&self) {
+        let _ = self.tx.send(PrefetchMessage::RunNow);
+    }
+}
+
+fn run(
+    job_context: Arc<Mutex<JobContext<DownloadJob>>>,
+    cache_purge_mutex: Arc<Mutex<()>>,
+    time_window: Option<TimeWindow>,
+    rx: Receiver<PrefetchMessage>,
+) {
+    let mut pending: VecDeque<PrefetchItem> = VecDeque::new();
+    loop {
+        let message = if pending.is_empty() {
+            match rx.recv() {
+                Ok(message) => Some(message),
+                Err(_) => return,
+            }
+        } else {
+            match rx.recv_timeout(TIME_WINDOW_CHECK_INTERVAL) {
+                Ok(message) => Some(message),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        };
+        let mut run_now = false;
+        match message {
+            Some(PrefetchMessage::DatabaseRefreshed(refreshed_database)) => {
+                let RefreshedDatabase { database_path, requested_path, custom_provider } = *refreshed_database;
+                let cache_directory = job_context.lock().unwrap().properties.cache_directory.clone();
+                match prefetch_candidates(&database_path, &requested_path, Path::new(&cache_directory)) {
+                    Ok(paths) => {
+                        debug!("Found {} packages to prefetch in {}", paths.len(), requested_path.to_str());
+                        for path in paths {
+                            let item = PrefetchItem { path, custom_provider: custom_provider.clone() };
+                            if !pending.contains(&item) {
+                                pending.push_back(item);
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Unable to read database file {:?}: {:?}", &database_path, e);
+                    }
+                }
+            }
+            Some(PrefetchMessage::RunNow) => {
+                run_now = true;
+            }
+            None => {}
+        }
+        let mut cache_tainted = false;
+        while !pending.is_empty() && (run_now || within_time_window(time_window)) {
+            let item = pending.pop_front().unwrap();
+            cache_tainted |= prefetch(&job_context, item);
+        }
+        if cache_tainted {
+            let properties = job_context.lock().unwrap().properties.clone();
+            match properties.num_versions_retain {
+                None | Some(0) => {}
+                Some(v) => {
+                    let _lock = cache_purge_mutex.lock().unwrap();
+                    crate::purge_cache(&properties.cache_directory, v);
+                    crate::purge_cfs_files(&properties.cache_directory);
+                }
+            }
+        }
+    }
+}
+
+fn within_time_window(time_window: Option<TimeWindow>) -> bool {
+    match time_window {
+        None => true,
+        Some(w) => w.contains(chrono::Local::now().time()),
+    }
+}
+
+/// Returns true if a new file was stored in the cache.
+fn prefetch(job_context: &Arc<Mutex<JobContext<DownloadJob>>>, item: PrefetchItem) -> bool {
+    let order = DownloadOrder::new(item.path.clone());
+    let result = job_context.lock().unwrap().try_schedule(order, item.custom_provider, None);
+    match result {
+        ScheduleOutcome::Scheduled(ScheduledItem { join_handle, .. }) => {
+            match join_handle.join() {
+                Ok(JobOutcome::Success(provider)) => {
+                    info!("Prefetched {} from {}", item.path.to_str(), provider.identifier());
+                    true
+                }
+                Ok(JobOutcome::Error(_)) => {
+                    warn!("Unable to prefetch {}", item.path.to_str());
+                    false
+                }
+                Err(e) => {
+                    error!("Unable to prefetch {}: {:?}", item.path.to_str(), e);
+                    false
+                }
+            }
+        }
+        ScheduleOutcome::AlreadyInProgress => {
+            debug!("{} is already being downloaded, no need to prefetch it.", item.path.to_str());
+            false
+        }
+        ScheduleOutcome::Overloaded => {
+            info!("Unable to prefetch {}: All threads are busy.", item.path.to_str());
+            false
+        }
+        ScheduleOutcome::Cached | ScheduleOutcome::Uncacheable(_) => false,
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct DatabaseEntry {
+    filename: String,
+    name: String,
+    version: String,
+}
+
+impl DatabaseEntry {
+    /// Parses the contents of a "desc" file, as it is included for each package in the database file.
+    fn from_desc(desc: &str) -> Option<Self> {
+        let mut filename = None;
+        let mut name = None;
+        let mut version = None;
+        let mut lines = desc.lines();
+        while let Some(line) = lines.next() {
+            match line {
+                "%FILENAME%" => filename = lines.next(),
+                "%NAME%" => name = lines.next(),
+                "%VERSION%" => version = lines.next(),
+                _ => {}
+            }
+        }
+        Some(DatabaseEntry {
+            filename: filename?.to_owned(),
+            name: name?.to_owned(),
+            version: version?.to_owned(),
+        })
+    }
+}
+
+fn read_database(path: &Path) -> io::Result<Vec<DatabaseEntry>> {
+    let file = File::open(path)?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    let mut database_entries = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let is_desc_file = entry.path()?.file_name().map(|f| f == "desc").unwrap_or(false);
+        if is_desc_file {
+            let mut desc = String::new();
+            entry.read_to_string(&mut desc)?;
+            match DatabaseEntry::from_desc(&desc) {
+                None => warn!("Skipping malformed database entry in {:?}", path),
+                Some(database_entry) => database_entries.push(database_entry),
+            }
+        }
+    }
+    Ok(database_entries)
+}
+
+/// Returns the paths of all packages that are listed in the given database file, and whose previous version
+/// is available in the cache.
+fn prefetch_candidates(
+    database_path: &Path,
+    requested_path: &StrPath,
+    cache_directory: &Path,
+) -> io::Result<Vec<StrPath>> {
+    let repo_directory = match requested_path.as_ref().parent() {
+        None => return Ok(vec![]),
+        Some(p) => p,
+    };
+    let cached_filenames = match fs::read_dir(cache_directory.join(repo_directory)) {
+        Ok(entries) => {
+            entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| entry.file_name().to_str().map(|s| s.to_owned()))
+                .filter(|filename| !filename.starts_with('.'))
+                .collect::<Vec<String>>()
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            // Nothing from this repository has been cached so far.
+            return Ok(vec![]);
+        }
+        Err(e) => return Err(e),
+    };
+    let database_entries = read_database(database_path)?;
+    let paths = outdated_packages(&database_entries, &cached_filenames)
+        .into_iter()
+        .filter_map(|entry| StrPath::from_path_buf(repo_directory.join(&entry.filename)))
+        .collect();
+    Ok(paths)
+}
+
+/// Returns all database entries for which only older versions are available in the cache. Packages are
+/// identified by their name and architecture, so that different architectures of a package are not mistaken
+/// for different versions.
+fn outdated_packages<'a>(database_entries: &'a [DatabaseEntry], cached_filenames: &[String]) -> Vec<&'a DatabaseEntry> {
+    let mut cached_versions: HashMap<(String, String), Vec<String>> = HashMap::new();
+    for package_file in cached_filenames.iter().filter_map(|f| PackageFile::from_filename(f)) {
+        let key = (package_file.name, package_file.arch);
+        cached_versions.entry(key).or_default().push(package_file.version);
+    }
+    database_entries.iter().filter(|entry| {
+        let arch = match PackageFile::from_filename(&entry.filename) {
+            None => return false,
+            Some(package_file) => package_file.arch,
+        };
+        match cached_versions.get(&(entry.name.clone(), arch)) {
+            None => false,
+            Some(versions) => {
+                !cached_filenames.contains(&entry.filename) &&
+                    versions.iter().all(|v| vercmp(v, &entry.version) == Ordering::Less)
+            }
+        }
+    }).collect()
+}
+
+#[test]
+fn test_database_entry_from_desc() {
+    let desc = "%FILENAME%\nglibc-2.33-4-x86_64.pkg.tar.zst\n\n%NAME%\nglibc\n\n%BASE%\nglibc\n\n\
+        %VERSION%\n2.33-4\n\n%CSIZE%\n10000\n";
+    let expected = DatabaseEntry {
+        filename: "glibc-2.33-4-x86_64.pkg.tar.zst".to_owned(),
+        name: "glibc".to_owned(),
+        version: "2.33-4".to_owned(),
+    };
+    assert_eq!(Some(expected), DatabaseEntry::from_desc(desc));
+    assert_eq!(None, DatabaseEntry::from_desc("%NAME%\nglibc\n"));
+}
+
+#[test]
+fn test_outdated_packages() {
+    let entry = |name: &str, version: &str| DatabaseEntry {
+        filename: format!("{}-{}-x86_64.pkg.tar.zst", name, version),
+        name: name.to_owned(),
+        version: version.to_owned(),
+    };
+    let database_entries = vec![
+        entry("glibc", "2.33-4"),
+        entry("bash", "5.1.008-1"),
+        entry("linux", "5.13.8.arch1-1"),
+        entry("vim", "8.2.3200-1"),
+    ];
+    let cached_filenames = vec![
+        "glibc-2.33-3-x86_64.pkg.tar.zst".to_owned(),
+        "glibc-2.33-3-x86_64.pkg.tar.zst.sig".to_owned(),
+        "bash-5.1.008-1-x86_64.pkg.tar.zst".to_owned(),
+        "linux-5.13.9.arch1-1-x86_64.pkg.tar.zst".to_owned(),
+    ];
+    let outdated = outdated_packages(&database_entries, &cached_filenames);
+    assert_eq!(vec![&database_entries[0]], outdated);
+}
+
+#[test]
+fn test_outdated_packages_with_different_architectures() {
+    let entry = |name: &str, version: &str, arch: &str| DatabaseEntry {
+        filename: format!("{}-{}-{}.pkg.tar.zst", name, version, arch),
+        name: name.to_owned(),
+        version: version.to_owned(),
+    };
+    let database_entries = vec![
+        entry("lib32-glibc", "2.33-4", "x86_64"),
+        entry("ca-certificates", "20210603-1", "any"),
+        entry("zlib", "1:1.2.11-5", "x86_64"),
+        entry("zlib", "1:1.2.11-5", "aarch64"),
+    ];
+    let cached_filenames = vec![
+        // Same name, but a different architecture: Must not be compared against the database entry.
+        "lib32-glibc-2.33-3-any.pkg.tar.zst".to_owned(),
+        "ca-certificates-20210603-1-x86_64.pkg.tar.zst".to_owned(),
+        "ca-certificates-20181109-1-any.pkg.tar.zst".to_owned(),
+        // A newer version is cached for aarch64 only, which must not prevent prefetching for x86_64.
+        "zlib-1:1.2.11-4-x86_64.pkg.tar.zst".to_owned(),
+        "zlib-1:1.2.12-1-aarch64.pkg.tar.zst".to_owned(),
+    ];
+    let outdated = outdated_packages(&database_entries, &cached_filenames);
+    assert_eq!(vec![&database_entries[1], &database_entries[2]], outdated);
+}
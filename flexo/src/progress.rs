@@ -0,0 +1,286 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::RecvTimeoutError;
+
+use crate::FlexoProgress;
+
+/// The download speed is determined from the progress within this interval.
+const SPEED_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Creates the sender used by a job to report its progress, and the first receiver.
+pub fn channel() -> (ProgressSender, ProgressReceiver) {
+    let state = HubState {
+        events: Vec::new(),
+        latest_progress: None,
+        speed_sample: None,
+        bytes_per_second: None,
+        num_senders: 1,
+    };
+    let hub = Arc::new(ProgressHub {
+        state: Mutex::new(state),
+        changed: Condvar::new(),
+        num_attached_clients: AtomicUsize::new(0),
+    });
+    let sender = ProgressSender { hub: hub.clone() };
+    let receiver = ProgressReceiver { hub, cursor: Cell::new(Cursor::default()) };
+    (sender, receiver)
+}
+
+/// Sends the progress messages of a job. Messages are stored as they are sent, so that they do not pile up while
+/// no receiver is waiting for them: Progress messages are coalesced, only the other messages are kept.
+pub struct ProgressSender {
+    hub: Arc<ProgressHub>,
+}
+
+impl ProgressSender {
+    /// Never blocks: The message is stored even if nobody is receiving, for receivers subscribed later.
+    pub fn send(&self, message: FlexoProgress) {
+        self.hub.state.lock().unwrap().store(message);
+        self.hub.changed.notify_all();
+    }
+}
+
+impl Clone for ProgressSender {
+    fn clone(&self) -> Self {
+        self.hub.state.lock().unwrap().num_senders += 1;
+        ProgressSender { hub: self.hub.clone() }
+    }
+}
+
+impl Drop for ProgressSender {
+    fn drop(&mut self) {
+        self.hub.state.lock().unwrap().num_senders -= 1;
+        self.hub.changed.notify_all();
+    }
+}
+
+impl std::fmt::Debug for ProgressSender {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProgressSender").finish_non_exhaustive()
+    }
+}
+
+/// Receives the progress messages of a job. Unlike a plain channel, any number of receivers can be subscribed to
+/// the same job, and each receiver receives all messages. Consecutive Progress messages are coalesced: Receivers
+/// that do not keep up only receive the latest progress.
+pub struct ProgressReceiver {
+    hub: Arc<ProgressHub>,
+    cursor: Cell<Cursor>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Cursor {
+    events_received: usize,
+    progress_received: Option<u64>,
+}
+
+struct ProgressHub {
+    state: Mutex<HubState>,
+    /// Notified when a new message has been stored or when a sender has been dropped.
+    changed: Condvar,
+    num_attached_clients: AtomicUsize,
+}
+
+struct HubState {
+    /// All messages except for Progress messages, along with the progress at the time the message was received.
+    events: Vec<(Option<u64>, FlexoProgress)>,
+    latest_progress: Option<u64>,
+    /// The progress at the start of the current speed interval.
+    speed_sample: Option<(Instant, u64)>,
+    bytes_per_second: Option<u64>,
+    /// The receivers are disconnected once all senders have been dropped.
+    num_senders: usize,
+}
+
+impl HubState {
+    fn store(&mut self, message: FlexoProgress) {
+        match message {
+            FlexoProgress::Progress(progress) => {
+                self.latest_progress = Some(progress);
+                self.update_speed(progress);
+            }
+            message => self.events.push((self.latest_progress, message)),
+        }
+    }
+
+    fn update_speed(&mut self, progress: u64) {
+        let now = Instant::now();
+        match self.speed_sample {
+            Some((start, start_progress)) if now.duration_since(start) >= SPEED_INTERVAL => {
+                let bytes = progress.saturating_sub(start_progress) as f64;
+                self.bytes_per_second = Some((bytes / now.duration_since(start).as_secs_f64()) as u64);
+                self.speed_sample = Some((now, progress));
+            }
+            Some(_) => {}
+            None => self.speed_sample = Some((now, progress)),
+        }
+    }
+
+    fn snapshot(&self, num_attached_clients: usize) -> ProgressSnapshot {
+        let job_size = self.events.iter().rev().find_map(|(_, event)| match event {
+            FlexoProgress::JobSize(size) => Some(*size),
+            _ => None,
+        });
+        let is_stalled = self.speed_sample
+            .map(|(start, _)| start.elapsed() >= SPEED_INTERVAL * 2)
+            .unwrap_or(false);
+        ProgressSnapshot {
+            job_size,
+            size_written: self.latest_progress,
+            bytes_per_second: if is_stalled { Some(0) } else { self.bytes_per_second },
+            num_attached_clients,
+        }
+    }
+
+    fn next_message(&self, cursor: &mut Cursor) -> Option<FlexoProgress> {
+        let progress_received = |progress: Option<u64>| match (progress, cursor.progress_received) {
+            (None, _) => true,
+            (Some(p), Some(r)) => p <= r,
+            (Some(_), None) => false,
+        };
+        match self.events.get(cursor.events_received) {
+            Some((progress, _)) if !progress_received(*progress) => {
+                cursor.progress_received = *progress;
+                progress.map(FlexoProgress::Progress)
+            }
+            Some((_, event)) => {
+                cursor.events_received += 1;
+                Some(event.clone())
+            }
+            None if !progress_received(self.latest_progress) => {
+                cursor.progress_received = self.latest_progress;
+                self.latest_progress.map(FlexoProgress::Progress)
+            }
+            None => None,
+        }
+    }
+}
+
+impl ProgressReceiver {
+    /// Returns a new receiver for the same job, which receives all messages from the beginning.
+    pub fn subscribe(&self) -> Self {
+        ProgressReceiver {
+            hub: self.hub.clone(),
+            cursor: Cell::new(Cursor::default()),
+        }
+    }
+
+    /// Returns the current progress of the job, without consuming any messages of this receiver.
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        let state = self.hub.state.lock().unwrap();
+        state.snapshot(self.hub.num_attached_clients.load(Ordering::SeqCst))
+    }
+
+    /// Marks a client as attached to the job until the returned value is dropped.
+    pub fn attach_client(&self) -> AttachedClient {
+        self.hub.num_attached_clients.fetch_add(1, Ordering::SeqCst);
+        AttachedClient { hub: self.hub.clone() }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<FlexoProgress, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut cursor = self.cursor.get();
+        let mut state = self.hub.state.lock().unwrap();
+        let result = loop {
+            if let Some(message) = state.next_message(&mut cursor) {
+                break Ok(message);
+            }
+            if state.num_senders == 0 {
+                break Err(RecvTimeoutError::Disconnected);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                break Err(RecvTimeoutError::Timeout);
+            }
+            state = self.hub.changed.wait_timeout(state, remaining).unwrap().0;
+        };
+        self.cursor.set(cursor);
+        result
+    }
+}
+
+/// The progress of a job at a given time.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProgressSnapshot {
+    /// The size of the complete file, known after the provider has sent the header.
+    pub job_size: Option<u64>,
+    pub size_written: Option<u64>,
+    pub bytes_per_second: Option<u64>,
+    /// The number of clients that are served the file while it is being downloaded.
+    pub num_attached_clients: usize,
+}
+
+pub struct AttachedClient {
+    hub: Arc<ProgressHub>,
+}
+
+impl Drop for AttachedClient {
+    fn drop(&mut self) {
+        self.hub.num_attached_clients.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn test_progress_receiver() {
+    let (tx, receiver1) = channel();
+    let timeout = Duration::from_millis(50);
+    tx.send(FlexoProgress::JobSize(100));
+    tx.send(FlexoProgress::Progress(10));
+    assert_eq!(Ok(FlexoProgress::JobSize(100)), receiver1.recv_timeout(timeout));
+    assert_eq!(Ok(FlexoProgress::Progress(10)), receiver1.recv_timeout(timeout));
+    assert_eq!(Err(RecvTimeoutError::Timeout), receiver1.recv_timeout(timeout));
+    let receiver2 = receiver1.subscribe();
+    tx.send(FlexoProgress::Progress(50));
+    tx.send(FlexoProgress::Progress(100));
+    tx.send(FlexoProgress::Completed);
+    drop(tx);
+    assert_eq!(Ok(FlexoProgress::Progress(100)), receiver1.recv_timeout(timeout));
+    assert_eq!(Ok(FlexoProgress::Completed), receiver1.recv_timeout(timeout));
+    assert_eq!(Err(RecvTimeoutError::Disconnected), receiver1.recv_timeout(timeout));
+    // The new receiver starts from the beginning, but intermediate progress is skipped.
+    assert_eq!(Ok(FlexoProgress::JobSize(100)), receiver2.recv_timeout(timeout));
+    assert_eq!(Ok(FlexoProgress::Progress(100)), receiver2.recv_timeout(timeout));
+    assert_eq!(Ok(FlexoProgress::Completed), receiver2.recv_timeout(timeout));
+    assert_eq!(Err(RecvTimeoutError::Disconnected), receiver2.recv_timeout(timeout));
+}
+
+#[test]
+fn test_progress_snapshot() {
+    let (tx, receiver) = channel();
+    assert_eq!(ProgressSnapshot::default(), receiver.snapshot());
+    tx.send(FlexoProgress::JobSize(100));
+    tx.send(FlexoProgress::Progress(10));
+    let attached_client = receiver.attach_client();
+    let expected = ProgressSnapshot {
+        job_size: Some(100),
+        size_written: Some(10),
+        bytes_per_second: None,
+        num_attached_clients: 1,
+    };
+    assert_eq!(expected, receiver.snapshot());
+    // The snapshot does not consume the messages of the receiver.
+    assert_eq!(Ok(FlexoProgress::JobSize(100)), receiver.recv_timeout(Duration::from_millis(50)));
+    drop(attached_client);
+    assert_eq!(0, receiver.snapshot().num_attached_clients);
+}
+
+#[test]
+fn test_progress_without_receivers() {
+    let (tx, receiver) = channel();
+    tx.send(FlexoProgress::JobSize(10_000));
+    for progress in 0..10_000 {
+        tx.send(FlexoProgress::Progress(progress));
+    }
+    tx.send(FlexoProgress::Completed);
+    drop(tx);
+    // Nobody has been receiving, but only the latest progress has been kept.
+    assert_eq!(2, receiver.hub.state.lock().unwrap().events.len());
+    let timeout = Duration::from_millis(50);
+    assert_eq!(Ok(FlexoProgress::JobSize(10_000)), receiver.recv_timeout(timeout));
+    assert_eq!(Ok(FlexoProgress::Progress(9_999)), receiver.recv_timeout(timeout));
+    assert_eq!(Ok(FlexoProgress::Completed), receiver.recv_timeout(timeout));
+    assert_eq!(Err(RecvTimeoutError::Disconnected), receiver.recv_timeout(timeout));
+}
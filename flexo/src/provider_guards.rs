@@ -17,31 +17,70 @@ impl <P> ProviderGuards<P> where P: Debug {
         }
     }
 
+    /// Returns only those providers for which the given function returns true. The guards are shared with this
+    /// instance, so that usages of the remaining providers are still counted.
+    pub fn retain<F>(&self, f: F) -> Self where F: Fn(&P) -> bool {
+        let _lock = self.mutex.lock().unwrap();
+        let guards = self.guards.iter()
+            .filter(|g| f(&g.guarded_provider))
+            .map(|g| ProviderGuard { guarded_provider: Arc::clone(&g.guarded_provider) })
+            .collect();
+        Self {
+            guards,
+            mutex: Mutex::new(()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.guards.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.guards.is_empty()
+    }
+
     pub fn get_provider_guard<F, O>(&self, mirror_score: F) -> (ProviderGuard<P>, usize)
         where F: Fn(&P, usize) -> ProviderChoice<O>, O: Ord + Copy
+    {
+        self.try_get_provider_guard(mirror_score).unwrap()
+    }
+
+    /// Returns None if all providers were excluded.
+    pub fn try_get_provider_guard<F, O>(&self, mirror_score: F) -> Option<(ProviderGuard<P>, usize)>
+        where F: Fn(&P, usize) -> ProviderChoice<O>, O: Ord + Copy
     {
         let _lock = self.mutex.lock().unwrap();
-        let guards_with_scores = self.guards.iter()
-            .filter_map(|g| {
-                match mirror_score(&g.guarded_provider, g.num_current_usages()) {
-                    ProviderChoice::Include(score) => Some((g, score)),
-                    ProviderChoice::Exclude => None,
-                }
-            }).collect::<Vec<(&ProviderGuard<P>, O)>>();
-        let (guard, _) = guards_with_scores.iter().min_by_key(|(_guard, score)| {
+        let mut guards_with_scores = Vec::new();
+        let mut fallback_guards_with_scores = Vec::new();
+        for g in self.guards.iter() {
+            match mirror_score(&g.guarded_provider, g.num_current_usages()) {
+                ProviderChoice::Include(score) => guards_with_scores.push((g, score)),
+                ProviderChoice::Fallback(score) => fallback_guards_with_scores.push((g, score)),
+                ProviderChoice::Exclude => {},
+            }
+        }
+        let num_candidates = guards_with_scores.len() + fallback_guards_with_scores.len();
+        let candidates = if guards_with_scores.is_empty() {
+            fallback_guards_with_scores
+        } else {
+            guards_with_scores
+        };
+        let (guard, _) = candidates.iter().min_by_key(|(_guard, score)| {
             *score
-        }).unwrap();
+        })?;
         debug!("Selected {:?}, number of usages: {} [{:?}]",
                  &guard.guarded_provider, guard.num_current_usages(), std::thread::current().id());
         let guard = ProviderGuard {
             guarded_provider: Arc::clone(&guard.guarded_provider)
         };
-        (guard, guards_with_scores.len())
+        Some((guard, num_candidates))
     }
 }
 
 pub enum ProviderChoice<O> {
     Include(O),
+    /// Only selected if no provider was included.
+    Fallback(O),
     Exclude,
 }
 
@@ -0,0 +1,142 @@
+use std::str;
+
+/// Headers with more fields than this are considered invalid.
+const MAX_FIELD_COUNT: usize = 64;
+
+/// The header of a response from a remote mirror, assembled from the lines passed to curl's header callback.
+/// curl passes the header lines in the same format regardless of the HTTP version, except for the status line,
+/// which is "HTTP/2 200" for HTTP/2.
+#[derive(Debug, Default)]
+pub struct ResponseHeader {
+    status_code: Option<u16>,
+    fields: Vec<(String, String)>,
+    /// Set when the empty line that ends the header has been received.
+    is_complete: bool,
+    /// Set if the connection may be tunneled through an HTTP proxy: curl then passes the proxy's reply to the
+    /// CONNECT request to the header callback, followed by the header from the remote mirror.
+    via_proxy_tunnel: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HeaderProgress {
+    /// More lines are required to complete the header.
+    Partial,
+    /// The header is complete and has the given status code.
+    Complete(u16),
+    Invalid,
+}
+
+impl ResponseHeader {
+    pub fn new(via_proxy_tunnel: bool) -> Self {
+        ResponseHeader {
+            via_proxy_tunnel,
+            ..Default::default()
+        }
+    }
+
+    /// Adds a single line, as received by curl's header callback.
+    pub fn push_line(&mut self, line: &[u8]) -> HeaderProgress {
+        let line = match str::from_utf8(line) {
+            Ok(l) => l.trim_end_matches(&['\r', '\n'][..]),
+            Err(_) => return HeaderProgress::Invalid,
+        };
+        if self.is_complete && line.starts_with("HTTP/") {
+            // Another header follows, e.g. after a redirect.
+            self.clear();
+        }
+        match self.status_code {
+            None => {
+                let mut parts = line.split_whitespace();
+                let is_http = parts.next().map(|p| p.starts_with("HTTP/")).unwrap_or(false);
+                match parts.next().and_then(|p| p.parse::<u16>().ok()) {
+                    Some(status_code) if is_http => {
+                        self.status_code = Some(status_code);
+                        HeaderProgress::Partial
+                    }
+                    _ => HeaderProgress::Invalid,
+                }
+            }
+            Some(status_code) if line.is_empty() && self.is_connect_reply(status_code) => {
+                debug!("Skip the reply of the proxy to the CONNECT request.");
+                self.clear();
+                HeaderProgress::Partial
+            }
+            Some(status_code) if line.is_empty() => {
+                self.is_complete = true;
+                HeaderProgress::Complete(status_code)
+            }
+            Some(_) if self.fields.len() >= MAX_FIELD_COUNT => HeaderProgress::Invalid,
+            Some(_) => match line.split_once(':') {
+                None => HeaderProgress::Invalid,
+                Some((name, value)) => {
+                    self.fields.push((name.trim().to_owned(), value.trim().to_owned()));
+                    HeaderProgress::Partial
+                }
+            },
+        }
+    }
+
+    /// Returns the value of the given field. Field names are case-insensitive.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.iter()
+            .find(|(field_name, _)| field_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Removes all lines received so far, e.g. to wait for the header that follows a redirect.
+    pub fn clear(&mut self) {
+        self.status_code = None;
+        self.fields.clear();
+        self.is_complete = false;
+    }
+
+    /// A successful reply to a CONNECT request has neither a Content-Length nor a Transfer-Encoding field
+    /// (RFC 9110, section 9.3.6), unlike any response from a remote mirror that we can use.
+    fn is_connect_reply(&self, status_code: u16) -> bool {
+        self.via_proxy_tunnel && (200..300).contains(&status_code) &&
+            self.field("content-length").is_none() && self.field("transfer-encoding").is_none()
+    }
+}
+
+#[test]
+fn test_response_header() {
+    let http1_lines: [&[u8]; 4] = [b"HTTP/1.1 200 OK\r\n", b"Content-Length: 1024\r\n", b"Server: nginx\r\n", b"\r\n"];
+    let http2_lines: [&[u8]; 4] = [b"HTTP/2 200\r\n", b"content-length: 1024\r\n", b"server: nginx\r\n", b"\r\n"];
+    for lines in [http1_lines, http2_lines].iter() {
+        let mut header = ResponseHeader::default();
+        let progress = lines.iter().map(|line| header.push_line(line)).collect::<Vec<_>>();
+        assert_eq!(HeaderProgress::Complete(200), progress[3]);
+        assert_eq!(Some("1024"), header.field("Content-Length"));
+        assert_eq!(None, header.field("Content-Range"));
+    }
+    let mut header = ResponseHeader::default();
+    assert_eq!(HeaderProgress::Invalid, header.push_line(b"garbage\r\n"));
+}
+
+#[test]
+fn test_response_header_after_redirect() {
+    let lines: [&[u8]; 6] = [b"HTTP/1.1 301 Moved Permanently\r\n", b"Location: https://example.com/\r\n", b"\r\n",
+        b"HTTP/1.1 200 OK\r\n", b"Content-Length: 1024\r\n", b"\r\n"];
+    let mut header = ResponseHeader::default();
+    let progress = lines.iter().map(|line| header.push_line(line)).collect::<Vec<_>>();
+    assert_eq!(HeaderProgress::Complete(301), progress[2]);
+    assert_eq!(HeaderProgress::Complete(200), progress[5]);
+    assert_eq!(Some("1024"), header.field("Content-Length"));
+    assert_eq!(None, header.field("Location"));
+}
+
+#[test]
+fn test_response_header_via_proxy_tunnel() {
+    let lines: [&[u8]; 6] = [b"HTTP/1.1 200 Connection established\r\n", b"Proxy-Agent: squid\r\n", b"\r\n",
+        b"HTTP/2 200\r\n", b"content-length: 1024\r\n", b"\r\n"];
+    let mut header = ResponseHeader::new(true);
+    let progress = lines.iter().map(|line| header.push_line(line)).collect::<Vec<_>>();
+    assert!(progress[..5].iter().all(|p| *p == HeaderProgress::Partial));
+    assert_eq!(HeaderProgress::Complete(200), progress[5]);
+    assert_eq!(Some("1024"), header.field("Content-Length"));
+    assert_eq!(None, header.field("Proxy-Agent"));
+    // Without a proxy, a response without Content-Length is passed on, so that the job can reject it.
+    let mut header = ResponseHeader::new(false);
+    let progress = lines[..3].iter().map(|line| header.push_line(line)).collect::<Vec<_>>();
+    assert_eq!(HeaderProgress::Complete(200), progress[2]);
+}
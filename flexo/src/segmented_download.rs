@@ -0,0 +1,339 @@
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::{Receiver, RecvTimeoutError, Sender};
+use curl::easy::{Easy2, Handler, WriteError};
+use flexo::thread_pool::{PoolOverloaded, TaskStart};
+use uuid::Uuid;
+
+use crate::bandwidth::{UpstreamBandwidth, UpstreamTransfer};
+use crate::mirror_config::MirrorConfig;
+use crate::mirror_flexo::{apply_transfer_options, is_valid_resumption, parse_content_range, UNCACHEABLE_DIRECTORY};
+use crate::mirror_flexo::via_proxy_tunnel;
+use crate::response_header::{HeaderProgress, ResponseHeader};
+use crate::upstream_transfers::{work_channel, UpstreamTransferError, UpstreamTransfers, WorkSender};
+#[cfg(test)]
+use crate::test_fixtures;
+
+/// How often the part file is checked for new data while the segment is still being downloaded.
+const TIMEOUT_SEGMENT_PROGRESS: Duration = Duration::from_millis(100);
+
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Splits the given range into at most max_segments segments of roughly equal size. Segments are not smaller than
+/// min_segment_size, so small files are not split at all.
+pub fn plan_segments(range: Range<u64>, max_segments: usize, min_segment_size: u64) -> Vec<Range<u64>> {
+    let size = range.end.saturating_sub(range.start);
+    let num_segments = (size / std::cmp::max(min_segment_size, 1)).clamp(1, std::cmp::max(max_segments, 1) as u64);
+    let segment_size = size / num_segments;
+    (0..num_segments).map(|i| {
+        let start = range.start + i * segment_size;
+        let end = if i == num_segments - 1 { range.end } else { start + segment_size };
+        start..end
+    }).collect()
+}
+
+#[derive(Debug)]
+pub enum SegmentError {
+    IoError(io::Error),
+    CurlError(curl::Error),
+    /// The download could not be performed together with the other upstream transfers.
+    TransferError(UpstreamTransferError),
+    /// The remote mirror did not reply with the requested range of the expected file.
+    InvalidResponse,
+    /// The part file is smaller than the segment, although the download has finished.
+    Incomplete,
+    /// The download has waited too long for a thread of the download thread pool.
+    NotStarted,
+}
+
+impl fmt::Display for SegmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SegmentError::IoError(e) => write!(f, "I/O error: {}", e),
+            SegmentError::CurlError(e) => write!(f, "curl error: {}", e),
+            SegmentError::TransferError(e) => write!(f, "{}", e),
+            SegmentError::InvalidResponse => write!(f, "the remote mirror did not send the requested range"),
+            SegmentError::Incomplete => write!(f, "the segment is incomplete"),
+            SegmentError::NotStarted => write!(f, "no download thread has become available in time"),
+        }
+    }
+}
+
+impl From<io::Error> for SegmentError {
+    fn from(error: io::Error) -> Self {
+        SegmentError::IoError(error)
+    }
+}
+
+/// Downloads one segment of a file into a separate part file, using a thread of the download thread pool. Dropping
+/// this value cancels the download and removes the part file.
+#[derive(Debug)]
+pub struct SegmentDownload {
+    range: Range<u64>,
+    part_path: PathBuf,
+    rx_result: Receiver<Result<(), SegmentError>>,
+    is_cancelled: Arc<AtomicBool>,
+}
+
+impl Drop for SegmentDownload {
+    fn drop(&mut self) {
+        self.is_cancelled.store(true, Ordering::Relaxed);
+        let _ = fs::remove_file(&self.part_path);
+    }
+}
+
+impl SegmentDownload {
+    pub fn start(
+        uri: String,
+        range: Range<u64>,
+        complete_size: u64,
+        properties: &MirrorConfig,
+    ) -> io::Result<Self> {
+        let directory = Path::new(UNCACHEABLE_DIRECTORY).join("segments");
+        fs::create_dir_all(&directory)?;
+        let part_path = directory.join(Uuid::new_v4().to_string());
+        let file = File::create(&part_path)?;
+        let is_cancelled = Arc::new(AtomicBool::new(false));
+        let (tx_result, rx_result) = crossbeam::channel::bounded::<Result<(), SegmentError>>(1);
+        let (tx_work, rx_work) = work_channel();
+        let state = SegmentState {
+            tx_work,
+            received_header: ResponseHeader::new(via_proxy_tunnel(properties)),
+            is_valid: None,
+            first_byte: range.start,
+            last_byte: range.end - 1,
+            size_remaining: range.end - range.start,
+            complete_size,
+            is_cancelled: is_cancelled.clone(),
+            upstream_bandwidth_limiter: properties.upstream_bandwidth_limiter.clone(),
+            upstream_resume_at: None,
+        };
+        let mut handle = Easy2::new(state);
+        handle.url(&uri).map_err(io::Error::from)?;
+        apply_transfer_options(&mut handle, properties).map_err(io::Error::from)?;
+        handle.progress(true).map_err(io::Error::from)?;
+        handle.range(&format!("{}-{}", range.start, range.end - 1)).map_err(io::Error::from)?;
+        // Like the download of the first segment, the segment downloads share the connections to the remote mirrors
+        // with all other downloads, see UpstreamTransfers.
+        handle.pipewait(true).map_err(io::Error::from)?;
+        debug!("Download bytes {:?} from {}", range, uri);
+        let upstream_transfers = properties.upstream_transfers.clone();
+        // The segments count towards the limit of the download thread pool, just like the downloads themselves.
+        properties.download_thread_pool.execute(move |task_start| match task_start {
+            TaskStart::InTime => download(handle, file, rx_work, uri, &upstream_transfers, tx_result),
+            TaskStart::Expired => {
+                warn!("Segment download from {} has waited too long for a thread.", uri);
+                let _ = tx_result.send(Err(SegmentError::NotStarted));
+            }
+        }).map_err(|PoolOverloaded| io::Error::other("All download threads are busy."))?;
+        Ok(SegmentDownload {
+            range,
+            part_path,
+            rx_result,
+            is_cancelled,
+        })
+    }
+
+    /// Passes the segment to the given function as the data arrives, until the segment is complete.
+    pub fn transfer<F>(&self, mut append: F) -> Result<(), SegmentError> where F: FnMut(&[u8]) -> io::Result<()> {
+        let segment_size = self.range.end - self.range.start;
+        let mut part_file = File::open(&self.part_path)?;
+        let mut buf = vec![0; COPY_BUFFER_SIZE];
+        let mut size_transferred = 0;
+        let mut is_finished = false;
+        loop {
+            loop {
+                match part_file.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        let n = std::cmp::min(n as u64, segment_size - size_transferred) as usize;
+                        append(&buf[..n])?;
+                        size_transferred += n as u64;
+                        if size_transferred >= segment_size {
+                            break;
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => return Err(SegmentError::IoError(e)),
+                }
+            }
+            if size_transferred >= segment_size {
+                return Ok(());
+            } else if is_finished {
+                return Err(SegmentError::Incomplete);
+            }
+            match self.rx_result.recv_timeout(TIMEOUT_SEGMENT_PROGRESS) {
+                Ok(Ok(())) => is_finished = true,
+                Ok(Err(e)) => return Err(e),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => is_finished = true,
+            }
+        }
+    }
+}
+
+/// Writes the part file on the download thread, while the segment is downloaded by the thread that drives all
+/// upstream transfers.
+fn download(
+    handle: Easy2<SegmentState>,
+    part_file: File,
+    rx_work: Receiver<Vec<u8>>,
+    uri: String,
+    upstream_transfers: &UpstreamTransfers,
+    tx_result: Sender<Result<(), SegmentError>>,
+) {
+    let mut buf_writer = BufWriter::new(part_file);
+    let mut write_error = None;
+    let transfer = upstream_transfers.perform(handle, rx_work, |data: Vec<u8>| {
+        match buf_writer.write_all(&data) {
+            Ok(()) => true,
+            Err(e) => {
+                write_error = Some(e);
+                false
+            }
+        }
+    });
+    let (handle, perform_result) = match transfer {
+        Ok(transfer) => transfer,
+        Err(e) => {
+            let error = SegmentError::TransferError(e);
+            warn!("Segment download from {} has failed: {}", uri, error);
+            let _ = tx_result.send(Err(error));
+            return;
+        }
+    };
+    let write_result = match write_error {
+        Some(e) => Err(e),
+        None => buf_writer.flush(),
+    };
+    let state = handle.get_ref();
+    let result = match (perform_result, write_result) {
+        (_, Err(e)) => Err(SegmentError::IoError(e)),
+        _ if state.is_valid != Some(true) => Err(SegmentError::InvalidResponse),
+        // The download is aborted if the remote mirror sends more than the requested range.
+        _ if state.size_remaining == 0 => Ok(()),
+        (Ok(()), Ok(())) => Err(SegmentError::Incomplete),
+        (Err(e), Ok(())) => Err(SegmentError::CurlError(e)),
+    };
+    match &result {
+        Ok(()) => debug!("Segment download from {} has completed.", uri),
+        Err(_) if handle.get_ref().is_cancelled.load(Ordering::Relaxed) => {
+            debug!("Segment download from {} was cancelled.", uri);
+        }
+        Err(e) => warn!("Segment download from {} has failed: {}", uri, e),
+    }
+    let _ = tx_result.send(result);
+}
+
+struct SegmentState {
+    /// Hands the payload over to the download thread, which writes it to the part file.
+    tx_work: WorkSender<Vec<u8>>,
+    received_header: ResponseHeader,
+    /// True if the remote mirror has replied with the requested range, None if the header is incomplete.
+    is_valid: Option<bool>,
+    first_byte: u64,
+    last_byte: u64,
+    /// Data beyond the requested range is not written to the part file.
+    size_remaining: u64,
+    complete_size: u64,
+    is_cancelled: Arc<AtomicBool>,
+    upstream_bandwidth_limiter: UpstreamBandwidth,
+    /// Set while the transfer is paused because the upstream bandwidth budget is used up.
+    upstream_resume_at: Option<Instant>,
+}
+
+impl UpstreamTransfer for SegmentState {
+    fn resume_at(&mut self) -> &mut Option<Instant> {
+        &mut self.upstream_resume_at
+    }
+}
+
+impl Handler for SegmentState {
+    fn write(&mut self, data: &[u8]) -> Result<usize, WriteError> {
+        if self.is_valid != Some(true) {
+            // Returning fewer bytes than we were given aborts the download.
+            return Ok(0);
+        }
+        let data = &data[..std::cmp::min(data.len() as u64, self.size_remaining) as usize];
+        if let Some(resume_at) = self.tx_work.backlog_resume_at() {
+            self.upstream_resume_at = Some(resume_at);
+            return Err(WriteError::Pause);
+        }
+        if let Some(resume_at) = self.upstream_bandwidth_limiter.try_consume(data.len() as u64) {
+            self.upstream_resume_at = Some(resume_at);
+            return Err(WriteError::Pause);
+        }
+        if !self.tx_work.send(data.to_vec()) {
+            // The download thread was unable to write the part file.
+            return Ok(0);
+        }
+        self.size_remaining -= data.len() as u64;
+        Ok(data.len())
+    }
+
+    fn header(&mut self, data: &[u8]) -> bool {
+        match self.received_header.push_line(data) {
+            HeaderProgress::Complete(code) => {
+                if (300..400).contains(&code) {
+                    // Wait for the header that follows the redirect.
+                    return true;
+                }
+                let content_range = self.received_header.field("content-range").and_then(parse_content_range);
+                let is_valid = is_valid_resumption(code, content_range, self.first_byte, Some(self.complete_size)) &&
+                    content_range.map(|r| r.last_byte) == Some(self.last_byte);
+                self.is_valid = Some(is_valid);
+                is_valid
+            }
+            HeaderProgress::Partial => true,
+            HeaderProgress::Invalid => {
+                warn!("Unable to parse header: {:?}", String::from_utf8_lossy(data));
+                false
+            }
+        }
+    }
+
+    fn progress(&mut self, _dltotal: f64, _dlnow: f64, _ultotal: f64, _ulnow: f64) -> bool {
+        !self.is_cancelled.load(Ordering::Relaxed)
+    }
+}
+
+#[test]
+fn test_plan_segments() {
+    let mib = 1024 * 1024;
+    assert_eq!(vec![0..10 * mib], plan_segments(0..10 * mib, 4, 16 * mib));
+    assert_eq!(vec![0..50 * mib, 50 * mib..100 * mib], plan_segments(0..100 * mib, 2, 16 * mib));
+    let segments = plan_segments(mib..(100 * mib + 3), 4, 16 * mib);
+    assert_eq!(4, segments.len());
+    assert_eq!(mib, segments[0].start);
+    assert_eq!(100 * mib + 3, segments[3].end);
+    assert!(segments.windows(2).all(|s| s[0].end == s[1].start));
+}
+
+#[test]
+fn test_segment_downloads() {
+    let content = test_fixtures::file_content(300_000);
+    let complete_size = content.len() as u64;
+    let uri = test_fixtures::serve_content(content.clone(), "/core/os/x86_64/segmented-1-1-x86_64.pkg.tar.zst");
+    let properties = test_fixtures::mirror_config("port = 7878");
+    let downloads = plan_segments(0..complete_size, 3, 1).into_iter()
+        .map(|segment| SegmentDownload::start(uri.clone(), segment, complete_size, &properties).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(3, downloads.len());
+    let mut reassembled = Vec::new();
+    for download in &downloads {
+        download.transfer(|data| {
+            reassembled.extend_from_slice(data);
+            Ok(())
+        }).unwrap();
+    }
+    assert_eq!(content, reassembled);
+}
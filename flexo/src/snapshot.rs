@@ -0,0 +1,151 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{NaiveDate, Utc};
+
+use crate::mirror_config::{MirrorConfig, SnapshotConfig};
+use crate::mirror_flexo::{cfs_path_from_pkg_path, DownloadOrder, DownloadProvider, MirrorResults, ProviderLayout};
+use crate::str_path::StrPath;
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// A request for a file as it was available at a given date, for example
+/// /snapshot/2026-10-01/core/os/x86_64/core.db
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SnapshotRequest {
+    pub date: NaiveDate,
+    /// The path without the /snapshot/<date> prefix.
+    pub path: StrPath,
+}
+
+impl SnapshotRequest {
+    pub fn from_path(path: &StrPath) -> Option<Self> {
+        let mut components = path.as_ref().components();
+        if components.next()?.as_os_str().to_str()? != "snapshot" {
+            return None;
+        }
+        let date = components.next()?.as_os_str().to_str()?;
+        let date = match NaiveDate::parse_from_str(date, DATE_FORMAT) {
+            Ok(d) => d,
+            Err(e) => {
+                info!("Unable to parse the date of snapshot request {:?}: {:?}", path.to_str(), e);
+                return None;
+            }
+        };
+        let path_without_prefix = components.collect::<PathBuf>();
+        if path_without_prefix.as_os_str().is_empty() {
+            return None;
+        }
+        let path = StrPath::from_path_buf(path_without_prefix)?;
+        Some(SnapshotRequest { date, path })
+    }
+
+    pub fn order(&self) -> DownloadOrder {
+        DownloadOrder::snapshot(self.path.clone(), self.date)
+    }
+
+    /// The provider that serves the repositories as they were available at the requested date.
+    pub fn archive_provider(&self, snapshot_config: &SnapshotConfig) -> DownloadProvider {
+        let uri = format!("{}/repos/{}/", snapshot_config.archive_url().trim_end_matches('/'),
+                          self.date.format("%Y/%m/%d"));
+        DownloadProvider {
+            uri,
+            name: "archive".to_owned(),
+            mirror_results: MirrorResults::default(),
+            country_code: "Unknown".to_owned(),
+            layout: ProviderLayout::Mirror,
+            tls: None,
+            credentials: None,
+        }
+    }
+
+    /// Returns the most recent snapshot that has been stored locally at or before the requested date.
+    pub fn latest_local_snapshot(&self, properties: &MirrorConfig) -> Option<PathBuf> {
+        latest_snapshot(Path::new(properties.snapshot_directory()), self.date, &self.path)
+    }
+}
+
+/// Stores a copy of the given database file in the snapshot directory, so that it can be served later on
+/// via /snapshot/<today>/<requested_path>. Any previous snapshot of the same file from today is replaced.
+pub fn store_snapshot(
+    snapshot_config: &SnapshotConfig,
+    database_path: &Path,
+    requested_path: &StrPath,
+) -> io::Result<PathBuf> {
+    let date = Utc::today().naive_utc().format(DATE_FORMAT).to_string();
+    let snapshot_path = Path::new(snapshot_config.directory()).join(date).join(requested_path);
+    let directory = snapshot_path.parent().unwrap();
+    fs::create_dir_all(directory)?;
+    let filename = snapshot_path.file_name().unwrap().to_str().unwrap();
+    // Copy to a temporary file first: Clients must never be served a snapshot that has not been copied completely.
+    let tmp_path = directory.join(format!(".{}.tmp", filename));
+    fs::copy(database_path, &tmp_path)?;
+    if let Some(cfs_path) = cfs_path_from_pkg_path(&snapshot_path) {
+        match fs::remove_file(&cfs_path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    fs::rename(&tmp_path, &snapshot_path)?;
+    Ok(snapshot_path)
+}
+
+fn latest_snapshot(snapshot_directory: &Path, date: NaiveDate, path: &StrPath) -> Option<PathBuf> {
+    let entries = match fs::read_dir(snapshot_directory) {
+        Ok(entries) => entries,
+        Err(e) => {
+            debug!("Unable to read snapshot directory {:?}: {:?}", snapshot_directory, e);
+            return None;
+        }
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let snapshot_date = NaiveDate::parse_from_str(entry.file_name().to_str()?, DATE_FORMAT).ok()?;
+            let snapshot_path = entry.path().join(path);
+            // Empty files remain when a download from the archive has failed.
+            let is_complete = snapshot_path.metadata().map(|m| m.is_file() && m.len() > 0).unwrap_or(false);
+            if snapshot_date <= date && is_complete {
+                Some((snapshot_date, snapshot_path))
+            } else {
+                None
+            }
+        })
+        .max_by_key(|(snapshot_date, _)| *snapshot_date)
+        .map(|(_, snapshot_path)| snapshot_path)
+}
+
+#[test]
+fn test_snapshot_request_from_path() {
+    let path = StrPath::new("/snapshot/2026-10-01/core/os/x86_64/core.db".to_owned());
+    let expected = SnapshotRequest {
+        date: NaiveDate::from_ymd_opt(2026, 10, 1).unwrap(),
+        path: StrPath::new("core/os/x86_64/core.db".to_owned()),
+    };
+    assert_eq!(Some(expected), SnapshotRequest::from_path(&path));
+    assert_eq!(None, SnapshotRequest::from_path(&StrPath::new("/core/os/x86_64/core.db".to_owned())));
+    assert_eq!(None, SnapshotRequest::from_path(&StrPath::new("/snapshot/2026-13-01/core.db".to_owned())));
+    assert_eq!(None, SnapshotRequest::from_path(&StrPath::new("/snapshot/2026-10-01".to_owned())));
+}
+
+#[test]
+fn test_latest_snapshot() {
+    let snapshot_directory = tempfile::tempdir().unwrap();
+    let path = StrPath::new("core/os/x86_64/core.db".to_owned());
+    let create_snapshot = |date: &str, contents: &[u8]| {
+        let snapshot_path = snapshot_directory.path().join(date).join(&path);
+        fs::create_dir_all(snapshot_path.parent().unwrap()).unwrap();
+        fs::write(snapshot_path, contents).unwrap();
+    };
+    create_snapshot("2026-09-01", b"core");
+    create_snapshot("2026-09-15", b"core");
+    create_snapshot("2026-09-20", b"");
+    let date = |d| NaiveDate::from_ymd_opt(2026, 9, d).unwrap();
+    let expected = snapshot_directory.path().join("2026-09-15").join(&path);
+    assert_eq!(Some(expected), latest_snapshot(snapshot_directory.path(), date(30), &path));
+    let expected = snapshot_directory.path().join("2026-09-01").join(&path);
+    assert_eq!(Some(expected), latest_snapshot(snapshot_directory.path(), date(14), &path));
+    assert_eq!(None, latest_snapshot(snapshot_directory.path(), NaiveDate::from_ymd_opt(2026, 8, 31).unwrap(), &path));
+}
@@ -0,0 +1,69 @@
+//! Fixtures shared by the unit tests.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread;
+
+use crate::mirror_config::MirrorConfig;
+
+/// A configuration file that contains all required settings except for the port, followed by the given settings.
+pub fn config_toml(settings: &str) -> String {
+    format!("\
+        cache_directory = \"/var/cache/flexo\"
+        mirrorlist_fallback_file = \"/var/cache/flexo/state/mirrorlist\"
+        mirror_selection_method = \"predefined\"
+        mirrors_predefined = []
+        {}", settings)
+}
+
+/// Parses the configuration returned by [`config_toml`].
+pub fn mirror_config(settings: &str) -> MirrorConfig {
+    toml::from_str::<MirrorConfig>(&config_toml(settings)).unwrap()
+}
+
+/// Content of the given size that does not repeat within short distances, so that misplaced parts are noticed.
+pub fn file_content(size: u32) -> Vec<u8> {
+    (0..size).map(|i| (i % 251) as u8).collect()
+}
+
+/// Serves the given content at the given path to any number of concurrent clients, and returns its URI. Requests
+/// with a Range header are answered with the requested range.
+pub fn serve_content(content: Vec<u8>, path: &str) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let uri = format!("http://{}{}", listener.local_addr().unwrap(), path);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let content = content.clone();
+            thread::spawn(move || {
+                let mut range = None;
+                for line in BufReader::new(stream.try_clone().unwrap()).lines() {
+                    let line = line.unwrap();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.to_lowercase().strip_prefix("range: bytes=") {
+                        let (first, last) = value.split_once('-').unwrap();
+                        range = Some((first.parse::<usize>().unwrap(), last.parse::<usize>().unwrap()));
+                    }
+                }
+                let (header, payload) = match range {
+                    None => {
+                        let header = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                                             content.len());
+                        (header, &content[..])
+                    }
+                    Some((first, last)) => {
+                        let header = format!("HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\n\
+                            Content-Range: bytes {}-{}/{}\r\nConnection: close\r\n\r\n",
+                            last + 1 - first, first, last, content.len());
+                        (header, &content[first..=last])
+                    }
+                };
+                stream.write_all(header.as_bytes()).unwrap();
+                stream.write_all(payload).unwrap();
+            });
+        }
+    });
+    uri
+}
@@ -0,0 +1,186 @@
+use std::collections::VecDeque;
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Idle threads terminate after this duration, so that the pool shrinks again after a burst of tasks.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadPoolConfig {
+    pub max_threads: usize,
+    /// The number of tasks that may wait for a thread to become available.
+    pub max_queue_size: usize,
+    /// Tasks that have waited longer than this duration are expired instead of being run.
+    pub queue_timeout: Duration,
+}
+
+/// Passed to each task to indicate whether the task has started in time, or whether it has waited in the queue for
+/// too long. Expired tasks should only do the bare minimum, e.g. inform the client that the server is overloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskStart {
+    InTime,
+    Expired,
+}
+
+/// Returned if a task cannot be run because all threads are busy and the queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolOverloaded;
+
+type TaskFn = Box<dyn FnOnce(TaskStart) + Send>;
+
+struct Task {
+    enqueued: Instant,
+    run: TaskFn,
+}
+
+struct PoolState {
+    queue: VecDeque<Task>,
+    num_threads: usize,
+    /// Threads that are currently running a task. All other threads will take the next task from the queue.
+    num_running: usize,
+}
+
+struct Inner {
+    name: String,
+    config: ThreadPoolConfig,
+    state: Mutex<PoolState>,
+    task_available: Condvar,
+}
+
+/// A pool of threads with an upper bound on the number of threads. Threads are spawned when required and terminate
+/// when they have been idle for a while.
+#[derive(Clone)]
+pub struct ThreadPool {
+    inner: Arc<Inner>,
+}
+
+impl std::fmt::Debug for ThreadPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ThreadPool")
+            .field("name", &self.inner.name)
+            .field("config", &self.inner.config)
+            .finish()
+    }
+}
+
+impl ThreadPool {
+    pub fn new(name: &str, config: ThreadPoolConfig) -> Self {
+        let state = PoolState {
+            queue: VecDeque::new(),
+            num_threads: 0,
+            num_running: 0,
+        };
+        let inner = Inner {
+            name: name.to_owned(),
+            config,
+            state: Mutex::new(state),
+            task_available: Condvar::new(),
+        };
+        ThreadPool {
+            inner: Arc::new(inner),
+        }
+    }
+
+    /// Runs the given task as soon as a thread is available. Returns an error if the task can neither be run
+    /// immediately nor be queued.
+    pub fn execute<F>(&self, task: F) -> Result<(), PoolOverloaded> where F: FnOnce(TaskStart) + Send + 'static {
+        let mut state = self.inner.state.lock().unwrap();
+        let num_tasks = state.queue.len() + state.num_running;
+        if num_tasks >= self.inner.config.max_threads + self.inner.config.max_queue_size {
+            return Err(PoolOverloaded);
+        }
+        state.queue.push_back(Task {
+            enqueued: Instant::now(),
+            run: Box::new(task),
+        });
+        // Each thread that is not running a task will take one task from the queue. If there are more tasks than
+        // such threads, we need another thread, if the limit permits.
+        let num_threads_waiting = state.num_threads - state.num_running;
+        if state.queue.len() > num_threads_waiting && state.num_threads < self.inner.config.max_threads {
+            state.num_threads += 1;
+            let inner = self.inner.clone();
+            let spawn_result = thread::Builder::new()
+                .name(format!("{}-worker", self.inner.name))
+                .spawn(move || work(inner));
+            if let Err(e) = spawn_result {
+                error!("Unable to spawn thread for pool {}: {:?}", self.inner.name, e);
+                state.num_threads -= 1;
+            }
+        }
+        self.inner.task_available.notify_one();
+        Ok(())
+    }
+}
+
+fn work(inner: Arc<Inner>) {
+    loop {
+        let task = {
+            let mut state = inner.state.lock().unwrap();
+            loop {
+                if let Some(task) = state.queue.pop_front() {
+                    state.num_running += 1;
+                    break task;
+                }
+                let (new_state, wait_result) = inner.task_available.wait_timeout(state, IDLE_TIMEOUT).unwrap();
+                state = new_state;
+                if wait_result.timed_out() && state.queue.is_empty() {
+                    state.num_threads -= 1;
+                    return;
+                }
+            }
+        };
+        let task_start = if task.enqueued.elapsed() > inner.config.queue_timeout {
+            TaskStart::Expired
+        } else {
+            TaskStart::InTime
+        };
+        let run = task.run;
+        // A panicking task must not reduce the number of threads available in the pool.
+        if std::panic::catch_unwind(AssertUnwindSafe(move || run(task_start))).is_err() {
+            error!("A task of thread pool {} has panicked.", inner.name);
+        }
+        inner.state.lock().unwrap().num_running -= 1;
+    }
+}
+
+#[test]
+fn test_thread_pool_overloaded() {
+    let config = ThreadPoolConfig {
+        max_threads: 2,
+        max_queue_size: 1,
+        queue_timeout: Duration::from_secs(60),
+    };
+    let thread_pool = ThreadPool::new("test", config);
+    let (tx_release, rx_release) = crossbeam::channel::unbounded::<()>();
+    let (tx_done, rx_done) = crossbeam::channel::unbounded::<TaskStart>();
+    for _ in 0..3 {
+        let rx_release = rx_release.clone();
+        let tx_done = tx_done.clone();
+        thread_pool.execute(move |task_start| {
+            rx_release.recv().unwrap();
+            tx_done.send(task_start).unwrap();
+        }).unwrap();
+    }
+    // Two tasks are running, one task is queued.
+    assert_eq!(Err(PoolOverloaded), thread_pool.execute(|_| {}));
+    for _ in 0..3 {
+        tx_release.send(()).unwrap();
+        assert_eq!(TaskStart::InTime, rx_done.recv().unwrap());
+    }
+}
+
+#[test]
+fn test_thread_pool_expired() {
+    let config = ThreadPoolConfig {
+        max_threads: 1,
+        max_queue_size: 1,
+        queue_timeout: Duration::from_millis(10),
+    };
+    let thread_pool = ThreadPool::new("test", config);
+    let (tx_done, rx_done) = crossbeam::channel::unbounded::<TaskStart>();
+    thread_pool.execute(|_| thread::sleep(Duration::from_millis(50))).unwrap();
+    thread_pool.execute(move |task_start| tx_done.send(task_start).unwrap()).unwrap();
+    assert_eq!(TaskStart::Expired, rx_done.recv().unwrap());
+}
@@ -0,0 +1,104 @@
+use std::net::TcpStream;
+use std::sync::{Arc, RwLock};
+
+use openssl::error::ErrorStack;
+use openssl::ssl::{HandshakeError, SslAcceptor, SslFiletype, SslMethod, SslStream};
+
+use crate::mirror_config::TlsConfig;
+
+/// Accepts TLS connections from clients. The certificates are read from the file system when Flexo starts and
+/// every time reload() is called, e.g., after a renewed certificate has been stored.
+#[derive(Clone)]
+pub struct TlsAcceptor {
+    acceptor: Arc<RwLock<SslAcceptor>>,
+    tls_config: TlsConfig,
+}
+
+impl TlsAcceptor {
+    pub fn new(tls_config: TlsConfig) -> Result<Self, ErrorStack> {
+        let acceptor = build_acceptor(&tls_config)?;
+        Ok(TlsAcceptor {
+            acceptor: Arc::new(RwLock::new(acceptor)),
+            tls_config,
+        })
+    }
+
+    pub fn reload(&self) {
+        match build_acceptor(&self.tls_config) {
+            Ok(acceptor) => {
+                *self.acceptor.write().unwrap() = acceptor;
+                info!("TLS certificate reloaded from {}", self.tls_config.certificate_file);
+            }
+            Err(e) => {
+                // Keep using the previous certificate: This is preferable to refusing all HTTPS connections.
+                error!("Unable to reload the TLS certificate, will continue to use the previous one: {:?}", e);
+            }
+        }
+    }
+
+    /// Performs the TLS handshake. Clients that stall during the handshake are disconnected after the handshake
+    /// timeout, so that they do not occupy a thread indefinitely.
+    pub fn accept(&self, stream: TcpStream) -> Result<SslStream<TcpStream>, HandshakeError<TcpStream>> {
+        let acceptor = self.acceptor.read().unwrap().clone();
+        let handshake_timeout = Some(self.tls_config.handshake_timeout());
+        if let Err(e) = stream.set_read_timeout(handshake_timeout).and(stream.set_write_timeout(handshake_timeout)) {
+            warn!("Unable to set the TLS handshake timeout: {:?}", e);
+        }
+        let tls_stream = acceptor.accept(stream)?;
+        if let Err(e) = tls_stream.get_ref().set_read_timeout(None).and(tls_stream.get_ref().set_write_timeout(None)) {
+            warn!("Unable to reset the timeout after the TLS handshake: {:?}", e);
+        }
+        Ok(tls_stream)
+    }
+}
+
+fn build_acceptor(tls_config: &TlsConfig) -> Result<SslAcceptor, ErrorStack> {
+    let mut builder = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())?;
+    builder.set_private_key_file(&tls_config.private_key_file, SslFiletype::PEM)?;
+    builder.set_certificate_chain_file(&tls_config.certificate_file)?;
+    builder.check_private_key()?;
+    Ok(builder.build())
+}
+
+#[test]
+fn test_handshake_timeout() {
+    use openssl::asn1::Asn1Time;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::pkey::PKey;
+    use openssl::x509::{X509NameBuilder, X509};
+    use std::time::{Duration, Instant};
+
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_text("CN", "localhost").unwrap();
+    let name = name.build();
+    let mut certificate = X509::builder().unwrap();
+    certificate.set_subject_name(&name).unwrap();
+    certificate.set_issuer_name(&name).unwrap();
+    certificate.set_pubkey(&key).unwrap();
+    certificate.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+    certificate.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+    certificate.sign(&key, MessageDigest::sha256()).unwrap();
+    let directory = tempfile::tempdir().unwrap();
+    let certificate_file = directory.path().join("cert.pem");
+    let private_key_file = directory.path().join("key.pem");
+    std::fs::write(&certificate_file, certificate.build().to_pem().unwrap()).unwrap();
+    std::fs::write(&private_key_file, key.private_key_to_pem_pkcs8().unwrap()).unwrap();
+    let tls_acceptor = TlsAcceptor::new(TlsConfig {
+        port: 0,
+        certificate_file: certificate_file.to_str().unwrap().to_owned(),
+        private_key_file: private_key_file.to_str().unwrap().to_owned(),
+        handshake_timeout_secs: Some(1),
+    }).unwrap();
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    // The client connects, but never starts the handshake.
+    let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    let start = Instant::now();
+    assert!(tls_acceptor.accept(stream).is_err());
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
@@ -0,0 +1,80 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+
+use crate::curl_handle::CurlHandle;
+use crate::mirror_config::UpstreamTlsConfig;
+
+#[derive(Debug)]
+pub enum InvalidUpstreamTls {
+    UnreadableFile(String, io::Error),
+    /// The private key must be accompanied by the certificate it belongs to.
+    PrivateKeyWithoutCertificate,
+}
+
+impl fmt::Display for InvalidUpstreamTls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidUpstreamTls::UnreadableFile(path, e) => write!(f, "unable to read {}: {}", path, e),
+            InvalidUpstreamTls::PrivateKeyWithoutCertificate => {
+                write!(f, "client_private_key_file is set, but client_certificate_file is not")
+            }
+        }
+    }
+}
+
+impl UpstreamTlsConfig {
+    /// Makes sure that all files can be read, so that errors show up at startup rather than with the first
+    /// download.
+    pub fn validate(&self) -> Result<(), InvalidUpstreamTls> {
+        if self.client_private_key_file.is_some() && self.client_certificate_file.is_none() {
+            return Err(InvalidUpstreamTls::PrivateKeyWithoutCertificate);
+        }
+        let files = [&self.ca_bundle_file, &self.client_certificate_file, &self.client_private_key_file];
+        for path in files.iter().filter_map(|f| f.as_ref()) {
+            File::open(path).map_err(|e| InvalidUpstreamTls::UnreadableFile(path.clone(), e))?;
+        }
+        Ok(())
+    }
+
+    /// Applies all settings that are set, and leaves all other settings unchanged. This allows the settings of
+    /// a custom repo to be applied on top of the global settings.
+    pub fn apply<C: CurlHandle>(&self, handle: &mut C) -> Result<(), curl::Error> {
+        if let Some(ca_bundle_file) = &self.ca_bundle_file {
+            handle.cainfo(ca_bundle_file)?;
+        }
+        if let Some(client_certificate_file) = &self.client_certificate_file {
+            handle.ssl_cert(client_certificate_file)?;
+        }
+        if let Some(client_private_key_file) = &self.client_private_key_file {
+            handle.ssl_key(client_private_key_file)?;
+        }
+        if let Some(insecure) = self.insecure_disable_verification {
+            handle.ssl_verify_peer(!insecure)?;
+            handle.ssl_verify_host(!insecure)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_upstream_tls_validate() {
+    let config = UpstreamTlsConfig {
+        ca_bundle_file: None,
+        client_certificate_file: None,
+        client_private_key_file: Some("/dev/null".to_owned()),
+        insecure_disable_verification: None,
+    };
+    assert!(matches!(config.validate(), Err(InvalidUpstreamTls::PrivateKeyWithoutCertificate)));
+    let config = UpstreamTlsConfig {
+        client_certificate_file: Some("/dev/null".to_owned()),
+        ca_bundle_file: Some("/nonexistent/ca.pem".to_owned()),
+        ..config
+    };
+    assert!(matches!(config.validate(), Err(InvalidUpstreamTls::UnreadableFile(_, _))));
+    let config = UpstreamTlsConfig {
+        ca_bundle_file: None,
+        ..config
+    };
+    assert!(config.validate().is_ok());
+}
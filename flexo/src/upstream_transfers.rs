@@ -0,0 +1,352 @@
+use std::cmp::Reverse;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::{select, Receiver, Sender, TryRecvError};
+use curl::easy::Easy2;
+use curl::MultiError;
+use curl::multi::{Easy2Handle, Message, Multi, MultiWaker};
+
+use crate::bandwidth::{UpstreamTransfer, MAX_PAUSE_CHECK_INTERVAL};
+#[cfg(test)]
+use crate::test_fixtures;
+
+/// The number of items of work that may be pending for a single transfer. If the submitting thread falls behind, for
+/// example because the disk is slow, the transfer is paused until the work has been done.
+const MAX_PENDING_WORK: usize = 64;
+
+/// How long a transfer is paused while too much of its work is pending.
+const PENDING_WORK_PAUSE: Duration = Duration::from_millis(10);
+
+type TransferResult<H> = (Easy2<H>, Result<(), curl::Error>);
+
+/// Why a transfer could not be performed. The handle of the transfer is lost in either case.
+#[derive(Debug)]
+pub enum UpstreamTransferError {
+    /// The multi handle has refused to add or to remove the transfer.
+    MultiError(MultiError),
+    /// The thread performing the upstream transfers has terminated.
+    Terminated,
+}
+
+impl std::fmt::Display for UpstreamTransferError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpstreamTransferError::MultiError(e) => write!(f, "curl multi error: {}", e),
+            UpstreamTransferError::Terminated => {
+                write!(f, "the thread performing the upstream transfers has terminated")
+            }
+        }
+    }
+}
+
+type TransferOutcome<H> = Result<TransferResult<H>, UpstreamTransferError>;
+
+/// Adds a transfer to the multi handle. If the transfer cannot be added, the submitting thread is informed and
+/// None is returned.
+type Submission = Box<dyn FnOnce(&Multi) -> Option<Box<dyn ActiveTransfer>> + Send>;
+
+/// A transfer that has been added to the multi handle. Downloads and segment downloads use different handlers, but
+/// share the same multi handle, so the thread driving the transfers only knows them by this trait.
+trait ActiveTransfer {
+    /// Returns the result of the transfer if the given message says that this transfer has completed.
+    fn result(&self, message: &Message) -> Option<Result<(), curl::Error>>;
+
+    fn resume_at(&mut self) -> &mut Option<Instant>;
+
+    fn unpause_write(&self) -> Result<(), curl::Error>;
+
+    /// Removes the completed transfer from the multi handle and passes it on to the submitting thread.
+    fn complete(self: Box<Self>, multi: &Multi, result: Result<(), curl::Error>);
+}
+
+struct Transfer<H> {
+    handle: Easy2Handle<H>,
+    tx_done: Sender<TransferOutcome<H>>,
+}
+
+impl<H: UpstreamTransfer> ActiveTransfer for Transfer<H> {
+    fn result(&self, message: &Message) -> Option<Result<(), curl::Error>> {
+        message.result_for2(&self.handle)
+    }
+
+    fn resume_at(&mut self) -> &mut Option<Instant> {
+        self.handle.get_mut().resume_at()
+    }
+
+    fn unpause_write(&self) -> Result<(), curl::Error> {
+        self.handle.unpause_write()
+    }
+
+    fn complete(self: Box<Self>, multi: &Multi, result: Result<(), curl::Error>) {
+        let Transfer { handle, tx_done } = *self;
+        let outcome = match multi.remove2(handle) {
+            Ok(handle) => Ok((handle, result)),
+            Err(e) => {
+                error!("Unable to remove the transfer from the multi handle: {:?}", e);
+                Err(UpstreamTransferError::MultiError(e))
+            }
+        };
+        let _ = tx_done.send(outcome);
+    }
+}
+
+struct Driver {
+    tx: Sender<Submission>,
+    waker: MultiWaker,
+}
+
+/// Creates the channel over which the callbacks of a transfer hand over their work, such as writing the payload to
+/// disk, to the thread that has submitted the transfer, see [`UpstreamTransfers::perform`].
+pub fn work_channel<W>() -> (WorkSender<W>, Receiver<W>) {
+    let (tx, rx) = crossbeam::channel::unbounded();
+    (WorkSender { tx }, rx)
+}
+
+/// Used by the callbacks of a transfer, which run on the thread that drives all upstream transfers and therefore must
+/// not block.
+#[derive(Debug)]
+pub struct WorkSender<W> {
+    tx: Sender<W>,
+}
+
+impl<W> WorkSender<W> {
+    /// Returns false if the submitting thread no longer accepts work because previous work has failed: The callback
+    /// should then abort the transfer.
+    pub fn send(&self, work: W) -> bool {
+        self.tx.send(work).is_ok()
+    }
+
+    /// If the submitting thread has fallen behind, the point in time is returned at which the transfer may continue:
+    /// The write callback then pauses the transfer, see [`UpstreamTransfer`].
+    pub fn backlog_resume_at(&self) -> Option<Instant> {
+        if self.tx.len() >= MAX_PENDING_WORK {
+            Some(Instant::now() + PENDING_WORK_PAUSE)
+        } else {
+            None
+        }
+    }
+}
+
+/// Performs the downloads from remote mirrors with a single multi handle, so that all downloads share curl's
+/// connection cache: Concurrent downloads from a remote mirror that supports HTTP/2 are multiplexed over a single
+/// connection instead of opening a new connection for each download. The multi handle is owned by a thread of its
+/// own, which is started with the first transfer.
+#[derive(Clone, Default)]
+pub struct UpstreamTransfers {
+    driver: Arc<Mutex<Option<Driver>>>,
+}
+
+impl std::fmt::Debug for UpstreamTransfers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let is_running = self.driver.lock().unwrap().is_some();
+        f.debug_struct("UpstreamTransfers").field("is_running", &is_running).finish()
+    }
+}
+
+impl UpstreamTransfers {
+    /// Performs the transfer, like [`Easy2::perform`], and blocks until it has completed. Transfers that have been
+    /// paused by their write callback are resumed, see [`UpstreamTransfer`]. Meanwhile, the work that the callbacks
+    /// hand over through the [`WorkSender`] is passed to `work` on the calling thread, so that the thread driving all
+    /// transfers never waits for the disk. Once `work` returns false, the remaining work is discarded.
+    pub fn perform<H, W, F>(&self, handle: Easy2<H>, rx_work: Receiver<W>, mut work: F) -> TransferOutcome<H>
+        where H: UpstreamTransfer + Send + 'static, F: FnMut(W) -> bool
+    {
+        let (tx, waker) = {
+            let mut driver = self.driver.lock().unwrap();
+            let driver = driver.get_or_insert_with(start_driver);
+            (driver.tx.clone(), driver.waker.clone())
+        };
+        let (tx_done, rx_done) = crossbeam::channel::bounded(1);
+        let submission: Submission = Box::new(move |multi: &Multi| match multi.add2(handle) {
+            Ok(handle) => Some(Box::new(Transfer { handle, tx_done }) as Box<dyn ActiveTransfer>),
+            Err(e) => {
+                error!("Unable to add the transfer to the multi handle: {:?}", e);
+                let _ = tx_done.send(Err(UpstreamTransferError::MultiError(e)));
+                None
+            }
+        });
+        if tx.send(submission).is_err() {
+            error!("The thread performing the upstream transfers has terminated.");
+            return Err(UpstreamTransferError::Terminated);
+        }
+        if let Err(e) = waker.wakeup() {
+            warn!("Unable to wake up the thread performing the upstream transfers: {:?}", e);
+        }
+        let outcome = loop {
+            let accepted = select! {
+                recv(rx_work) -> w => match w {
+                    Ok(w) => work(w),
+                    // The transfer has been dropped.
+                    Err(_) => false,
+                },
+                recv(rx_done) -> outcome => {
+                    // The callbacks have handed over all their work before the transfer has completed.
+                    for w in rx_work.try_iter() {
+                        if !work(w) {
+                            break;
+                        }
+                    }
+                    break outcome;
+                }
+            };
+            if !accepted {
+                // The callbacks notice that the receiver is gone, see WorkSender::send.
+                drop(rx_work);
+                break rx_done.recv();
+            }
+        };
+        outcome.unwrap_or(Err(UpstreamTransferError::Terminated))
+    }
+}
+
+fn start_driver() -> Driver {
+    let (tx, rx) = crossbeam::channel::unbounded();
+    let (tx_waker, rx_waker) = crossbeam::channel::bounded(1);
+    thread::Builder::new().name("upstream-transfers".to_owned()).spawn(move || {
+        // The multi handle cannot be sent to another thread, so it is created by the thread that owns it.
+        let mut multi = Multi::new();
+        if let Err(e) = multi.pipelining(false, true) {
+            warn!("Unable to enable multiplexing: {:?}", e);
+        }
+        tx_waker.send(multi.waker()).unwrap();
+        drive(multi, rx);
+    }).expect("Unable to spawn the thread performing the upstream transfers");
+    let waker = rx_waker.recv().unwrap();
+    Driver { tx, waker }
+}
+
+fn drive(multi: Multi, rx: Receiver<Submission>) {
+    let mut transfers: Vec<Box<dyn ActiveTransfer>> = Vec::new();
+    loop {
+        let mut submissions = Vec::new();
+        if transfers.is_empty() {
+            // Nothing to do until the next transfer is submitted.
+            match rx.recv() {
+                Ok(submission) => submissions.push(submission),
+                Err(_) => return,
+            }
+        }
+        loop {
+            match rx.try_recv() {
+                Ok(submission) => submissions.push(submission),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) if transfers.is_empty() && submissions.is_empty() => return,
+                Err(TryRecvError::Disconnected) => break,
+            }
+        }
+        transfers.extend(submissions.into_iter().filter_map(|submission| submission(&multi)));
+        if let Err(e) = multi.perform() {
+            error!("Unable to perform the upstream transfers: {:?}", e);
+        }
+        let mut completed = Vec::new();
+        multi.messages(|message| {
+            for (index, transfer) in transfers.iter().enumerate() {
+                if let Some(result) = transfer.result(&message) {
+                    completed.push((index, result));
+                }
+            }
+        });
+        // Remove from the back, so that swap_remove does not move any of the transfers that are yet to be removed.
+        completed.sort_by_key(|(index, _)| Reverse(*index));
+        for (index, result) in completed {
+            transfers.swap_remove(index).complete(&multi, result);
+        }
+        let now = Instant::now();
+        let mut timeout = MAX_PAUSE_CHECK_INTERVAL;
+        for transfer in transfers.iter_mut() {
+            let resume_at = transfer.resume_at();
+            match *resume_at {
+                Some(r) if now >= r => {
+                    *resume_at = None;
+                    if let Err(e) = transfer.unpause_write() {
+                        warn!("Unable to resume the transfer: {:?}", e);
+                    }
+                    timeout = Duration::ZERO;
+                }
+                Some(r) => timeout = timeout.min(r - now),
+                None => {}
+            }
+        }
+        if !transfers.is_empty() {
+            // curl returns earlier if data arrives, if one of its own timeouts expires, or if a transfer is submitted.
+            if let Err(e) = multi.poll(&mut [], timeout) {
+                error!("Unable to wait for the upstream transfers: {:?}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+struct TestTransfer {
+    tx_work: WorkSender<Vec<u8>>,
+    resume_at: Option<Instant>,
+    pause: bool,
+}
+
+#[cfg(test)]
+impl curl::easy::Handler for TestTransfer {
+    fn write(&mut self, data: &[u8]) -> Result<usize, curl::easy::WriteError> {
+        if self.pause {
+            self.pause = false;
+            self.resume_at = Some(Instant::now() + Duration::from_millis(50));
+            return Err(curl::easy::WriteError::Pause);
+        }
+        if !self.tx_work.send(data.to_vec()) {
+            return Ok(0);
+        }
+        Ok(data.len())
+    }
+}
+
+#[cfg(test)]
+impl UpstreamTransfer for TestTransfer {
+    fn resume_at(&mut self) -> &mut Option<Instant> {
+        &mut self.resume_at
+    }
+}
+
+#[test]
+fn test_concurrent_transfers() {
+    let content = test_fixtures::file_content(200_000);
+    let uri = test_fixtures::serve_content(content.clone(), "/core/os/x86_64/core.db");
+    let upstream_transfers = UpstreamTransfers::default();
+    let threads = (0..4).map(|i| {
+        let upstream_transfers = upstream_transfers.clone();
+        let uri = uri.clone();
+        thread::spawn(move || {
+            let (tx_work, rx_work) = work_channel();
+            let mut handle = Easy2::new(TestTransfer { tx_work, resume_at: None, pause: i == 0 });
+            handle.url(&uri).unwrap();
+            let mut received = Vec::new();
+            let (handle, result) = upstream_transfers.perform(handle, rx_work, |data: Vec<u8>| {
+                received.extend_from_slice(&data);
+                true
+            }).unwrap();
+            result.unwrap();
+            assert_eq!(200, handle.response_code().unwrap());
+            received
+        })
+    }).collect::<Vec<_>>();
+    for t in threads {
+        assert_eq!(content, t.join().unwrap());
+    }
+}
+
+#[test]
+fn test_failed_work_aborts_transfer() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("core.db");
+    std::fs::write(&path, vec![0; 1_000_000]).unwrap();
+    let (tx_work, rx_work) = work_channel();
+    let mut handle = Easy2::new(TestTransfer { tx_work, resume_at: None, pause: false });
+    handle.url(&format!("file://{}", path.to_str().unwrap())).unwrap();
+    let mut num_chunks = 0;
+    let (_, result) = UpstreamTransfers::default().perform(handle, rx_work, |_: Vec<u8>| {
+        num_chunks += 1;
+        false
+    }).unwrap();
+    assert!(result.unwrap_err().is_write_error());
+    assert_eq!(1, num_chunks);
+}
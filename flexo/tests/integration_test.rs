@@ -2,8 +2,11 @@ extern crate http;
 extern crate rand;
 
 use flexo::*;
+use flexo::progress::ProgressSender;
+use flexo::thread_pool::{ThreadPool, ThreadPoolConfig};
 use std::collections::HashMap;
-use crossbeam::channel::{Sender, Receiver};
+use std::time::Duration;
+use crossbeam::channel::Receiver;
 
 static EXPECT_SCHEDULED: &str = "Expected the job to be scheduled";
 static EXPECT_SKIPPED: &str = "Expected the job to be skipped";
@@ -22,6 +25,8 @@ enum DummyProvider {
     Success(DummyProviderItem),
     PartialCompletion(DummyProviderItem),
     Failure(DummyProviderItem),
+    /// A provider which does not have any of the requested orders.
+    Unavailable(DummyProviderItem),
 }
 
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
@@ -47,6 +52,7 @@ impl Provider for DummyProvider {
             DummyProvider::Success(p) => p.score,
             DummyProvider::Failure(p) => p.score,
             DummyProvider::PartialCompletion(p) => p.score,
+            DummyProvider::Unavailable(p) => p.score,
         }
     }
 
@@ -55,6 +61,7 @@ impl Provider for DummyProvider {
             DummyProvider::Success(DummyProviderItem { identifier, .. } ) => identifier,
             DummyProvider::PartialCompletion(DummyProviderItem { identifier, .. } ) => identifier,
             DummyProvider::Failure(DummyProviderItem { identifier, .. } ) => identifier,
+            DummyProvider::Unavailable(DummyProviderItem { identifier, .. } ) => identifier,
         };
         let identifier = format!("DummyProvider {}", i);
         ProviderIdentifier {
@@ -74,9 +81,17 @@ struct DummyJob {
     properties: DummyProperties,
 }
 
-#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
-struct DummyProperties {}
-impl Properties for DummyProperties {}
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, Default)]
+struct DummyProperties {
+    /// None to use the default budget.
+    failover_budget: Option<FailoverBudget>,
+}
+
+impl Properties for DummyProperties {
+    fn failover_budget(&self) -> FailoverBudget {
+        self.failover_budget.unwrap_or(DEFAULT_FAILOVER_BUDGET)
+    }
+}
 
 #[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
 struct DummyOrderError {}
@@ -150,11 +165,12 @@ impl Job for DummyJob {
                 JobResult::Partial(JobPartiallyCompleted { channel, continue_at: 1 })
             },
             (DummyOrder { variant: DummyOrderVariant::InfiniteBlocking, .. }, DummyProvider::Success(_)) => {
-                let _result = channel.collector.tx.send(FlexoProgress::Progress(0));
+                channel.collector.tx.send(FlexoProgress::Progress(0));
                 std::thread::park(); // block forever.
                 JobResult::Complete(JobCompleted::new(channel, self.provider, 1))
             }
             (DummyOrder { variant: DummyOrderVariant::Panic, ..}, _) => panic!("{}", ORDER_PANIC),
+            (_, DummyProvider::Unavailable(_)) => JobResult::Unavailable(channel),
             _ => JobResult::Error(JobTerminated { channel, error: DummyJobError {} }),
         }
     }
@@ -222,7 +238,7 @@ impl DummyOrder {
 impl Order for DummyOrder {
     type J = DummyJob;
 
-    fn new_channel(self, _properties: <<Self as Order>::J as Job>::PR, tx: Sender<FlexoProgress>, _last_chance: bool) -> Result<DummyChannel, DummyOrderError> {
+    fn new_channel(self, _properties: <<Self as Order>::J as Job>::PR, tx: ProgressSender, _last_chance: bool) -> Result<DummyChannel, DummyOrderError> {
         Ok(DummyChannel {
             handle: 1,
             collector: JobState {
@@ -234,7 +250,7 @@ impl Order for DummyOrder {
         })
     }
 
-    fn reuse_channel(self, properties: <<Self as Order>::J as Job>::PR, tx: Sender<FlexoProgress>, last_chance: bool, _channel: DummyChannel) -> Result<DummyChannel, DummyOrderError> {
+    fn reuse_channel(self, properties: <<Self as Order>::J as Job>::PR, tx: ProgressSender, last_chance: bool, _channel: DummyChannel) -> Result<DummyChannel, DummyOrderError> {
         self.new_channel(properties, tx, last_chance)
     }
 
@@ -296,6 +312,19 @@ fn wait_until_message_received <F, R>(
     }
 }
 
+fn providers_selected(schedule_outcome: ScheduleOutcome<DummyJob>) -> Vec<ProviderIdentifier> {
+    match schedule_outcome {
+        ScheduleOutcome::Scheduled(ScheduledItem { join_handle, rx_integration_test, .. }) => {
+            join_handle.join().unwrap();
+            rx_integration_test.try_iter().filter_map(|msg| match msg {
+                IntegrationTestMessage::ProviderSelected(p) => Some(p),
+                _ => None,
+            }).collect()
+        },
+        _ => panic!("{}", EXPECT_SCHEDULED),
+    }
+}
+
 fn wait_until_provider_selected(schedule_outcome: ScheduleOutcome<DummyJob>) -> ProviderIdentifier {
     match schedule_outcome {
         ScheduleOutcome::Scheduled(ScheduledItem { join_handle: _, rx_integration_test: rx, rx_progress: _ }) => {
@@ -360,7 +389,7 @@ fn provider_lowest_score() {
     let p1 = DummyProvider::Success(DummyProviderItem { identifier: 1, score: 0 });
     let p2 = DummyProvider::Success(DummyProviderItem { identifier: 2, score: 1 });
     let providers = vec![p1, p2];
-    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties{});
+    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties::default());
     let result = match job_context.try_schedule(DummyOrder::success(0), None, None) {
         ScheduleOutcome::Scheduled(ScheduledItem { join_handle, ..}) => {
             // wait for the job to complete.
@@ -376,7 +405,7 @@ fn second_provider_success_after_first_provider_failure() {
     let p1 = DummyProvider::Failure(DummyProviderItem { identifier: 1, score: 0 });
     let p2 = DummyProvider::Success(DummyProviderItem { identifier: 2, score: 1 });
     let providers = vec![p1, p2];
-    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties{});
+    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties::default());
     match job_context.try_schedule(DummyOrder::success(0), None, None) {
         ScheduleOutcome::Scheduled(ScheduledItem { join_handle, ..}) => {
             // wait for the job to complete.
@@ -395,7 +424,7 @@ fn next_order_success_after_first_order_failed() {
     // that a failing job does not cause all available providers to be "blacklisted", i.e., when some mechanism
     // is used to downgrade a provider after it has failed to complete a job, a subsequent job should still
     // succeed with this provider, even though it has been downgraded.
-    let mut job_context: JobContext<DummyJob> = JobContext::new(successful_providers(), DummyProperties{});
+    let mut job_context: JobContext<DummyJob> = JobContext::new(successful_providers(), DummyProperties::default());
     job_context.try_schedule(DummyOrder::failure(0), None, None);
     match job_context.try_schedule(DummyOrder::success(1), None, None) {
         ScheduleOutcome::Scheduled(ScheduledItem { join_handle, ..}) => {
@@ -418,7 +447,7 @@ fn provider_no_two_simultaneous_jobs() {
     let p1 = DummyProvider::Success(DummyProviderItem { identifier: 1, score: 0 });
     let p2 = DummyProvider::Success(DummyProviderItem { identifier: 2, score: 1 });
     let providers = vec![p1, p2];
-    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties{});
+    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties::default());
     let provider_order1 = match job_context.try_schedule(DummyOrder::infinite_blocking(0), None, None) {
         ScheduleOutcome::Scheduled(ScheduledItem { rx_integration_test, ..}) => {
             rx_integration_test.recv().unwrap()
@@ -443,7 +472,7 @@ fn provider_two_simultaneous_jobs_if_uncacheable() {
     let p1 = DummyProvider::Success(DummyProviderItem { identifier: 1, score: 0 });
     let p2 = DummyProvider::Success(DummyProviderItem { identifier: 2, score: 1 });
     let providers = vec![p1, p2];
-    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties{});
+    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties::default());
     let order1 = DummyOrder {
         variant: DummyOrderVariant::InfiniteBlocking,
         identifier: 0,
@@ -475,7 +504,7 @@ fn provider_two_simultaneous_jobs_if_required() {
     // necessary if the number of providers is low and the frequency of newly arriving orders is high.
     let p1 = DummyProvider::Success(DummyProviderItem { identifier: 1, score: 0 });
     let providers = vec![p1];
-    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties{});
+    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties::default());
     let provider_order1 = match job_context.try_schedule(DummyOrder::infinite_blocking(0), None, None) {
         ScheduleOutcome::Scheduled(ScheduledItem { rx_integration_test, ..}) => {
             rx_integration_test.recv().unwrap()
@@ -503,7 +532,7 @@ fn provider_reused_after_job_completed() {
     let p1 = DummyProvider::Success(DummyProviderItem { identifier: 1, score: 0 });
     let p2 = DummyProvider::Success(DummyProviderItem { identifier: 2, score: 1 });
     let providers = vec![p1, p2];
-    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties{});
+    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties::default());
     let (provider_order1, join_handle_1) = match job_context.try_schedule(DummyOrder::success(1), None, None) {
         ScheduleOutcome::Scheduled(ScheduledItem { rx_integration_test, join_handle, ..}) => {
             (rx_integration_test.recv().unwrap(), join_handle)
@@ -522,6 +551,24 @@ fn provider_reused_after_job_completed() {
     assert_eq!(provider_order2, expected);
 }
 
+#[test]
+fn order_rejected_if_thread_pool_overloaded() {
+    let p1 = DummyProvider::Success(DummyProviderItem { identifier: 1, score: 0 });
+    let providers = vec![p1];
+    let thread_pool_config = ThreadPoolConfig {
+        max_threads: 1,
+        max_queue_size: 0,
+        queue_timeout: std::time::Duration::from_secs(60),
+    };
+    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties::default())
+        .with_thread_pool(ThreadPool::new("test", thread_pool_config));
+    wait_until_provider_selected(job_context.try_schedule(DummyOrder::infinite_blocking(0), None, None));
+    match job_context.try_schedule(DummyOrder::infinite_blocking(1), None, None) {
+        ScheduleOutcome::Overloaded => {}
+        _ => panic!("Expected the order to be rejected since the only thread is busy."),
+    }
+}
+
 #[test]
 fn order_skipped_if_already_in_progress() {
     // If an order is already in progress, scheduling the same order again will not cause a new job to be
@@ -530,7 +577,7 @@ fn order_skipped_if_already_in_progress() {
     let p1 = DummyProvider::Success(DummyProviderItem { identifier: 1, score: 0 });
     let order = DummyOrder::infinite_blocking(0);
     let providers = vec![p1];
-    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties{});
+    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties::default());
     wait_until_provider_selected(job_context.try_schedule(order, None, None));
 
     match job_context.try_schedule(order, None, None) {
@@ -542,6 +589,8 @@ fn order_skipped_if_already_in_progress() {
             panic!("{}", EXPECT_SKIPPED),
         ScheduleOutcome::Uncacheable(_) =>
             panic!("{}", EXPECT_SKIPPED),
+        ScheduleOutcome::Overloaded =>
+            panic!("{}", EXPECT_SKIPPED),
     }
 }
 
@@ -553,7 +602,7 @@ fn best_provider_selected() {
     let p2 = DummyProvider::Success(DummyProviderItem { identifier: 2, score: -1 });
     let p3 = DummyProvider::Success(DummyProviderItem { identifier: 3, score: 2 });
     let providers = vec![p1, p2, p3];
-    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties{});
+    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties::default());
     let result = job_context.try_schedule(DummyOrder::success(0), None, None);
 
     let DummyJobSuccess { provider } = wait_until_job_completed(result);
@@ -567,7 +616,7 @@ fn job_continued_after_partial_completion() {
     let p2 = DummyProvider::Success(DummyProviderItem { identifier: 2, score: 2 });
     let p3 = DummyProvider::Success(DummyProviderItem { identifier: 3, score: 3 });
     let providers = vec![p1, p2, p3];
-    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties{});
+    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties::default());
     let (provider_first_scheduled, provider_finally_scheduled) =
         match job_context.try_schedule(DummyOrder::success(0), None, None) {
             ScheduleOutcome::Scheduled(ScheduledItem { join_handle, rx_integration_test, ..}) => {
@@ -587,12 +636,55 @@ fn job_continued_after_partial_completion() {
     assert_eq!(provider_finally_scheduled, p2);
 }
 
+#[test]
+fn no_further_providers_after_failover_budget_used_up() {
+    // Once the maximum number of attempts or the retry timeout is used up, the remaining providers are skipped.
+    let providers = (1..=4)
+        .map(|identifier| DummyProvider::Failure(DummyProviderItem { identifier, score: identifier }))
+        .collect::<Vec<_>>();
+    let budget = FailoverBudget { max_attempts: 2, ..DEFAULT_FAILOVER_BUDGET };
+    let properties = DummyProperties { failover_budget: Some(budget) };
+    let mut job_context: JobContext<DummyJob> = JobContext::new(providers.clone(), properties);
+    let selected = providers_selected(job_context.try_schedule(DummyOrder::success(0), None, None));
+    assert_eq!(selected, vec![providers[0].identifier(), providers[1].identifier()]);
+
+    // The attempt that starts after the retry timeout has elapsed is the last one.
+    let budget = FailoverBudget { retry_timeout: Duration::from_secs(0), ..DEFAULT_FAILOVER_BUDGET };
+    let properties = DummyProperties { failover_budget: Some(budget) };
+    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, properties);
+    let selected = providers_selected(job_context.try_schedule(DummyOrder::success(0), None, None));
+    assert_eq!(2, selected.len());
+}
+
+#[test]
+fn failed_provider_used_again_after_cooldown() {
+    // A provider that has failed is avoided until its cooldown has passed. Uncacheable orders are used so that the
+    // number of failures does not affect the choice.
+    let p1 = DummyProvider::Failure(DummyProviderItem { identifier: 1, score: 1 });
+    let p2 = DummyProvider::Success(DummyProviderItem { identifier: 2, score: 2 });
+    let budget = FailoverBudget { provider_cooldown: Duration::from_millis(200), ..DEFAULT_FAILOVER_BUDGET };
+    let properties = DummyProperties { failover_budget: Some(budget) };
+    let mut job_context: JobContext<DummyJob> = JobContext::new(vec![p1, p2], properties);
+    let uncacheable_order = |identifier| DummyOrder {
+        variant: DummyOrderVariant::Success,
+        identifier,
+        is_cacheable: false,
+    };
+    let selected = providers_selected(job_context.try_schedule(uncacheable_order(0), None, None));
+    assert_eq!(selected, vec![p1.identifier(), p2.identifier()]);
+    let selected = providers_selected(job_context.try_schedule(uncacheable_order(1), None, None));
+    assert_eq!(selected, vec![p2.identifier()]);
+    std::thread::sleep(Duration::from_millis(250));
+    let selected = providers_selected(job_context.try_schedule(uncacheable_order(2), None, None));
+    assert_eq!(selected[0], p1.identifier());
+}
+
 #[test]
 fn no_infinite_loop() {
     // if all providers fail to fulfil the order, no infinite loop results.
     let p1 = DummyProvider::Failure(DummyProviderItem { identifier: 1, score: 1 });
     let providers = vec![p1];
-    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties{});
+    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties::default());
     let result = match job_context.try_schedule(DummyOrder::success(0), None, None) {
         ScheduleOutcome::Scheduled(ScheduledItem {join_handle, ..}) => {
             join_handle.join().unwrap()
@@ -615,7 +707,7 @@ fn downgrade_provider() {
     let p1 = DummyProvider::Failure(DummyProviderItem { identifier: 1, score: 1 });
     let p2 = DummyProvider::Success(DummyProviderItem { identifier: 2, score: 2 });
     let providers = vec![p1, p2];
-    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties{});
+    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties::default());
     let result1 = job_context.try_schedule(DummyOrder::success(0), None, None);
     wait_until_job_completed(result1);
     let result2 = job_context.try_schedule(DummyOrder::success(1), None, None);
@@ -632,7 +724,7 @@ fn no_downgrade_if_all_providers_fail() {
     // the client or the order.
     let p1 = DummyProvider::Failure(DummyProviderItem { identifier: 1, score: 1 });
     let providers = vec![p1];
-    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties{});
+    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties::default());
     let result1 = job_context.try_schedule(DummyOrder::success(0), None, None);
     let DummyJobFailure { metrics } = wait_until_job_failed(result1);
     let metrics = metrics.get(&p1.identifier());
@@ -642,13 +734,41 @@ fn no_downgrade_if_all_providers_fail() {
     }
 }
 
+#[test]
+fn fallback_provider_used_if_unavailable_at_all_providers() {
+    // The fallback provider is a last resort for orders that none of the regular providers has available.
+    let p1 = DummyProvider::Unavailable(DummyProviderItem { identifier: 1, score: 1 });
+    let p2 = DummyProvider::Unavailable(DummyProviderItem { identifier: 2, score: 2 });
+    let fallback = DummyProvider::Success(DummyProviderItem { identifier: 3, score: 3 });
+    let providers = vec![p1, p2];
+    let mut job_context: JobContext<DummyJob> =
+        JobContext::new(providers, DummyProperties::default()).with_fallback_provider(fallback);
+    let result = job_context.try_schedule(DummyOrder::success(0), None, None);
+    let DummyJobSuccess { provider } = wait_until_job_completed(result);
+    assert_eq!(provider, fallback);
+}
+
+#[test]
+fn fallback_provider_not_used_after_failure() {
+    // If a regular provider has failed (as opposed to not having the order available), the fallback provider is
+    // not used: it is not meant to replace unreliable providers.
+    let p1 = DummyProvider::Unavailable(DummyProviderItem { identifier: 1, score: 1 });
+    let p2 = DummyProvider::Failure(DummyProviderItem { identifier: 2, score: 2 });
+    let fallback = DummyProvider::Success(DummyProviderItem { identifier: 3, score: 3 });
+    let providers = vec![p1, p2];
+    let mut job_context: JobContext<DummyJob> =
+        JobContext::new(providers, DummyProperties::default()).with_fallback_provider(fallback);
+    let result = job_context.try_schedule(DummyOrder::success(0), None, None);
+    wait_until_job_failed(result);
+}
+
 #[test]
 fn no_new_channel_established() {
     // channels can be reused: If a job has completed, the channel used for this job will be retained such that
     // it can be reused by a subsequent job.
     let p1 = DummyProvider::Success(DummyProviderItem { identifier: 1, score: 1 });
     let providers = vec![p1];
-    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties{});
+    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties::default());
     let result1 = job_context.try_schedule(DummyOrder::success(0), None, None);
     wait_until_job_completed(result1);
     let channel_establishment = match job_context.try_schedule(DummyOrder::success(1), None, None) {
@@ -671,7 +791,7 @@ fn new_channel_established_because_channel_in_use() {
     // we cannot reuse the existing channel, therefore, a new channel must be established.
     let p1 = DummyProvider::Success(DummyProviderItem { identifier: 1, score: 1 });
     let providers = vec![p1];
-    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties{});
+    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties::default());
     let result1 = job_context.try_schedule(DummyOrder::infinite_blocking(0), None, None);
     wait_until_channel_established(result1);
     let channel_establishment = match job_context.try_schedule(DummyOrder::success(1), None, None) {
@@ -702,7 +822,7 @@ fn job_panic_results_in_main_panic() {
     let order1 = DummyOrder::panic(0);
     let order2 = DummyOrder::success(1);
     let providers = vec![p1];
-    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties{});
+    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties::default());
     let result1 = job_context.try_schedule(order1, None, None);
     wait_until_job_failed(result1);
     job_context.try_schedule(order2, None, None);
@@ -714,7 +834,7 @@ fn read_progress() {
     // has finished.
     let p1 = DummyProvider::Success(DummyProviderItem { identifier: 1, score: 0 });
     let providers = vec![p1];
-    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties{});
+    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties::default());
     let result = match job_context.try_schedule(DummyOrder::infinite_blocking(0), None, None) {
         ScheduleOutcome::Scheduled(ScheduledItem { rx_progress, .. }) => {
             rx_progress.recv_timeout(std::time::Duration::from_millis(50)).unwrap()
@@ -723,3 +843,47 @@ fn read_progress() {
     };
     assert_eq!(result, FlexoProgress::Progress(0));
 }
+
+#[test]
+fn failure_reported_via_progress() {
+    // Clients that are served while the job is running need to know when the job fails, so that they do not wait
+    // indefinitely for progress.
+    let p1 = DummyProvider::Failure(DummyProviderItem { identifier: 1, score: 0 });
+    let providers = vec![p1];
+    let mut job_context: JobContext<DummyJob> = JobContext::new(providers, DummyProperties::default());
+    let rx_progress = match job_context.try_schedule(DummyOrder::success(0), None, None) {
+        ScheduleOutcome::Scheduled(ScheduledItem { rx_progress, .. }) => rx_progress,
+        _ => panic!("{}", EXPECT_SCHEDULED),
+    };
+    let result = loop {
+        match rx_progress.recv_timeout(std::time::Duration::from_secs(1)).unwrap() {
+            FlexoProgress::Progress(_) => {},
+            progress => break progress,
+        }
+    };
+    assert_eq!(result, FlexoProgress::Failed);
+}
+
+#[test]
+fn single_terminal_event_after_failure() {
+    // Consumers must not receive contradicting events, e.g. both Unavailable and Failed.
+    let p1 = DummyProvider::Unavailable(DummyProviderItem { identifier: 1, score: 1 });
+    let p2 = DummyProvider::Failure(DummyProviderItem { identifier: 2, score: 2 });
+    let fallback = DummyProvider::Success(DummyProviderItem { identifier: 3, score: 3 });
+    let providers = vec![p1, p2];
+    let mut job_context: JobContext<DummyJob> =
+        JobContext::new(providers, DummyProperties::default()).with_fallback_provider(fallback);
+    let rx_progress = match job_context.try_schedule(DummyOrder::success(0), None, None) {
+        ScheduleOutcome::Scheduled(ScheduledItem { rx_progress, .. }) => rx_progress,
+        _ => panic!("{}", EXPECT_SCHEDULED),
+    };
+    let mut events = Vec::new();
+    loop {
+        match rx_progress.recv_timeout(std::time::Duration::from_secs(1)) {
+            Ok(FlexoProgress::Progress(_)) => {},
+            Ok(event) => events.push(event),
+            Err(_) => break,
+        }
+    }
+    assert_eq!(events, vec![FlexoProgress::Failed]);
+}